@@ -16,10 +16,10 @@
 
 use actix_web::{test, web, App};
 use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
-use lipsum::lipsum_words;
 use mock_openai::endpoints::*;
 use mock_openai::types::*;
 use mock_openai::utils::*;
+use rand::SeedableRng;
 use std::sync::atomic::AtomicUsize;
 use std::sync::Arc;
 
@@ -90,29 +90,8 @@ impl BenchConfig {
     }
 }
 
-/// Generate mock articles using lipsum
-fn generate_articles(count: usize, token_mean: f64, token_stddev: f64) -> Vec<Arc<String>> {
-    use rand::SeedableRng;
-
-    let mut rng = rand::rngs::StdRng::from_entropy();
-    let mut articles = Vec::with_capacity(count);
-
-    for _ in 0..count {
-        let sampled = sample_normal_f64(&mut rng, token_mean, token_stddev).round() as isize;
-        let tokens = sampled.max(1) as usize;
-        let chars = tokens_to_chars(tokens);
-        let words = std::cmp::max(1, (chars as f64 / 6.0).round() as usize);
-        let article_str = lipsum_words(words);
-        articles.push(Arc::new(article_str));
-    }
-
-    articles
-}
-
 /// Generate pre-computed token samples for streaming
 fn generate_stream_samples(count: usize, token_mean: f64, token_stddev: f64) -> Vec<usize> {
-    use rand::SeedableRng;
-
     let mut rng = rand::rngs::StdRng::from_entropy();
     let mut samples = Vec::with_capacity(count);
 
@@ -127,17 +106,29 @@ fn generate_stream_samples(count: usize, token_mean: f64, token_stddev: f64) ->
 /// Create app state with given configuration.
 /// This is created once per benchmark group and cloned for threads.
 fn create_app_state(config: BenchConfig) -> Arc<AppState> {
-    let articles = generate_articles(config.pregen_count, config.token_mean, config.token_stddev);
+    Arc::new(build_app_state(config))
+}
+
+/// Build the (unshared) app state for a configuration
+fn build_app_state(config: BenchConfig) -> AppState {
+    let mut rng = rand::rngs::StdRng::from_entropy();
+    let articles = pregenerate_articles(
+        config.pregen_count,
+        config.token_mean,
+        config.token_stddev,
+        &mut rng,
+    );
     let stream_samples = generate_stream_samples(20_000, config.token_mean, config.token_stddev);
 
-    Arc::new(AppState {
+    AppState {
         articles,
         stream_token_samples: Arc::new(stream_samples),
         stream_samples_idx: AtomicUsize::new(0),
         token_mean: config.token_mean,
         token_stddev: config.token_stddev,
         response_delay_ms: config.response_delay_ms,
-    })
+        ..Default::default()
+    }
 }
 
 // ============================================================================
@@ -163,7 +154,6 @@ fn bench_health(c: &mut Criterion) {
     group.bench_function("health_check", |b| {
         let app_service = &app_service;
         b.to_async(&rt).iter(|| {
-            let app_service = app_service;
             async move {
                 let req = test::TestRequest::get().uri("/health").to_request();
                 let resp = test::call_service(&app_service, req).await;
@@ -252,21 +242,18 @@ fn bench_embeddings(c: &mut Criterion) {
 
     group.bench_function("embeddings_single", |b| {
         let app_service = &app_service_single;
-        b.to_async(&rt).iter(|| {
-            let app_service = app_service;
-            async move {
-                let payload = serde_json::json!({
-                    "model": "text-embedding-3-small",
-                    "input": "test input"
-                });
+        b.to_async(&rt).iter(|| async move {
+            let payload = serde_json::json!({
+                "model": "text-embedding-3-small",
+                "input": "test input"
+            });
 
-                let req = test::TestRequest::post()
-                    .uri("/v1/embeddings")
-                    .set_json(payload)
-                    .to_request();
+            let req = test::TestRequest::post()
+                .uri("/v1/embeddings")
+                .set_json(payload)
+                .to_request();
 
-                black_box(test::call_service(app_service, req).await)
-            }
+            black_box(test::call_service(app_service, req).await)
         });
     });
 
@@ -281,21 +268,18 @@ fn bench_embeddings(c: &mut Criterion) {
 
     group.bench_function("embeddings_batch", |b| {
         let app_service = &app_service_batch;
-        b.to_async(&rt).iter(|| {
-            let app_service = app_service;
-            async move {
-                let payload = serde_json::json!({
-                    "model": "text-embedding-3-small",
-                    "input": vec!["test 1", "test 2", "test 3"]
-                });
+        b.to_async(&rt).iter(|| async move {
+            let payload = serde_json::json!({
+                "model": "text-embedding-3-small",
+                "input": vec!["test 1", "test 2", "test 3"]
+            });
 
-                let req = test::TestRequest::post()
-                    .uri("/v1/embeddings")
-                    .set_json(payload)
-                    .to_request();
+            let req = test::TestRequest::post()
+                .uri("/v1/embeddings")
+                .set_json(payload)
+                .to_request();
 
-                black_box(test::call_service(app_service, req).await)
-            }
+            black_box(test::call_service(app_service, req).await)
         });
     });
 
@@ -324,22 +308,19 @@ fn bench_completions(c: &mut Criterion) {
 
     group.bench_function("completions_small", |b| {
         let app_service = &app_service;
-        b.to_async(&rt).iter(|| {
-            let app_service = app_service;
-            async move {
-                let payload = serde_json::json!({
-                    "model": "gpt-4-mock",
-                    "prompt": "Once upon a time",
-                    "max_tokens": 100
-                });
+        b.to_async(&rt).iter(|| async move {
+            let payload = serde_json::json!({
+                "model": "gpt-4-mock",
+                "prompt": "Once upon a time",
+                "max_tokens": 100
+            });
 
-                let req = test::TestRequest::post()
-                    .uri("/v1/completions")
-                    .set_json(payload)
-                    .to_request();
+            let req = test::TestRequest::post()
+                .uri("/v1/completions")
+                .set_json(payload)
+                .to_request();
 
-                black_box(test::call_service(app_service, req).await)
-            }
+            black_box(test::call_service(app_service, req).await)
         });
     });
 
@@ -371,24 +352,21 @@ fn bench_chat_completions_non_streaming(c: &mut Criterion) {
 
     group.bench_function("chat_non_streaming", |b| {
         let app_service = &app_service;
-        b.to_async(&rt).iter(|| {
-            let app_service = app_service;
-            async move {
-                let payload = serde_json::json!({
-                    "model": "gpt-4-mock",
-                    "messages": [
-                        {"role": "user", "content": "Hello!"}
-                    ],
-                    "stream": false
-                });
+        b.to_async(&rt).iter(|| async move {
+            let payload = serde_json::json!({
+                "model": "gpt-4-mock",
+                "messages": [
+                    {"role": "user", "content": "Hello!"}
+                ],
+                "stream": false
+            });
 
-                let req = test::TestRequest::post()
-                    .uri("/v1/chat/completions")
-                    .set_json(payload)
-                    .to_request();
+            let req = test::TestRequest::post()
+                .uri("/v1/chat/completions")
+                .set_json(payload)
+                .to_request();
 
-                black_box(test::call_service(app_service, req).await)
-            }
+            black_box(test::call_service(app_service, req).await)
         });
     });
 
@@ -416,15 +394,63 @@ fn bench_chat_completions_streaming(c: &mut Criterion) {
 
     group.bench_function("chat_streaming", |b| {
         let app_service = &app_service;
-        b.to_async(&rt).iter(|| {
-            let app_service = app_service;
-            async move {
+        b.to_async(&rt).iter(|| async move {
+            let payload = serde_json::json!({
+                "model": "gpt-4-mock",
+                "messages": [
+                    {"role": "user", "content": "Hello!"}
+                ],
+                "stream": true
+            });
+
+            let req = test::TestRequest::post()
+                .uri("/v1/chat/completions")
+                .set_json(payload)
+                .to_request();
+
+            black_box(test::call_service(app_service, req).await)
+        });
+    });
+
+    group.finish();
+}
+
+// ============================================================================
+// Article Copy vs Shared Arc Benchmarks
+// ============================================================================
+
+fn bench_article_copy(c: &mut Criterion) {
+    let mut group = c.benchmark_group("article_copy");
+    group.sample_size(50);
+    let rt = tokio::runtime::Runtime::new().unwrap(); // One runtime for the whole group
+
+    for (name, copy_articles) in [("shared_arc", false), ("copied", true)] {
+        let app_state = Arc::new(AppState {
+            copy_articles,
+            ..build_app_state(BenchConfig::large_response())
+        });
+
+        let app_service = rt.block_on(async {
+            test::init_service(
+                App::new()
+                    .app_data(web::Data::from(Arc::clone(&app_state)))
+                    .route(
+                        "/v1/chat/completions",
+                        web::post().to(chat_completions_handler),
+                    ),
+            )
+            .await
+        });
+
+        group.bench_with_input(BenchmarkId::from_parameter(name), name, |b, _| {
+            let app_service = &app_service;
+            b.to_async(&rt).iter(|| async move {
                 let payload = serde_json::json!({
                     "model": "gpt-4-mock",
                     "messages": [
                         {"role": "user", "content": "Hello!"}
                     ],
-                    "stream": true
+                    "stream": false
                 });
 
                 let req = test::TestRequest::post()
@@ -433,9 +459,9 @@ fn bench_chat_completions_streaming(c: &mut Criterion) {
                     .to_request();
 
                 black_box(test::call_service(app_service, req).await)
-            }
+            });
         });
-    });
+    }
 
     group.finish();
 }
@@ -470,24 +496,21 @@ fn bench_response_delay_impact(c: &mut Criterion) {
 
         group.bench_with_input(BenchmarkId::from_parameter(name), name, |b, _| {
             let app_service = &app_service;
-            b.to_async(&rt).iter(|| {
-                let app_service = app_service;
-                async move {
-                    let payload = serde_json::json!({
-                        "model": "gpt-4-mock",
-                        "messages": [
-                            {"role": "user", "content": "Hello!"}
-                        ],
-                        "stream": false
-                    });
+            b.to_async(&rt).iter(|| async move {
+                let payload = serde_json::json!({
+                    "model": "gpt-4-mock",
+                    "messages": [
+                        {"role": "user", "content": "Hello!"}
+                    ],
+                    "stream": false
+                });
 
-                    let req = test::TestRequest::post()
-                        .uri("/v1/chat/completions")
-                        .set_json(payload)
-                        .to_request();
+                let req = test::TestRequest::post()
+                    .uri("/v1/chat/completions")
+                    .set_json(payload)
+                    .to_request();
 
-                    black_box(test::call_service(app_service, req).await)
-                }
+                black_box(test::call_service(app_service, req).await)
             });
         });
     }
@@ -495,6 +518,105 @@ fn bench_response_delay_impact(c: &mut Criterion) {
     group.finish();
 }
 
+// ============================================================================
+// SSE Frame Building Benchmarks
+// ============================================================================
+
+fn bench_sse_frame_building(c: &mut Criterion) {
+    let mut group = c.benchmark_group("sse_frame_building");
+
+    for (name, content) in [
+        ("plain", " consectetur adipiscing elit sed do"),
+        ("escaped", " \"quoted\"\tand\nbroken \\ lines"),
+    ] {
+        group.bench_with_input(
+            BenchmarkId::new("serde_value", name),
+            content,
+            |b, content| {
+                b.iter(|| {
+                    black_box(bytes::Bytes::from(sse_event_from_content(black_box(
+                        content,
+                    ))))
+                })
+            },
+        );
+        group.bench_with_input(
+            BenchmarkId::new("reused_buffer", name),
+            content,
+            |b, content| {
+                let mut buf = bytes::BytesMut::new();
+                b.iter(|| {
+                    write_sse_event_from_content(&mut buf, black_box(content));
+                    black_box(buf.split().freeze())
+                })
+            },
+        );
+    }
+
+    group.finish();
+}
+
+// ============================================================================
+// Long Article Benchmarks
+// ============================================================================
+
+fn bench_long_articles(c: &mut Criterion) {
+    let mut group = c.benchmark_group("long_articles");
+    group.sample_size(30);
+    let rt = tokio::runtime::Runtime::new().unwrap(); // One runtime for the whole group
+
+    for article_kib in [16, 256, 1024] {
+        // Mixed-width chars so byte and char offsets differ
+        let article = Arc::new("lorem ipsum dolor é sit amet ".repeat(article_kib * 1024 / 30));
+
+        // A short cut of a long article only scans up to the cut
+        group.bench_with_input(
+            BenchmarkId::new("slice_text_by_tokens", format!("{}KiB", article_kib)),
+            &article,
+            |b, article| b.iter(|| black_box(slice_text_by_tokens(black_box(article), 64, false))),
+        );
+
+        // Streaming the whole article: each chunk is sliced from a byte cursor
+        let app_state = Arc::new(AppState {
+            articles: vec![Arc::clone(&article)],
+            token_mean: (article.len() / 4) as f64,
+            token_stddev: 0.0,
+            ..build_app_state(BenchConfig::small_response())
+        });
+        let app_service = rt.block_on(async {
+            test::init_service(
+                App::new()
+                    .app_data(web::Data::from(Arc::clone(&app_state)))
+                    .route(
+                        "/v1/chat/completions",
+                        web::post().to(chat_completions_handler),
+                    ),
+            )
+            .await
+        });
+        group.bench_function(
+            BenchmarkId::new("chat_streaming", format!("{}KiB", article_kib)),
+            |b| {
+                let app_service = &app_service;
+                b.to_async(&rt).iter(|| async move {
+                    let req = test::TestRequest::post()
+                        .uri("/v1/chat/completions")
+                        .set_json(serde_json::json!({
+                            "model": "gpt-4-mock",
+                            "messages": [{"role": "user", "content": "Hello!"}],
+                            "stream": true
+                        }))
+                        .to_request();
+                    let resp = test::call_service(app_service, req).await;
+                    black_box(test::read_body(resp).await)
+                });
+            },
+        );
+    }
+
+    group.finish();
+}
+
 // ============================================================================
 // Article Pool Size Benchmarks
 // ============================================================================
@@ -530,24 +652,21 @@ fn bench_article_pool_sizes(c: &mut Criterion) {
             pool_size,
             |b, _| {
                 let app_service = &app_service;
-                b.to_async(&rt).iter(|| {
-                    let app_service = app_service;
-                    async move {
-                        let payload = serde_json::json!({
-                            "model": "gpt-4-mock",
-                            "messages": [
-                                {"role": "user", "content": "Test message"}
-                            ],
-                            "stream": false
-                        });
-
-                        let req = test::TestRequest::post()
-                            .uri("/v1/chat/completions")
-                            .set_json(payload)
-                            .to_request();
-
-                        black_box(test::call_service(app_service, req).await)
-                    }
+                b.to_async(&rt).iter(|| async move {
+                    let payload = serde_json::json!({
+                        "model": "gpt-4-mock",
+                        "messages": [
+                            {"role": "user", "content": "Test message"}
+                        ],
+                        "stream": false
+                    });
+
+                    let req = test::TestRequest::post()
+                        .uri("/v1/chat/completions")
+                        .set_json(payload)
+                        .to_request();
+
+                    black_box(test::call_service(app_service, req).await)
                 });
             },
         );
@@ -591,7 +710,6 @@ fn bench_combined_configurations(c: &mut Criterion) {
         group.bench_with_input(BenchmarkId::from_parameter(name), &config, |b, _| {
             let app_service = &app_service;
             b.to_async(&rt).iter(|| {
-                let app_service = app_service;
                 async move {
                     // Simulate a mixed workload
                     let health_req = test::TestRequest::get().uri("/health").to_request();
@@ -634,7 +752,10 @@ criterion_group!(
     bench_completions,
     bench_chat_completions_non_streaming,
     bench_chat_completions_streaming,
+    bench_article_copy,
     bench_response_delay_impact,
+    bench_sse_frame_building,
+    bench_long_articles,
     bench_article_pool_sizes,
     bench_combined_configurations
 );
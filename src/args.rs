@@ -1,36 +1,356 @@
 //! CLI argument definitions and environment variable handling
 
-use clap::Parser;
+use crate::metrics::{parse_latency_buckets, LatencyBuckets};
+use crate::types::{
+    ArticleSelection, ContentCharset, ContextOverflow, EmbeddingQuantization, Endpoint, ModelEcho,
+    ModelInfo, ModelPricing, ServiceTierFactors, StreamDelayDist, StreamGranularity,
+    TimestampPrecision, VerbosityFactors, DEFAULT_DONE_SENTINEL, DEFAULT_EMPTY_POOL_TEXT,
+};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use clap::{Parser, ValueEnum};
+use serde::{Serialize, Serializer};
 use std::path::PathBuf;
 
-/// CLI arguments for the server
-#[derive(Parser, Debug)]
+/// CLI arguments for the server; serialized for GET /debug/config
+#[derive(Parser, Debug, Serialize)]
 #[command(author, version, about = "Mock OpenAI API server for benchmarking")]
 pub struct Args {
     /// Port to listen on
     #[arg(short, long, default_value_t = 3000)]
     pub port: u16,
 
+    /// Extra attempts to bind the port while it is in use before giving up
+    /// (with short backoff)
+    #[arg(long, default_value_t = 0)]
+    pub bind_retries: u32,
+
+    /// Shut down gracefully after serving this many /v1/* requests (0 = unlimited)
+    #[arg(long, default_value_t = 0)]
+    pub max_requests: u64,
+
     /// Number of pre-generated articles
     #[arg(long, default_value_t = 4096)]
     pub pregen_count: usize,
 
+    /// Content served when the article pool is empty (e.g. `--pregen-count 0`)
+    #[arg(long, default_value = DEFAULT_EMPTY_POOL_TEXT, value_name = "STR")]
+    pub empty_pool_text: String,
+
+    /// Minimum number of words in every pre-generated article
+    #[arg(long, default_value_t = 1)]
+    pub min_article_words: usize,
+
+    /// Stop pre-generating once the article pool would exceed this many bytes,
+    /// serving a smaller pool instead (0 = unlimited)
+    #[arg(long, default_value_t = 0)]
+    pub max_corpus_bytes: usize,
+
+    /// Characters the pre-generated articles are written in: latin (lorem
+    /// ipsum), cjk, emoji, mixed or synthetic
+    #[arg(long, value_enum, default_value_t = ContentCharset::Latin)]
+    pub content_charset: ContentCharset,
+
+    /// Mean word length in characters of `--content-charset synthetic`
+    /// articles
+    #[arg(long, default_value_t = 5.0)]
+    pub word_length_mean: f64,
+
+    /// Standard deviation of the word length of `--content-charset
+    /// synthetic` articles
+    #[arg(long, default_value_t = 2.0)]
+    pub word_length_stddev: f64,
+
+    /// How each response's article is picked: uniform (any article) or fit
+    /// (one long enough for the sampled token count, joining several if needed)
+    #[arg(long, value_enum, default_value_t = ArticleSelection::Uniform)]
+    pub article_selection: ArticleSelection,
+
+    /// Log pre-generation progress every N articles (default: every 10%)
+    #[arg(long)]
+    pub pregen_progress_interval: Option<usize>,
+
     /// Mean tokens per generated response
-    #[arg(long, default_value_t = 256.0)]
+    #[arg(long, default_value_t = 256.0, allow_hyphen_values = true)]
     pub token_mean: f64,
 
     /// Standard deviation for tokens per response
-    #[arg(long, default_value_t = 64.0)]
+    #[arg(long, default_value_t = 64.0, allow_hyphen_values = true)]
     pub token_stddev: f64,
 
+    /// Extra random multiplier range for the sampled completion tokens, drawn
+    /// independently of the request seed (0.2 = ±20%; 0 = fully deterministic)
+    #[arg(long, default_value_t = 0.0)]
+    pub token_count_jitter: f64,
+
+    /// Smallest sampled completion token count (also floors streaming chunk sizes)
+    #[arg(long, default_value_t = 1)]
+    pub token_min: usize,
+
+    /// Largest sampled completion token count (also caps streaming chunk sizes)
+    #[arg(long)]
+    pub token_max: Option<usize>,
+
+    /// Token count multipliers for the `verbosity` request field, as "LOW,MEDIUM,HIGH"
+    #[arg(long, default_value = "0.3,1.0,2.5", value_parser = parse_verbosity_factors)]
+    pub verbosity_factors: VerbosityFactors,
+
+    /// Latency multipliers for the `service_tier` request field, as "DEFAULT,FLEX"
+    #[arg(long, default_value = "1.0,3.0", value_parser = parse_service_tier_factors)]
+    pub service_tier_factors: ServiceTierFactors,
+
     /// Delay in milliseconds per SSE event to emulate network latency
     #[arg(long, default_value_t = 0)]
     pub response_delay_ms: u64,
 
+    /// Stream the first N tokens of every response without any per-chunk
+    /// delay, then pace the rest (a KV-cache warm-up burst; 0 = off)
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    pub stream_burst_tokens: usize,
+
+    /// Distribution each per-chunk delay is drawn from, with mean
+    /// `--response-delay-ms`: constant, normal or exponential
+    #[arg(long, value_enum, default_value_t = StreamDelayDist::Constant)]
+    pub stream_delay_dist: StreamDelayDist,
+
+    /// Standard deviation in milliseconds of the per-chunk delay under
+    /// `--stream-delay-dist normal`
+    #[arg(long, default_value_t = 0.0)]
+    pub stream_delay_stddev_ms: f64,
+
+    /// How streamed content is cut into SSE frames: token (sampled chunk
+    /// sizes) or sentence (one sentence per frame)
+    #[arg(long, value_enum, default_value_t = StreamGranularity::Token)]
+    pub stream_granularity: StreamGranularity,
+
+    /// Delay in milliseconds before the request body is even read, to test
+    /// client upload/connect-phase timeouts separately from processing latency
+    #[arg(long, default_value_t = 0)]
+    pub pre_parse_delay_ms: u64,
+
+    /// Delay in milliseconds before the response head (status line and
+    /// headers) is sent, to test clients' connect-read timeouts
+    #[arg(long, default_value_t = 0)]
+    pub header_delay_ms: u64,
+
+    /// Delay in milliseconds before any content is sent, emulating a model that
+    /// pauses to reason (applied once, before the first SSE event or the body)
+    #[arg(long, default_value_t = 0)]
+    pub thinking_delay_ms: u64,
+
+    /// Stream this many tokens as `delta.reasoning_content` before the answer's
+    /// `delta.content`, like a reasoning model (chat streams only; 0 = off)
+    #[arg(long, default_value_t = 0)]
+    pub reasoning_tokens: usize,
+
+    /// Pause in milliseconds between the last reasoning delta and the first
+    /// answer delta
+    #[arg(long, default_value_t = 0)]
+    pub reasoning_gap_ms: u64,
+
+    /// Close every stream within this many milliseconds, cutting it short with
+    /// `finish_reason: "length"` if needed (0 = unlimited)
+    #[arg(long, default_value_t = 0)]
+    pub max_stream_duration_ms: u64,
+
+    /// Busy-compute for this many milliseconds in every chat/completions
+    /// handler, occupying the worker thread instead of sleeping
+    #[arg(long, default_value_t = 0)]
+    pub cpu_burn_ms: u64,
+
+    /// Extra latency per prompt token before the first output, like a real
+    /// model's prefill; added to the response delay and the streaming TTFT (ms)
+    #[arg(long, default_value_t = 0.0, allow_hyphen_values = true)]
+    pub prefill_ms_per_token: f64,
+
+    /// Simulated backend capacity: the thinking delay becomes
+    /// `delay * (1 + other_in_flight / capacity)` (0 = disabled)
+    #[arg(long, default_value_t = 0)]
+    pub capacity: usize,
+
+    /// Cap the combined streaming throughput of all concurrent streams at this
+    /// many tokens per second; streams slow down as they compete (0 = unlimited)
+    #[arg(long, default_value_t = 0.0)]
+    pub global_tps: f64,
+
+    /// Add a non-standard `_mock_ts_ms` field with the server-side emission
+    /// time (Unix millis) to every streamed chunk
+    #[arg(long, default_value_t = false)]
+    pub stream_timestamps: bool,
+
+    /// Yield to the runtime between SSE events so actix writes each one out
+    /// instead of coalescing events that are ready at the same time
+    #[arg(long, default_value_t = false)]
+    pub stream_flush_each: bool,
+
+    /// Occasionally write a streamed frame in two pieces cut inside a
+    /// multibyte character, so no single write is valid UTF-8 (tests client
+    /// reassembly of partial code points)
+    #[arg(long, default_value_t = false)]
+    pub split_multibyte: bool,
+
+    /// Start every stream with an SSE `retry: <MS>` line telling clients how
+    /// long to wait before reconnecting
+    #[arg(long, value_name = "MS")]
+    pub sse_retry_ms: Option<u64>,
+
+    /// Send an `event: <NAME>` line before every SSE data frame, for clients
+    /// that dispatch on named events (default: no event line)
+    #[arg(long, value_name = "NAME", value_parser = parse_sse_event_name)]
+    pub sse_event_name: Option<String>,
+
+    /// Payload of the stream's final `data:` frame, in place of `[DONE]`
+    #[arg(long, value_name = "STR", default_value = DEFAULT_DONE_SENTINEL, value_parser = parse_done_sentinel)]
+    pub done_sentinel: String,
+
+    /// While a stream waits out `--thinking-delay-ms`, send an SSE
+    /// `: keep-alive` comment every this many milliseconds (0 = off)
+    #[arg(long, default_value_t = 0)]
+    pub stream_keepalive_ms: u64,
+
+    /// Most `: keep-alive` comments a stream sends; once they are used up it
+    /// skips the rest of the delay and starts its content (0 = unlimited)
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    pub stream_keepalive_max: usize,
+
+    /// Minimum time in milliseconds every non-streaming response takes; fast
+    /// responses sleep out the remainder
+    #[arg(long, default_value_t = 0)]
+    pub min_response_ms: u64,
+
+    /// Copy articles into owned Strings per request instead of sharing them
+    /// (to benchmark the cost of the zero-copy design)
+    #[arg(long, default_value_t = false)]
+    pub copy_articles: bool,
+
+    /// Keep the article's original spacing and newlines at the edges of
+    /// response content instead of trimming it
+    #[arg(long, default_value_t = false)]
+    pub preserve_whitespace: bool,
+
+    /// Begin chat responses with a short quote of the last user message
+    #[arg(long, default_value_t = false)]
+    pub reflect_prompt: bool,
+
+    /// Sprinkle control characters (NUL, ESC), zero-width spaces and emoji
+    /// into generated content to exercise client parsing and rendering
+    #[arg(long, default_value_t = false)]
+    pub inject_control_chars: bool,
+
+    /// Answer non-streaming chat requests whose last user message contains
+    /// this phrase (case-insensitive) with a `refusal` instead of content
+    #[arg(long, value_name = "PHRASE")]
+    pub refusal_trigger: Option<String>,
+
+    /// Maximum number of tool calls returned in one assistant message when the
+    /// request has tools and `parallel_tool_calls` is enabled
+    #[arg(long, default_value_t = 1)]
+    pub max_tool_calls: usize,
+
+    /// Attach this many `url_citation` annotations, pointing at words of the
+    /// generated content, to each chat response (0 = no annotations)
+    #[arg(long, default_value_t = 0)]
+    pub url_citations: usize,
+
+    /// Report part of the prompt as `usage.prompt_tokens_details.cached_tokens`
+    /// when an identical prompt was seen recently
+    #[arg(long, default_value_t = false)]
+    pub simulate_prompt_cache: bool,
+
+    /// Answer identical chat, completions and embeddings request bodies with
+    /// byte-identical responses by caching each full non-streaming response
+    #[arg(long, default_value_t = false)]
+    pub idempotent: bool,
+
+    /// Fraction of prompt tokens reported as cached on a prompt cache hit
+    #[arg(long, default_value_t = 0.5)]
+    pub prompt_cache_fraction: f64,
+
+    /// Probability (0.0-1.0) that a chat response is cut off halfway with
+    /// `finish_reason: "content_filter"`
+    #[arg(long, default_value_t = 0.0, value_parser = parse_rate)]
+    pub content_filter_rate: f64,
+
+    /// Probability (0.0-1.0) that a non-streaming chat, completions or
+    /// embeddings response body is cut off at a random byte, still with status
+    /// 200 (fault injection)
+    #[arg(long, default_value_t = 0.0, value_parser = parse_rate)]
+    pub corrupt_response_rate: f64,
+
+    /// Probability (0.0-1.0) that a non-streaming chat response is a 200 with
+    /// an empty `choices` array and no completion tokens (fault injection)
+    #[arg(long, default_value_t = 0.0, value_parser = parse_rate)]
+    pub empty_choices_rate: f64,
+
+    /// Model id pattern (glob with `*`/`?`, otherwise substring) that always
+    /// returns a 404 model_not_found on chat/completions (repeatable)
+    #[arg(long = "deny-model", value_name = "PATTERN")]
+    pub deny_models: Vec<String>,
+
+    /// Serve requests for ALIAS as TARGET, as "ALIAS=TARGET" (repeatable)
+    #[arg(long = "model-alias", value_name = "ALIAS=TARGET", value_parser = parse_model_alias)]
+    pub model_aliases: Vec<(String, String)>,
+
+    /// Model name reported for aliased requests: the alias target
+    /// (`canonical`) or the name as requested (`requested`)
+    #[arg(long, value_enum, default_value_t = ModelEcho::Canonical)]
+    pub model_echo: ModelEcho,
+
+    /// Suffix the reported model with `-replica-<K>`, K drawn at random from
+    /// 1..=N per request, as if served by a backend pool (0 = off)
+    #[arg(long, default_value_t = 0)]
+    pub model_replicas: usize,
+
+    /// Reject chat/completions requests whose prompt tokens plus `max_tokens`
+    /// exceed this with a 400 `context_length_exceeded` (0 = no limit)
+    #[arg(long, default_value_t = 0)]
+    pub max_context_tokens: usize,
+
+    /// What happens to requests over `--max-context-tokens`: `error` (400
+    /// `context_length_exceeded`) or `truncate` (the prompt is cut to fit and
+    /// `prompt_tokens` reports what was kept)
+    #[arg(long, value_enum, default_value_t = ContextOverflow::Error)]
+    pub context_overflow: ContextOverflow,
+
+    /// Reject requests that mix up chat and legacy completions fields
+    /// (`messages` on /v1/completions, `prompt` on /v1/chat/completions)
+    #[arg(long, default_value_t = false)]
+    pub strict_schema: bool,
+
+    /// Shape chat responses like Azure OpenAI: add `prompt_filter_results`
+    /// with content-filter categories derived from the prompt text
+    #[arg(long, default_value_t = false)]
+    pub azure_compat: bool,
+
+    /// Leave an endpoint unregistered so requests to it get a 404, e.g.
+    /// `embeddings` or `completions` (repeatable)
+    #[arg(long = "disable-endpoint", value_enum, value_name = "NAME")]
+    pub disabled_endpoints: Vec<Endpoint>,
+
+    /// Enable debugging endpoints such as POST /debug/echo-request and
+    /// GET /debug/config
+    #[arg(long, default_value_t = false)]
+    pub debug: bool,
+
+    /// Mount the /admin/* endpoints, guarded by this bearer token
+    #[arg(long, value_name = "TOKEN")]
+    #[serde(skip)]
+    pub admin_token: Option<String>,
+
+    /// Require this API key on /v1/* requests, sent as `Authorization: Bearer
+    /// <KEY>` or Azure-style `api-key: <KEY>`
+    #[arg(long, value_name = "KEY")]
+    #[serde(skip)]
+    pub api_key: Option<String>,
+
     /// Verbose output
     #[arg(short, long, default_value_t = false)]
     pub verbose: bool,
 
+    /// JSON file returned verbatim by non-streaming chat completions,
+    /// overriding all generation (validated at startup)
+    #[arg(long, value_name = "PATH")]
+    pub canned_chat_response: Option<PathBuf>,
+
     /// Path to TLS certificate file (PEM format) for HTTPS/HTTP2 support
     #[arg(long)]
     pub tls_cert: Option<PathBuf>,
@@ -38,6 +358,264 @@ pub struct Args {
     /// Path to TLS private key file (PEM format) for HTTPS/HTTP2 support
     #[arg(long)]
     pub tls_key: Option<PathBuf>,
+
+    /// Compress non-streaming responses using the client's preferred
+    /// Accept-Encoding (br, zstd, gzip, deflate)
+    #[arg(long, default_value_t = false)]
+    pub compress: bool,
+
+    /// Compression level (0-11; clamped to 9 for gzip/deflate)
+    #[arg(long, default_value_t = 6, value_parser = clap::value_parser!(u32).range(0..=11))]
+    pub compression_level: u32,
+
+    /// Buffer non-streaming responses and always send an explicit
+    /// Content-Length (SSE streams stay chunked)
+    #[arg(long, default_value_t = false)]
+    pub force_content_length: bool,
+
+    /// Close the connection after every response (`Connection: close`) so
+    /// clients reconnect per request, instead of keeping it alive
+    #[arg(long, default_value_t = false)]
+    pub connection_close: bool,
+
+    /// Upper bounds in milliseconds of the /metrics latency histogram
+    /// buckets, comma-separated and ascending
+    #[arg(long, default_value = "5,10,25,50,100,250,500,1000,2500,5000,10000", value_parser = parse_latency_buckets)]
+    pub metrics_latency_buckets: LatencyBuckets,
+
+    /// Seconds added to the `created` timestamp of responses (negative values
+    /// simulate stale responses)
+    #[arg(long, default_value_t = 0, allow_hyphen_values = true)]
+    pub created_offset_secs: i64,
+
+    /// Unit of the `created` timestamp of completion responses: `s` (epoch
+    /// seconds) or `ms` (epoch milliseconds)
+    #[arg(long, value_enum, default_value_t = TimestampPrecision::Seconds)]
+    pub timestamp_precision: TimestampPrecision,
+
+    /// Value of the `openai-organization` header on /v1/* responses
+    #[arg(long, default_value = "mock-openai")]
+    pub organization: String,
+
+    /// Extra header added to every response, as "Name: Value" (repeatable)
+    #[arg(long = "response-header", value_name = "NAME: VALUE", value_parser = parse_response_header)]
+    #[serde(serialize_with = "serialize_response_headers")]
+    pub response_headers: Vec<(HeaderName, HeaderValue)>,
+
+    /// Quantize returned embeddings (int8: integers in [-127, 127])
+    #[arg(long, value_enum, value_name = "MODE")]
+    pub embedding_quantize: Option<EmbeddingQuantization>,
+
+    /// Largest `n` (embeddings per input) accepted on /v1/embeddings
+    #[arg(long, default_value_t = 16)]
+    pub max_embedding_n: usize,
+
+    /// Base delay in milliseconds before every /v1/embeddings response
+    #[arg(long, default_value_t = 0)]
+    pub embedding_delay_ms: u64,
+
+    /// Additional /v1/embeddings delay in milliseconds per input in the batch
+    #[arg(long, default_value_t = 0)]
+    pub embedding_delay_per_input_ms: u64,
+
+    /// Largest number of messages accepted in a chat request (0 = unlimited)
+    #[arg(long, default_value_t = 0)]
+    pub max_messages: usize,
+
+    /// Largest response body in bytes: non-streaming content is truncated to
+    /// fit (with `finish_reason: "length"`) and streams stop at the limit
+    /// (0 = unlimited)
+    #[arg(long, default_value_t = 0)]
+    pub max_response_bytes: usize,
+
+    /// Largest file (in bytes) accepted by POST /v1/files; uploads are kept in memory
+    #[arg(long, default_value_t = 512 * 1024 * 1024)]
+    pub max_file_bytes: usize,
+
+    /// Add OpenAI list-object pagination fields (`first_id`, `last_id`,
+    /// `has_more`) to embedding responses
+    #[arg(long, default_value_t = false)]
+    pub list_pagination: bool,
+
+    /// Page GET /v1/models by its `limit` and `after` query parameters and
+    /// add list-object pagination fields (`first_id`, `last_id`, `has_more`)
+    #[arg(long, default_value_t = false)]
+    pub models_paginate: bool,
+
+    /// Serve a model with metadata, as
+    /// "ID=CONTEXT_WINDOW,MAX_OUTPUT_TOKENS,INPUT_PER_1K,OUTPUT_PER_1K[,CHUNK_DELAY_MS[,ERROR_RATE]]";
+    /// replaces the default metadata for an existing id. CHUNK_DELAY_MS paces
+    /// this model's streams instead of `--response-delay-ms` and may be left
+    /// empty; ERROR_RATE is the probability a request to the model fails with
+    /// a 500 (repeatable)
+    #[arg(long = "model-metadata", value_name = "SPEC", value_parser = parse_model_metadata)]
+    pub model_metadata: Vec<ModelInfo>,
+}
+
+/// Read a boolean environment variable, accepting `true`/`false` or `1`/`0`
+/// for compatibility
+fn env_bool(name: &str) -> Option<bool> {
+    let val = std::env::var(name).ok()?;
+    match val.as_str() {
+        "1" => Some(true),
+        "0" => Some(false),
+        _ => val.parse::<bool>().ok(),
+    }
+}
+
+/// Parse "LOW,MEDIUM,HIGH" verbosity multipliers
+pub fn parse_verbosity_factors(s: &str) -> Result<VerbosityFactors, String> {
+    let factors = s
+        .split(',')
+        .map(|f| f.trim().parse::<f64>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("invalid verbosity factor in {:?}: {}", s, e))?;
+    match factors[..] {
+        [low, medium, high] if factors.iter().all(|f| f.is_finite() && *f > 0.0) => {
+            Ok(VerbosityFactors { low, medium, high })
+        }
+        _ => Err(format!(
+            "expected three positive factors \"LOW,MEDIUM,HIGH\", got {:?}",
+            s
+        )),
+    }
+}
+
+/// Parse "DEFAULT,FLEX" service tier latency multipliers
+pub fn parse_service_tier_factors(s: &str) -> Result<ServiceTierFactors, String> {
+    let factors = s
+        .split(',')
+        .map(|f| f.trim().parse::<f64>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("invalid service tier factor in {:?}: {}", s, e))?;
+    match factors[..] {
+        [default, flex] if factors.iter().all(|f| f.is_finite() && *f > 0.0) => {
+            Ok(ServiceTierFactors { default, flex })
+        }
+        _ => Err(format!(
+            "expected two positive factors \"DEFAULT,FLEX\", got {:?}",
+            s
+        )),
+    }
+}
+
+/// Parse a probability, which must be a finite number in 0.0-1.0
+pub fn parse_rate(s: &str) -> Result<f64, String> {
+    match s.trim().parse::<f64>() {
+        Ok(rate) if (0.0..=1.0).contains(&rate) => Ok(rate),
+        _ => Err(format!(
+            "expected a probability between 0.0 and 1.0, got {:?}",
+            s
+        )),
+    }
+}
+
+/// Parse an SSE event name, which must fit on the `event:` line
+pub fn parse_sse_event_name(s: &str) -> Result<String, String> {
+    if s.is_empty() || s.contains(['\r', '\n']) {
+        return Err(format!(
+            "event name must be non-empty and on one line, got {:?}",
+            s
+        ));
+    }
+    Ok(s.to_string())
+}
+
+/// Parse a `--done-sentinel`, which must fit on the final `data:` line
+pub fn parse_done_sentinel(s: &str) -> Result<String, String> {
+    if s.is_empty() || s.contains(['\r', '\n']) {
+        return Err(format!(
+            "done sentinel must be non-empty and on one line, got {:?}",
+            s
+        ));
+    }
+    Ok(s.to_string())
+}
+
+/// Serialize `--response-header` pairs back into their "Name: Value" form
+fn serialize_response_headers<S: Serializer>(
+    headers: &[(HeaderName, HeaderValue)],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(
+        headers
+            .iter()
+            .map(|(name, value)| format!("{}: {}", name, value.to_str().unwrap_or_default())),
+    )
+}
+
+/// Parse a "Name: Value" pair into a validated header name and value
+pub fn parse_response_header(s: &str) -> Result<(HeaderName, HeaderValue), String> {
+    let (name, value) = s
+        .split_once(':')
+        .ok_or_else(|| format!("expected \"Name: Value\", got {:?}", s))?;
+    let name = HeaderName::from_bytes(name.trim().as_bytes())
+        .map_err(|e| format!("invalid header name {:?}: {}", name.trim(), e))?;
+    let value = HeaderValue::from_str(value.trim())
+        .map_err(|e| format!("invalid header value {:?}: {}", value.trim(), e))?;
+    Ok((name, value))
+}
+
+/// Parse an "ALIAS=TARGET" model alias
+pub fn parse_model_alias(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((alias, target)) if !alias.trim().is_empty() && !target.trim().is_empty() => {
+            Ok((alias.trim().to_string(), target.trim().to_string()))
+        }
+        _ => Err(format!("expected \"ALIAS=TARGET\", got {:?}", s)),
+    }
+}
+
+/// Parse "ID=CONTEXT_WINDOW,MAX_OUTPUT_TOKENS,INPUT_PER_1K,OUTPUT_PER_1K[,CHUNK_DELAY_MS[,ERROR_RATE]]"
+/// into a model with metadata
+pub fn parse_model_metadata(s: &str) -> Result<ModelInfo, String> {
+    let err = || {
+        format!(
+            "expected \"ID=CONTEXT_WINDOW,MAX_OUTPUT_TOKENS,INPUT_PER_1K,OUTPUT_PER_1K[,CHUNK_DELAY_MS[,ERROR_RATE]]\", got {:?}",
+            s
+        )
+    };
+    let (id, spec) = s.split_once('=').ok_or_else(err)?;
+    let fields: Vec<&str> = spec.split(',').map(str::trim).collect();
+    let (context_window, max_output_tokens, input, output, delay, error_rate) = match fields[..] {
+        [c, m, i, o] => (c, m, i, o, "", ""),
+        [c, m, i, o, d] => (c, m, i, o, d, ""),
+        [c, m, i, o, d, e] => (c, m, i, o, d, e),
+        _ => return Err(err()),
+    };
+    let chunk_delay_ms = match delay {
+        "" => None,
+        d => Some(d.parse::<u64>().map_err(|_| err())?),
+    };
+    let error_rate = match error_rate {
+        "" => 0.0,
+        e => parse_rate(e).map_err(|_| err())?,
+    };
+    let id = id.trim();
+    let context_window = context_window.parse::<u32>().map_err(|_| err())?;
+    let max_output_tokens = max_output_tokens.parse::<u32>().map_err(|_| err())?;
+    let input_per_1k = input.parse::<f64>().map_err(|_| err())?;
+    let output_per_1k = output.parse::<f64>().map_err(|_| err())?;
+    if id.is_empty() || max_output_tokens > context_window {
+        return Err(err());
+    }
+    if ![input_per_1k, output_per_1k]
+        .iter()
+        .all(|p| p.is_finite() && *p >= 0.0)
+    {
+        return Err(err());
+    }
+    Ok(ModelInfo {
+        context_window: Some(context_window),
+        max_output_tokens: Some(max_output_tokens),
+        pricing: Some(ModelPricing {
+            input_per_1k,
+            output_per_1k,
+        }),
+        chunk_delay_ms,
+        error_rate,
+        ..ModelInfo::new(id)
+    })
 }
 
 impl Args {
@@ -52,11 +630,59 @@ impl Args {
                 self.port = v;
             }
         }
+        if let Ok(val) = std::env::var("MOCK_OPENAI_BIND_RETRIES") {
+            if let Ok(v) = val.parse::<u32>() {
+                self.bind_retries = v;
+            }
+        }
+        if let Ok(val) = std::env::var("MOCK_OPENAI_MAX_REQUESTS") {
+            if let Ok(v) = val.parse::<u64>() {
+                self.max_requests = v;
+            }
+        }
         if let Ok(val) = std::env::var("MOCK_OPENAI_PREG_COUNT") {
             if let Ok(v) = val.parse::<usize>() {
                 self.pregen_count = v;
             }
         }
+        if let Ok(val) = std::env::var("MOCK_OPENAI_EMPTY_POOL_TEXT") {
+            self.empty_pool_text = val;
+        }
+        if let Ok(val) = std::env::var("MOCK_OPENAI_MIN_ARTICLE_WORDS") {
+            if let Ok(v) = val.parse::<usize>() {
+                self.min_article_words = v;
+            }
+        }
+        if let Ok(val) = std::env::var("MOCK_OPENAI_MAX_CORPUS_BYTES") {
+            if let Ok(v) = val.parse::<usize>() {
+                self.max_corpus_bytes = v;
+            }
+        }
+        if let Ok(val) = std::env::var("MOCK_OPENAI_CONTENT_CHARSET") {
+            if let Ok(v) = ContentCharset::from_str(&val, true) {
+                self.content_charset = v;
+            }
+        }
+        if let Ok(val) = std::env::var("MOCK_OPENAI_WORD_LENGTH_MEAN") {
+            if let Ok(v) = val.parse::<f64>() {
+                self.word_length_mean = v;
+            }
+        }
+        if let Ok(val) = std::env::var("MOCK_OPENAI_WORD_LENGTH_STDDEV") {
+            if let Ok(v) = val.parse::<f64>() {
+                self.word_length_stddev = v;
+            }
+        }
+        if let Ok(val) = std::env::var("MOCK_OPENAI_ARTICLE_SELECTION") {
+            if let Ok(v) = ArticleSelection::from_str(&val, true) {
+                self.article_selection = v;
+            }
+        }
+        if let Ok(val) = std::env::var("MOCK_OPENAI_PREGEN_PROGRESS_INTERVAL") {
+            if let Ok(v) = val.parse::<usize>() {
+                self.pregen_progress_interval = Some(v);
+            }
+        }
         if let Ok(val) = std::env::var("MOCK_OPENAI_TOKEN_MEAN") {
             if let Ok(v) = val.parse::<f64>() {
                 self.token_mean = v;
@@ -67,21 +693,312 @@ impl Args {
                 self.token_stddev = v;
             }
         }
+        if let Ok(val) = std::env::var("MOCK_OPENAI_TOKEN_COUNT_JITTER") {
+            if let Ok(v) = val.parse::<f64>() {
+                self.token_count_jitter = v;
+            }
+        }
+        if let Ok(val) = std::env::var("MOCK_OPENAI_TOKEN_MIN") {
+            if let Ok(v) = val.parse::<usize>() {
+                self.token_min = v;
+            }
+        }
+        if let Ok(val) = std::env::var("MOCK_OPENAI_TOKEN_MAX") {
+            if let Ok(v) = val.parse::<usize>() {
+                self.token_max = Some(v);
+            }
+        }
+        if let Ok(val) = std::env::var("MOCK_OPENAI_VERBOSITY_FACTORS") {
+            if let Ok(v) = parse_verbosity_factors(&val) {
+                self.verbosity_factors = v;
+            }
+        }
+        if let Ok(val) = std::env::var("MOCK_OPENAI_SERVICE_TIER_FACTORS") {
+            if let Ok(v) = parse_service_tier_factors(&val) {
+                self.service_tier_factors = v;
+            }
+        }
         if let Ok(val) = std::env::var("MOCK_OPENAI_RESPONSE_DELAY_MS") {
             if let Ok(v) = val.parse::<u64>() {
                 self.response_delay_ms = v;
             }
         }
-        if let Ok(val) = std::env::var("MOCK_OPENAI_VERBOSE") {
-            // Accept `true`/`false` or `1`/`0` for compatibility
-            if let Ok(v) = val.parse::<bool>() {
-                self.verbose = v;
-            } else if val == "1" {
-                self.verbose = true;
-            } else if val == "0" {
-                self.verbose = false;
+        if let Ok(val) = std::env::var("MOCK_OPENAI_STREAM_BURST_TOKENS") {
+            if let Ok(v) = val.parse::<usize>() {
+                self.stream_burst_tokens = v;
+            }
+        }
+        if let Ok(val) = std::env::var("MOCK_OPENAI_STREAM_DELAY_DIST") {
+            if let Ok(v) = StreamDelayDist::from_str(&val, true) {
+                self.stream_delay_dist = v;
+            }
+        }
+        if let Ok(val) = std::env::var("MOCK_OPENAI_STREAM_GRANULARITY") {
+            if let Ok(v) = StreamGranularity::from_str(&val, true) {
+                self.stream_granularity = v;
+            }
+        }
+        if let Ok(val) = std::env::var("MOCK_OPENAI_STREAM_DELAY_STDDEV_MS") {
+            if let Ok(v) = val.parse::<f64>() {
+                self.stream_delay_stddev_ms = v;
+            }
+        }
+        if let Ok(val) = std::env::var("MOCK_OPENAI_PRE_PARSE_DELAY_MS") {
+            if let Ok(v) = val.parse::<u64>() {
+                self.pre_parse_delay_ms = v;
+            }
+        }
+        if let Ok(val) = std::env::var("MOCK_OPENAI_HEADER_DELAY_MS") {
+            if let Ok(v) = val.parse::<u64>() {
+                self.header_delay_ms = v;
+            }
+        }
+        if let Ok(val) = std::env::var("MOCK_OPENAI_THINKING_DELAY_MS") {
+            if let Ok(v) = val.parse::<u64>() {
+                self.thinking_delay_ms = v;
+            }
+        }
+        if let Ok(val) = std::env::var("MOCK_OPENAI_REASONING_TOKENS") {
+            if let Ok(v) = val.parse::<usize>() {
+                self.reasoning_tokens = v;
+            }
+        }
+        if let Ok(val) = std::env::var("MOCK_OPENAI_REASONING_GAP_MS") {
+            if let Ok(v) = val.parse::<u64>() {
+                self.reasoning_gap_ms = v;
+            }
+        }
+        if let Ok(val) = std::env::var("MOCK_OPENAI_MAX_STREAM_DURATION_MS") {
+            if let Ok(v) = val.parse::<u64>() {
+                self.max_stream_duration_ms = v;
+            }
+        }
+        if let Ok(val) = std::env::var("MOCK_OPENAI_CPU_BURN_MS") {
+            if let Ok(v) = val.parse::<u64>() {
+                self.cpu_burn_ms = v;
+            }
+        }
+        if let Ok(val) = std::env::var("MOCK_OPENAI_PREFILL_MS_PER_TOKEN") {
+            if let Ok(v) = val.parse::<f64>() {
+                self.prefill_ms_per_token = v;
+            }
+        }
+        if let Ok(val) = std::env::var("MOCK_OPENAI_CAPACITY") {
+            if let Ok(v) = val.parse::<usize>() {
+                self.capacity = v;
+            }
+        }
+        if let Ok(val) = std::env::var("MOCK_OPENAI_GLOBAL_TPS") {
+            if let Ok(v) = val.parse::<f64>() {
+                self.global_tps = v;
+            }
+        }
+        if let Some(v) = env_bool("MOCK_OPENAI_STREAM_TIMESTAMPS") {
+            self.stream_timestamps = v;
+        }
+        if let Some(v) = env_bool("MOCK_OPENAI_STREAM_FLUSH_EACH") {
+            self.stream_flush_each = v;
+        }
+        if let Some(v) = env_bool("MOCK_OPENAI_SPLIT_MULTIBYTE") {
+            self.split_multibyte = v;
+        }
+        if let Ok(val) = std::env::var("MOCK_OPENAI_SSE_RETRY_MS") {
+            if let Ok(v) = val.parse::<u64>() {
+                self.sse_retry_ms = Some(v);
+            }
+        }
+        if let Ok(val) = std::env::var("MOCK_OPENAI_SSE_EVENT_NAME") {
+            if let Ok(v) = parse_sse_event_name(&val) {
+                self.sse_event_name = Some(v);
+            }
+        }
+        if let Ok(val) = std::env::var("MOCK_OPENAI_DONE_SENTINEL") {
+            if let Ok(v) = parse_done_sentinel(&val) {
+                self.done_sentinel = v;
+            }
+        }
+        if let Ok(val) = std::env::var("MOCK_OPENAI_STREAM_KEEPALIVE_MS") {
+            if let Ok(v) = val.parse::<u64>() {
+                self.stream_keepalive_ms = v;
+            }
+        }
+        if let Ok(val) = std::env::var("MOCK_OPENAI_STREAM_KEEPALIVE_MAX") {
+            if let Ok(v) = val.parse::<usize>() {
+                self.stream_keepalive_max = v;
+            }
+        }
+        if let Ok(val) = std::env::var("MOCK_OPENAI_MAX_TOOL_CALLS") {
+            if let Ok(v) = val.parse::<usize>() {
+                self.max_tool_calls = v;
+            }
+        }
+        if let Ok(val) = std::env::var("MOCK_OPENAI_URL_CITATIONS") {
+            if let Ok(v) = val.parse::<usize>() {
+                self.url_citations = v;
+            }
+        }
+        if let Some(v) = env_bool("MOCK_OPENAI_SIMULATE_PROMPT_CACHE") {
+            self.simulate_prompt_cache = v;
+        }
+        if let Some(v) = env_bool("MOCK_OPENAI_IDEMPOTENT") {
+            self.idempotent = v;
+        }
+        if let Ok(val) = std::env::var("MOCK_OPENAI_PROMPT_CACHE_FRACTION") {
+            if let Ok(v) = val.parse::<f64>() {
+                self.prompt_cache_fraction = v;
+            }
+        }
+        if let Ok(val) = std::env::var("MOCK_OPENAI_CONTENT_FILTER_RATE") {
+            if let Ok(v) = parse_rate(&val) {
+                self.content_filter_rate = v;
             }
         }
+        if let Ok(val) = std::env::var("MOCK_OPENAI_CORRUPT_RESPONSE_RATE") {
+            if let Ok(v) = parse_rate(&val) {
+                self.corrupt_response_rate = v;
+            }
+        }
+        if let Ok(val) = std::env::var("MOCK_OPENAI_EMPTY_CHOICES_RATE") {
+            if let Ok(v) = parse_rate(&val) {
+                self.empty_choices_rate = v;
+            }
+        }
+        if let Ok(val) = std::env::var("MOCK_OPENAI_MIN_RESPONSE_MS") {
+            if let Ok(v) = val.parse::<u64>() {
+                self.min_response_ms = v;
+            }
+        }
+        if let Some(v) = env_bool("MOCK_OPENAI_COPY_ARTICLES") {
+            self.copy_articles = v;
+        }
+        if let Some(v) = env_bool("MOCK_OPENAI_PRESERVE_WHITESPACE") {
+            self.preserve_whitespace = v;
+        }
+        if let Some(v) = env_bool("MOCK_OPENAI_REFLECT_PROMPT") {
+            self.reflect_prompt = v;
+        }
+        if let Some(v) = env_bool("MOCK_OPENAI_INJECT_CONTROL_CHARS") {
+            self.inject_control_chars = v;
+        }
+        if let Ok(val) = std::env::var("MOCK_OPENAI_REFUSAL_TRIGGER") {
+            self.refusal_trigger = Some(val);
+        }
+        if let Ok(val) = std::env::var("MOCK_OPENAI_EMBEDDING_QUANTIZE") {
+            if let Ok(v) = EmbeddingQuantization::from_str(&val, true) {
+                self.embedding_quantize = Some(v);
+            }
+        }
+        if let Ok(val) = std::env::var("MOCK_OPENAI_MAX_EMBEDDING_N") {
+            if let Ok(v) = val.parse::<usize>() {
+                self.max_embedding_n = v;
+            }
+        }
+        if let Ok(val) = std::env::var("MOCK_OPENAI_EMBEDDING_DELAY_MS") {
+            if let Ok(v) = val.parse::<u64>() {
+                self.embedding_delay_ms = v;
+            }
+        }
+        if let Ok(val) = std::env::var("MOCK_OPENAI_EMBEDDING_DELAY_PER_INPUT_MS") {
+            if let Ok(v) = val.parse::<u64>() {
+                self.embedding_delay_per_input_ms = v;
+            }
+        }
+        if let Ok(val) = std::env::var("MOCK_OPENAI_MAX_MESSAGES") {
+            if let Ok(v) = val.parse::<usize>() {
+                self.max_messages = v;
+            }
+        }
+        if let Ok(val) = std::env::var("MOCK_OPENAI_MAX_RESPONSE_BYTES") {
+            if let Ok(v) = val.parse::<usize>() {
+                self.max_response_bytes = v;
+            }
+        }
+        if let Ok(val) = std::env::var("MOCK_OPENAI_MAX_FILE_BYTES") {
+            if let Ok(v) = val.parse::<usize>() {
+                self.max_file_bytes = v;
+            }
+        }
+        if let Some(v) = env_bool("MOCK_OPENAI_LIST_PAGINATION") {
+            self.list_pagination = v;
+        }
+        if let Some(v) = env_bool("MOCK_OPENAI_MODELS_PAGINATE") {
+            self.models_paginate = v;
+        }
+        if let Ok(val) = std::env::var("MOCK_OPENAI_MODEL_ECHO") {
+            if let Ok(v) = ModelEcho::from_str(&val, true) {
+                self.model_echo = v;
+            }
+        }
+        if let Ok(val) = std::env::var("MOCK_OPENAI_MODEL_REPLICAS") {
+            if let Ok(v) = val.parse::<usize>() {
+                self.model_replicas = v;
+            }
+        }
+        if let Ok(val) = std::env::var("MOCK_OPENAI_MAX_CONTEXT_TOKENS") {
+            if let Ok(v) = val.parse::<usize>() {
+                self.max_context_tokens = v;
+            }
+        }
+        if let Ok(val) = std::env::var("MOCK_OPENAI_CONTEXT_OVERFLOW") {
+            if let Ok(v) = ContextOverflow::from_str(&val, true) {
+                self.context_overflow = v;
+            }
+        }
+        if let Some(v) = env_bool("MOCK_OPENAI_STRICT_SCHEMA") {
+            self.strict_schema = v;
+        }
+        if let Some(v) = env_bool("MOCK_OPENAI_AZURE_COMPAT") {
+            self.azure_compat = v;
+        }
+        if let Some(v) = env_bool("MOCK_OPENAI_DEBUG") {
+            self.debug = v;
+        }
+        if let Ok(val) = std::env::var("MOCK_OPENAI_ADMIN_TOKEN") {
+            self.admin_token = Some(val);
+        }
+        if let Ok(val) = std::env::var("MOCK_OPENAI_API_KEY") {
+            self.api_key = Some(val);
+        }
+        if let Some(v) = env_bool("MOCK_OPENAI_VERBOSE") {
+            self.verbose = v;
+        }
+        if let Some(v) = env_bool("MOCK_OPENAI_COMPRESS") {
+            self.compress = v;
+        }
+        if let Ok(val) = std::env::var("MOCK_OPENAI_CREATED_OFFSET_SECS") {
+            if let Ok(v) = val.parse::<i64>() {
+                self.created_offset_secs = v;
+            }
+        }
+        if let Ok(val) = std::env::var("MOCK_OPENAI_TIMESTAMP_PRECISION") {
+            if let Ok(v) = TimestampPrecision::from_str(&val, true) {
+                self.timestamp_precision = v;
+            }
+        }
+        if let Some(v) = env_bool("MOCK_OPENAI_FORCE_CONTENT_LENGTH") {
+            self.force_content_length = v;
+        }
+        if let Some(v) = env_bool("MOCK_OPENAI_CONNECTION_CLOSE") {
+            self.connection_close = v;
+        }
+        if let Ok(val) = std::env::var("MOCK_OPENAI_COMPRESSION_LEVEL") {
+            if let Ok(v) = val.parse::<u32>() {
+                if v <= 11 {
+                    self.compression_level = v;
+                }
+            }
+        }
+        if let Ok(val) = std::env::var("MOCK_OPENAI_METRICS_LATENCY_BUCKETS") {
+            if let Ok(v) = parse_latency_buckets(&val) {
+                self.metrics_latency_buckets = v;
+            }
+        }
+        if let Ok(val) = std::env::var("MOCK_OPENAI_ORGANIZATION") {
+            self.organization = val;
+        }
+        if let Ok(val) = std::env::var("MOCK_OPENAI_CANNED_CHAT_RESPONSE") {
+            self.canned_chat_response = Some(PathBuf::from(val));
+        }
         if let Ok(val) = std::env::var("MOCK_OPENAI_TLS_CERT") {
             self.tls_cert = Some(PathBuf::from(val));
         }
@@ -104,4 +1021,133 @@ impl Args {
 
         Ok(())
     }
+
+    /// Validate that the token count distribution and per-token prefill
+    /// latency are usable: each must be finite and non-negative
+    pub fn validate_distribution(&self) -> Result<(), String> {
+        for (flag, value) in [
+            ("--token-mean", self.token_mean),
+            ("--token-stddev", self.token_stddev),
+            ("--prefill-ms-per-token", self.prefill_ms_per_token),
+        ] {
+            if !value.is_finite() || value < 0.0 {
+                return Err(format!(
+                    "{} must be a finite, non-negative number, got {}",
+                    flag, value
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_response_header() {
+        let (name, value) = parse_response_header("X-Upstream: mock").unwrap();
+        assert_eq!(name.as_str(), "x-upstream");
+        assert_eq!(value, "mock");
+
+        assert!(parse_response_header("no-colon").is_err());
+        assert!(parse_response_header("bad name: value").is_err());
+        assert!(parse_response_header("x-ok: bad\nvalue").is_err());
+    }
+
+    #[test]
+    fn test_parse_model_alias() {
+        assert_eq!(
+            parse_model_alias("gpt-4 = gpt-4-mock").unwrap(),
+            ("gpt-4".to_string(), "gpt-4-mock".to_string())
+        );
+        assert!(parse_model_alias("gpt-4").is_err());
+        assert!(parse_model_alias("=gpt-4-mock").is_err());
+    }
+
+    #[test]
+    fn test_parse_model_metadata() {
+        let model = parse_model_metadata("gpt-4o-mock=128000,16384,0.0025,0.01").unwrap();
+        assert_eq!(model.id, "gpt-4o-mock");
+        assert_eq!(model.context_window, Some(128000));
+        assert_eq!(model.max_output_tokens, Some(16384));
+        assert_eq!(model.pricing.unwrap().output_per_1k, 0.01);
+
+        assert_eq!(model.chunk_delay_ms, None);
+        let paced = parse_model_metadata("slow-mock=8192,4096,0.03,0.06,120").unwrap();
+        assert_eq!(paced.chunk_delay_ms, Some(120));
+        assert!(parse_model_metadata("m=8192,4096,0.03,0.06,fast").is_err());
+        let flaky = parse_model_metadata("flaky-mock=8192,4096,0.03,0.06,,0.25").unwrap();
+        assert_eq!(flaky.chunk_delay_ms, None);
+        assert_eq!(flaky.error_rate, 0.25);
+        assert!(parse_model_metadata("m=8192,4096,0.03,0.06,,1.5").is_err());
+
+        assert!(parse_model_metadata("no-spec").is_err());
+        assert!(parse_model_metadata("m=8192,4096,0.03").is_err());
+        assert!(parse_model_metadata("m=4096,8192,0.03,0.06").is_err());
+        assert!(parse_model_metadata("m=8192,4096,-1,0.06").is_err());
+    }
+
+    #[test]
+    fn test_validate_distribution() {
+        let args = Args::parse_from(["mock-openai", "--token-stddev", "0"]);
+        assert!(args.validate_distribution().is_ok());
+
+        for (flag, value) in [
+            ("--token-stddev", "-5"),
+            ("--token-mean", "NaN"),
+            ("--token-mean", "-1"),
+            ("--token-stddev", "inf"),
+            ("--prefill-ms-per-token", "-0.5"),
+            ("--prefill-ms-per-token", "NaN"),
+        ] {
+            let args = Args::parse_from(["mock-openai", flag, value]);
+            let err = args.validate_distribution().unwrap_err();
+            assert!(err.starts_with(flag), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_parse_rate() {
+        assert_eq!(parse_rate("0.25").unwrap(), 0.25);
+        assert_eq!(parse_rate("1").unwrap(), 1.0);
+        for bad in ["NaN", "inf", "-inf", "1.5", "-0.1", "often"] {
+            assert!(parse_rate(bad).is_err(), "{}", bad);
+        }
+        for flag in [
+            "--content-filter-rate",
+            "--corrupt-response-rate",
+            "--empty-choices-rate",
+        ] {
+            assert!(Args::try_parse_from(["mock-openai", flag, "NaN"]).is_err());
+        }
+    }
+
+    #[test]
+    fn test_parse_done_sentinel() {
+        assert_eq!(parse_done_sentinel("[END]").unwrap(), "[END]");
+        assert!(parse_done_sentinel("").is_err());
+        assert!(parse_done_sentinel("a\nb").is_err());
+        let args = Args::parse_from(["mock-openai"]);
+        assert_eq!(args.done_sentinel, DEFAULT_DONE_SENTINEL);
+    }
+
+    #[test]
+    fn test_parse_verbosity_factors() {
+        let factors = parse_verbosity_factors("0.5, 1, 3").unwrap();
+        assert_eq!(factors.low, 0.5);
+        assert_eq!(factors.high, 3.0);
+        assert!(parse_verbosity_factors("1,2").is_err());
+        assert!(parse_verbosity_factors("1,2,-3").is_err());
+    }
+
+    #[test]
+    fn test_parse_service_tier_factors() {
+        let factors = parse_service_tier_factors("1, 4.5").unwrap();
+        assert_eq!(factors.default, 1.0);
+        assert_eq!(factors.flex, 4.5);
+        assert!(parse_service_tier_factors("1").is_err());
+        assert!(parse_service_tier_factors("1,0").is_err());
+    }
 }
@@ -0,0 +1,206 @@
+//! In-memory batch jobs backing the /v1/batches lifecycle stub
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use uuid::Uuid;
+
+/// Requests a batch answers when its input file is unknown
+pub const SYNTHETIC_BATCH_REQUESTS: usize = 3;
+
+/// Endpoints a batch may target
+pub const BATCH_ENDPOINTS: [&str; 2] = ["/v1/chat/completions", "/v1/completions"];
+
+/// POST /v1/batches request body
+#[derive(Debug, Deserialize)]
+pub struct BatchCreateRequest {
+    pub input_file_id: String,
+    pub endpoint: String,
+    #[serde(default = "default_completion_window")]
+    pub completion_window: String,
+    #[serde(default)]
+    pub metadata: Option<serde_json::Value>,
+}
+
+fn default_completion_window() -> String {
+    "24h".to_string()
+}
+
+/// Batch lifecycle; every poll moves a batch one step towards `Completed`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchStatus {
+    Validating,
+    InProgress,
+    Finalizing,
+    Completed,
+}
+
+impl BatchStatus {
+    fn next(self) -> Self {
+        match self {
+            BatchStatus::Validating => BatchStatus::InProgress,
+            BatchStatus::InProgress => BatchStatus::Finalizing,
+            BatchStatus::Finalizing | BatchStatus::Completed => BatchStatus::Completed,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BatchRequestCounts {
+    pub total: usize,
+    pub completed: usize,
+    pub failed: usize,
+}
+
+/// Batch object as returned by /v1/batches
+#[derive(Debug, Clone, Serialize)]
+pub struct Batch {
+    pub id: String,
+    pub object: &'static str,
+    pub endpoint: String,
+    pub input_file_id: String,
+    pub completion_window: String,
+    pub status: BatchStatus,
+    pub output_file_id: Option<String>,
+    pub error_file_id: Option<String>,
+    pub created_at: i64,
+    pub completed_at: Option<i64>,
+    pub request_counts: BatchRequestCounts,
+    pub metadata: Option<serde_json::Value>,
+    /// `custom_id` of every request in the input file, in order
+    #[serde(skip)]
+    pub custom_ids: Vec<String>,
+}
+
+/// `custom_id`s of the requests in a batch input file (JSONL); lines without
+/// one are numbered `request-N`. Without an input file the batch answers
+/// `SYNTHETIC_BATCH_REQUESTS` numbered requests.
+pub fn batch_custom_ids(input: Option<&[u8]>) -> Vec<String> {
+    let Some(input) = input else {
+        return (1..=SYNTHETIC_BATCH_REQUESTS)
+            .map(|i| format!("request-{}", i))
+            .collect();
+    };
+    String::from_utf8_lossy(input)
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .enumerate()
+        .map(|(i, line)| {
+            serde_json::from_str::<serde_json::Value>(line)
+                .ok()
+                .and_then(|v| v["custom_id"].as_str().map(str::to_string))
+                .unwrap_or_else(|| format!("request-{}", i + 1))
+        })
+        .collect()
+}
+
+/// Batches keyed by id
+#[derive(Default)]
+pub struct BatchStore {
+    batches: Mutex<HashMap<String, Batch>>,
+}
+
+impl BatchStore {
+    /// Register a new batch in the `validating` state
+    pub fn create(
+        &self,
+        req: BatchCreateRequest,
+        custom_ids: Vec<String>,
+        created_at: i64,
+    ) -> Batch {
+        let batch = Batch {
+            id: format!("batch_{}", Uuid::new_v4().simple()),
+            object: "batch",
+            endpoint: req.endpoint,
+            input_file_id: req.input_file_id,
+            completion_window: req.completion_window,
+            status: BatchStatus::Validating,
+            output_file_id: None,
+            error_file_id: None,
+            created_at,
+            completed_at: None,
+            request_counts: BatchRequestCounts {
+                total: custom_ids.len(),
+                completed: 0,
+                failed: 0,
+            },
+            metadata: req.metadata,
+            custom_ids,
+        };
+        self.batches
+            .lock()
+            .unwrap()
+            .insert(batch.id.clone(), batch.clone());
+        batch
+    }
+
+    /// Advance a batch one lifecycle step and return its new state. On reaching
+    /// `completed` the output file is stored by `output`, which returns its id.
+    pub fn poll(&self, id: &str, now: i64, output: impl FnOnce(&Batch) -> String) -> Option<Batch> {
+        let mut batches = self.batches.lock().unwrap();
+        let batch = batches.get_mut(id)?;
+        if batch.status == BatchStatus::Completed {
+            return Some(batch.clone());
+        }
+        batch.status = batch.status.next();
+        if batch.status == BatchStatus::Completed {
+            batch.output_file_id = Some(output(batch));
+            batch.completed_at = Some(now);
+            batch.request_counts.completed = batch.request_counts.total;
+        }
+        Some(batch.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_batch_status_advances_to_completed() {
+        let store = BatchStore::default();
+        let batch = store.create(
+            BatchCreateRequest {
+                input_file_id: "file-in".to_string(),
+                endpoint: BATCH_ENDPOINTS[0].to_string(),
+                completion_window: default_completion_window(),
+                metadata: None,
+            },
+            batch_custom_ids(None),
+            0,
+        );
+        assert_eq!(batch.status, BatchStatus::Validating);
+
+        let statuses: Vec<_> = (0..4)
+            .map(|_| {
+                store
+                    .poll(&batch.id, 1, |_| "file-out".to_string())
+                    .unwrap()
+            })
+            .map(|b| b.status)
+            .collect();
+        assert_eq!(
+            statuses,
+            [
+                BatchStatus::InProgress,
+                BatchStatus::Finalizing,
+                BatchStatus::Completed,
+                BatchStatus::Completed
+            ]
+        );
+
+        let done = store.poll(&batch.id, 2, |_| unreachable!()).unwrap();
+        assert_eq!(done.completed_at, Some(1));
+        assert_eq!(done.output_file_id.as_deref(), Some("file-out"));
+        assert_eq!(done.request_counts.completed, SYNTHETIC_BATCH_REQUESTS);
+        assert!(store.poll("batch_missing", 0, |_| String::new()).is_none());
+    }
+
+    #[test]
+    fn test_batch_custom_ids() {
+        let input = b"{\"custom_id\": \"a\", \"body\": {}}\n\n{\"body\": {}}\n";
+        assert_eq!(batch_custom_ids(Some(input)), ["a", "request-2"]);
+        assert_eq!(batch_custom_ids(None).len(), SYNTHETIC_BATCH_REQUESTS);
+    }
+}
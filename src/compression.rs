@@ -0,0 +1,131 @@
+//! Response compression: `Accept-Encoding` negotiation and encoders
+
+use std::io::{self, Write};
+
+/// Highest accepted `--compression-level` (brotli's maximum quality)
+pub const MAX_COMPRESSION_LEVEL: u32 = 11;
+
+/// Supported content encodings, in server preference order
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Brotli,
+    Zstd,
+    Gzip,
+    Deflate,
+}
+
+impl Encoding {
+    const PREFERENCE: [Encoding; 4] = [
+        Encoding::Brotli,
+        Encoding::Zstd,
+        Encoding::Gzip,
+        Encoding::Deflate,
+    ];
+
+    /// Token used in `Accept-Encoding` / `Content-Encoding`
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Zstd => "zstd",
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+        }
+    }
+}
+
+/// Pick an encoding from an `Accept-Encoding` header value.
+///
+/// Encodings with the highest q-value win; ties are broken by server
+/// preference (br, zstd, gzip, deflate). `*` matches any encoding not listed
+/// explicitly. Returns `None` when nothing acceptable is supported.
+pub fn negotiate(accept_encoding: &str) -> Option<Encoding> {
+    let mut entries: Vec<(&str, f32)> = Vec::new();
+    for part in accept_encoding.split(',') {
+        let mut params = part.split(';');
+        let name = params.next().unwrap_or("").trim();
+        if name.is_empty() {
+            continue;
+        }
+        let q = params
+            .filter_map(|p| p.trim().strip_prefix("q="))
+            .find_map(|q| q.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+        entries.push((name, q));
+    }
+
+    let q_for = |encoding: Encoding| {
+        entries
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(encoding.as_str()))
+            .or_else(|| entries.iter().find(|(name, _)| *name == "*"))
+            .map(|(_, q)| *q)
+            .unwrap_or(0.0)
+    };
+
+    let mut best: Option<(Encoding, f32)> = None;
+    for encoding in Encoding::PREFERENCE {
+        let q = q_for(encoding);
+        if q > 0.0 && !matches!(best, Some((_, best_q)) if q <= best_q) {
+            best = Some((encoding, q));
+        }
+    }
+    best.map(|(encoding, _)| encoding)
+}
+
+/// Compress `data` with `encoding` at `level` (0-11, clamped to what each
+/// algorithm supports)
+pub fn compress(encoding: Encoding, level: u32, data: &[u8]) -> io::Result<Vec<u8>> {
+    let level = level.min(MAX_COMPRESSION_LEVEL);
+    match encoding {
+        Encoding::Brotli => {
+            let mut out = Vec::new();
+            {
+                let mut writer = brotli::CompressorWriter::new(&mut out, 4096, level, 22);
+                writer.write_all(data)?;
+            }
+            Ok(out)
+        }
+        Encoding::Zstd => zstd::bulk::compress(data, level as i32),
+        Encoding::Gzip => {
+            let mut encoder =
+                flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::new(level.min(9)));
+            encoder.write_all(data)?;
+            encoder.finish()
+        }
+        Encoding::Deflate => {
+            let mut encoder =
+                flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::new(level.min(9)));
+            encoder.write_all(data)?;
+            encoder.finish()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_negotiate() {
+        assert_eq!(negotiate("gzip, deflate, br"), Some(Encoding::Brotli));
+        assert_eq!(negotiate("gzip;q=1.0, br;q=0.5"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("zstd, gzip"), Some(Encoding::Zstd));
+        assert_eq!(negotiate("br;q=0, *"), Some(Encoding::Zstd));
+        assert_eq!(negotiate("identity"), None);
+        assert_eq!(negotiate(""), None);
+    }
+
+    #[test]
+    fn test_compress_roundtrip_gzip() {
+        use std::io::Read;
+
+        let data = b"hello hello hello hello hello".repeat(10);
+        let compressed = compress(Encoding::Gzip, 6, &data).unwrap();
+        assert!(compressed.len() < data.len());
+        let mut decoded = Vec::new();
+        flate2::read::GzDecoder::new(&compressed[..])
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, data);
+    }
+}
@@ -1,372 +1,5273 @@
 //! HTTP endpoint handlers for the mock OpenAI API
 
+use crate::batches::{batch_custom_ids, Batch, BatchCreateRequest, BATCH_ENDPOINTS};
+use crate::files::{multipart_boundary, parse_multipart, FileRecord};
+use crate::prompt_cache::hash_prompt;
+use crate::tools::sample_tool_calls;
 use crate::types::*;
 use crate::utils::*;
-use actix_web::{web, Error, HttpResponse, Responder};
-use bytes::Bytes;
+use crate::validation::*;
+use actix_web::http::StatusCode;
+use actix_web::{web, Error, HttpRequest, HttpResponse, Responder};
+use bytes::{Bytes, BytesMut};
 use futures::stream::{unfold, StreamExt};
-use rand::{rngs::StdRng, SeedableRng};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::VecDeque;
+use std::hash::{Hash, Hasher};
 use std::pin::Pin;
 use std::sync::atomic::Ordering;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use uuid::Uuid;
 
 // Static string constants to avoid repeated allocations
 const FINISH_REASON_STOP: &str = "stop";
+const FINISH_REASON_TOOL_CALLS: &str = "tool_calls";
+const FINISH_REASON_CONTENT_FILTER: &str = "content_filter";
+const FINISH_REASON_LENGTH: &str = "length";
+
+/// Embedding length when the request does not set `dimensions`
+const DEFAULT_EMBEDDING_DIMENSIONS: usize = 128;
 const ROLE_ASSISTANT: &str = "assistant";
 const OBJECT_CHAT_COMPLETION: &str = "chat.completion";
 const OBJECT_TEXT_COMPLETION: &str = "text.completion";
-const OBJECT_MODEL: &str = "model";
-const OWNED_BY: &str = "mock-openai";
+const REFUSAL_MESSAGE: &str = "I can't help with that.";
+
+/// `frequency_penalty` from which repeated words are dropped from the content
+const HIGH_FREQUENCY_PENALTY: f64 = 1.0;
 
-/// GET /health
-pub async fn health_handler() -> impl Responder {
-    HttpResponse::Ok().json(serde_json::json!({ "status": "ok" }))
+/// Sample how many completion tokens to produce: the configured normal
+/// distribution scaled by `scale`, clamped to `--token-min`/`--token-max` and
+/// capped by the request's `max_tokens`. `--token-count-jitter` applies a further
+/// multiplier drawn from the thread RNG, so it varies even for seeded requests
+fn sample_completion_tokens<R: Rng>(
+    state: &AppState,
+    rng: &mut R,
+    scale: f64,
+    max_tokens: Option<usize>,
+) -> usize {
+    let mut sampled = sample_normal_f64(rng, state.token_mean * scale, state.token_stddev * scale);
+    if state.token_count_jitter > 0.0 {
+        let jitter = state.token_count_jitter;
+        sampled *= 1.0 + rand::thread_rng().gen_range(-jitter..=jitter);
+    }
+    let tokens = clamp_token_count(sampled, state.token_min, state.token_max);
+    match max_tokens {
+        Some(max_tokens) => tokens.min(max_tokens),
+        None => tokens,
+    }
 }
 
-/// GET /v1/models
-pub async fn models_list_handler() -> impl Responder {
-    let models = vec![ModelInfo {
-        id: "gpt-4-mock".into(),
-        object: OBJECT_MODEL.into(),
-        owned_by: OWNED_BY.into(),
-    }];
-    HttpResponse::Ok().json(ModelsListResponse { data: models })
+/// RNG for one request: seeded when the request carries a `seed` so the
+/// same request reproduces the same content, otherwise random
+fn request_rng(seed: Option<u64>) -> StdRng {
+    match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_rng(rand::thread_rng()).expect("thread_rng never fails"),
+    }
 }
 
-/// GET /v1/models/{id}
-pub async fn model_get_handler(path: web::Path<String>) -> impl Responder {
-    let id = path.into_inner();
-    let known = ["gpt-4-mock"];
-    if known.contains(&id.as_str()) {
-        HttpResponse::Ok().json(ModelInfo {
-            id,
-            object: OBJECT_MODEL.into(),
-            owned_by: OWNED_BY.into(),
-        })
+/// `system_fingerprint` for a seeded request: a hash of the seed, model and
+/// the sampling config, so it changes whenever the output distribution does
+fn system_fingerprint(state: &AppState, seed: u64, model: &str) -> String {
+    let mut hasher = DefaultHasher::new();
+    seed.hash(&mut hasher);
+    model.hash(&mut hasher);
+    state.token_mean.to_bits().hash(&mut hasher);
+    state.token_stddev.to_bits().hash(&mut hasher);
+    format!("fp_{:016x}", hasher.finish())
+}
+
+/// Roll whether this response trips the simulated content filter
+/// (`--content-filter-rate`)
+fn sample_content_filter<R: Rng>(state: &AppState, rng: &mut R) -> bool {
+    state.content_filter_rate > 0.0 && rng.gen_bool(state.content_filter_rate.min(1.0))
+}
+
+/// Roll whether this chat response comes back without choices
+/// (`--empty-choices-rate`)
+fn sample_empty_choices<R: Rng>(state: &AppState, rng: &mut R) -> bool {
+    state.empty_choices_rate > 0.0 && rng.gen_bool(state.empty_choices_rate.min(1.0))
+}
+
+/// Pick the article a response for `tokens` completion tokens is cut from,
+/// per `--article-selection`
+fn pick_article<R: Rng>(state: &AppState, tokens: usize, rng: &mut R) -> Arc<String> {
+    match state.article_selection {
+        ArticleSelection::Uniform => choose_article(&state.articles, &state.empty_pool_text, rng),
+        ArticleSelection::Fit => {
+            choose_article_fitting(&state.articles, &state.empty_pool_text, tokens, rng)
+        }
+    }
+}
+
+/// Slice the response content out of `article`: borrowed from the shared
+/// article by default, or copied into owned Strings with `--copy-articles`
+fn article_content<'a>(state: &AppState, article: &'a Arc<String>, tokens: usize) -> Cow<'a, str> {
+    let preserve = state.preserve_whitespace;
+    if state.copy_articles {
+        let owned = String::clone(article);
+        Cow::Owned(slice_text_by_tokens(&owned, tokens, preserve).to_owned())
     } else {
-        HttpResponse::NotFound().json(serde_json::json!({ "error": "model_not_found" }))
+        Cow::Borrowed(slice_text_by_tokens(article, tokens, preserve))
     }
 }
 
-/// POST /v1/completions
-pub async fn completions_handler(
-    state: web::Data<AppState>,
-    req: web::Json<CompletionsRequest>,
-) -> Result<HttpResponse, Error> {
-    let req = req.into_inner();
+/// Whether generated content is rewritten by [`shape_content`]
+fn reshapes_content(state: &AppState, penalty: Option<f64>) -> bool {
+    penalty.unwrap_or(0.0) >= HIGH_FREQUENCY_PENALTY || state.inject_control_chars
+}
 
-    let mut rng = rand::thread_rng();
-    let mut sampled =
-        sample_normal_f64(&mut rng, state.token_mean, state.token_stddev).round() as isize;
-    if sampled < 1 {
-        sampled = 1;
+/// Rewrite generated content: a high `frequency_penalty` is approximated by
+/// dropping repeated words, then `--inject-control-chars` applies
+fn shape_content<'a>(
+    state: &AppState,
+    content: Cow<'a, str>,
+    penalty: Option<f64>,
+) -> Cow<'a, str> {
+    let mut content = content;
+    if penalty.unwrap_or(0.0) >= HIGH_FREQUENCY_PENALTY {
+        content = Cow::Owned(dedupe_repeated_words(&content));
     }
-    let mut completion_tokens = sampled as usize;
-    if let Some(max_tokens) = req.max_tokens {
-        if completion_tokens > max_tokens {
-            completion_tokens = max_tokens;
-        }
+    if state.inject_control_chars {
+        content = Cow::Owned(inject_control_chars(&content));
     }
+    content
+}
 
-    let article = choose_article(&state.articles, &mut rng);
-    let content = slice_text_by_tokens(&article, completion_tokens);
-
-    // Recompute actual tokens based on output produced
-    let actual_completion_tokens = chars_to_tokens(content.chars().count());
+/// [`shape_content`] for a streamed response: the rewritten content becomes
+/// the article to stream, with its token count
+fn shaped_article(
+    state: &AppState,
+    article: Arc<String>,
+    tokens: usize,
+    penalty: Option<f64>,
+) -> (Arc<String>, usize) {
+    if !reshapes_content(state, penalty) {
+        return (article, tokens);
+    }
+    let content = shape_content(state, article_content(state, &article, tokens), penalty);
+    let tokens = chars_to_tokens(content.chars().count());
+    (Arc::new(content.into_owned()), tokens)
+}
 
-    let created = SystemTime::now()
+/// `created` value for a response: now, shifted by `--created-offset-secs`
+fn created_timestamp(state: &AppState) -> i64 {
+    let now = SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .unwrap()
         .as_secs() as i64;
-    let prompt_tokens = req
-        .prompt
+    now.saturating_add(state.created_offset_secs)
+}
+
+/// `created` value of a completion: [`created_timestamp`] in the unit set by
+/// `--timestamp-precision`
+fn created_field(state: &AppState) -> i64 {
+    match state.timestamp_precision {
+        TimestampPrecision::Seconds => created_timestamp(state),
+        TimestampPrecision::Millis => {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_millis() as i64;
+            now.saturating_add(state.created_offset_secs.saturating_mul(1000))
+        }
+    }
+}
+
+/// Sleep out whatever is left of the `--min-response-ms` floor for a
+/// non-streaming response whose handling started at `started`
+async fn wait_for_min_response_time(state: &AppState, started: Instant) {
+    let floor = Duration::from_millis(state.min_response_ms);
+    if let Some(remaining) = floor.checked_sub(started.elapsed()) {
+        tokio::time::sleep(remaining).await;
+    }
+}
+
+/// Occupy the worker thread for `--cpu-burn-ms`
+fn burn_configured_cpu(state: &AppState) {
+    if state.cpu_burn_ms > 0 {
+        std::hint::black_box(burn_cpu(Duration::from_millis(state.cpu_burn_ms)));
+    }
+}
+
+/// Per-request latency override header, in milliseconds
+const DELAY_HEADER: &str = "x-mock-delay-ms";
+
+/// Delay requested through `X-Mock-Delay-Ms`; `None` when the header is absent
+/// or not a valid number of milliseconds, so the configured delay applies
+fn delay_override_ms(req: &HttpRequest) -> Option<u64> {
+    req.headers()
+        .get(DELAY_HEADER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Sleep for an `X-Mock-Delay-Ms` override before a non-streaming response
+async fn wait_for_delay_override(req: &HttpRequest) {
+    if let Some(ms) = delay_override_ms(req).filter(|ms| *ms > 0) {
+        tokio::time::sleep(Duration::from_millis(ms)).await;
+    }
+}
+
+/// Marks a chat or completions request as in flight for the `--capacity` latency model until
+/// dropped; streams hold it until their last event
+struct InFlight(web::Data<AppState>);
+
+impl InFlight {
+    /// Start counting this request; `None` when the latency model is off
+    fn enter(state: &web::Data<AppState>) -> Option<Self> {
+        (state.capacity > 0).then(|| {
+            state.in_flight.fetch_add(1, Ordering::Relaxed);
+            InFlight(state.clone())
+        })
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Scale `base_ms` like a saturating backend:
+/// `base * (1 + other_requests_in_flight / capacity)`
+fn queued_delay_ms(state: &AppState, base_ms: u64) -> u64 {
+    if state.capacity == 0 || base_ms == 0 {
+        return base_ms;
+    }
+    let others = state.in_flight.load(Ordering::Relaxed).saturating_sub(1);
+    (base_ms as f64 * (1.0 + others as f64 / state.capacity as f64)).round() as u64
+}
+
+/// Tier a request's `service_tier` is processed with; `auto` picks `default`
+fn resolve_service_tier(requested: &str) -> &'static str {
+    match requested {
+        "flex" => "flex",
+        _ => "default",
+    }
+}
+
+/// Scale a delay by a `--service-tier-factors` multiplier
+fn tier_delay_ms(delay_ms: u64, factor: f64) -> u64 {
+    (delay_ms as f64 * factor).round() as u64
+}
+
+/// Prefill latency of a prompt under `--prefill-ms-per-token`
+fn prefill_delay_ms(state: &AppState, prompt_tokens: usize) -> u64 {
+    (prompt_tokens as f64 * state.prefill_ms_per_token).round() as u64
+}
+
+/// Usage details for `--simulate-prompt-cache`: a configurable fraction of the
+/// prompt counts as cached when the same prompt was seen recently
+fn prompt_tokens_details<'a>(
+    state: &AppState,
+    prompt_parts: impl IntoIterator<Item = &'a str>,
+    prompt_tokens: usize,
+) -> Option<PromptTokensDetails> {
+    let cache = state.prompt_cache.as_ref()?;
+    let hit = cache.check_and_insert(hash_prompt(prompt_parts));
+    let cached_tokens = if hit {
+        (prompt_tokens as f64 * state.prompt_cache_fraction).floor() as usize
+    } else {
+        0
+    };
+    Some(PromptTokensDetails { cached_tokens })
+}
+
+/// OpenAI-style error body `{"error": {message, type, param, code}}`, typed
+/// `server_error` for 5xx statuses and `invalid_request_error` otherwise
+pub(crate) fn openai_error(
+    status: StatusCode,
+    message: impl Into<String>,
+    param: Option<&str>,
+    code: Option<&str>,
+) -> HttpResponse {
+    let error_type = if status.is_server_error() {
+        "server_error"
+    } else {
+        "invalid_request_error"
+    };
+    HttpResponse::build(status).json(serde_json::json!({
+        "error": {
+            "message": message.into(),
+            "type": error_type,
+            "param": param,
+            "code": code
+        }
+    }))
+}
+
+/// OpenAI-style 404 for a model that does not exist
+fn model_not_found(model: &str) -> HttpResponse {
+    openai_error(
+        StatusCode::NOT_FOUND,
+        format!(
+            "The model `{}` does not exist or you do not have access to it.",
+            model
+        ),
+        None,
+        Some("model_not_found"),
+    )
+}
+
+/// Prompt tokens to bill for a request against `--max-context-tokens`: all of
+/// them when it fits (or the limit is off), the part that fits beside
+/// `max_tokens` under `--context-overflow truncate`, and otherwise an
+/// OpenAI-style 400
+fn check_context_length(
+    state: &AppState,
+    prompt_tokens: usize,
+    max_tokens: Option<usize>,
+    param: &str,
+) -> Result<usize, HttpResponse> {
+    let limit = state.max_context_tokens;
+    if limit == 0 {
+        return Ok(prompt_tokens);
+    }
+    let completion_tokens = max_tokens.unwrap_or(0);
+    // `max_tokens` is client-controlled and unbounded
+    let requested = prompt_tokens.saturating_add(completion_tokens);
+    if requested <= limit {
+        return Ok(prompt_tokens);
+    }
+    // No prompt cut makes room when `max_tokens` alone fills the window
+    if state.context_overflow == ContextOverflow::Truncate && completion_tokens < limit {
+        return Ok(limit - completion_tokens);
+    }
+    Err(openai_error(
+        StatusCode::BAD_REQUEST,
+        format!(
+            "This model's maximum context length is {} tokens. However, you requested {} tokens ({} in the {}, {} in the completion). Please reduce the length of the {} or completion.",
+            limit, requested, prompt_tokens, param, completion_tokens, param
+        ),
+        Some(param),
+        Some("context_length_exceeded"),
+    ))
+}
+
+/// Prompt tokens charged per `image_url` message part (OpenAI's low-detail cost)
+const IMAGE_PROMPT_TOKENS: usize = 85;
+
+/// Approximate prompt tokens of a chat request from its message text, plus a
+/// fixed surcharge per image part
+fn chat_prompt_tokens(req: &ChatCompletionRequest) -> usize {
+    req.messages
         .as_ref()
-        .map(|p| chars_to_tokens(p.chars().count()))
-        .unwrap_or(0);
-    let usage = Usage {
-        prompt_tokens,
-        completion_tokens: actual_completion_tokens,
-        total_tokens: prompt_tokens + actual_completion_tokens,
+        .map(|msgs| {
+            let chars: usize = msgs.iter().map(|m| m.content.text().chars().count()).sum();
+            let images: usize = msgs.iter().map(|m| m.content.image_count()).sum();
+            chars_to_tokens(chars) + images * IMAGE_PROMPT_TOKENS
+        })
+        .unwrap_or(0)
+}
+
+/// OpenAI-style 400 listing every validation problem; with a single issue the
+/// top-level message and param match what the real API would report
+fn validation_failed(issues: &[ValidationIssue]) -> HttpResponse {
+    let (message, param) = match issues {
+        [issue] => (issue.message.clone(), Some(issue.param)),
+        _ => (
+            format!(
+                "{} validation errors: {}",
+                issues.len(),
+                issues
+                    .iter()
+                    .map(|i| format!("{}: {}", i.param, i.message))
+                    .collect::<Vec<_>>()
+                    .join("; ")
+            ),
+            None,
+        ),
     };
+    HttpResponse::BadRequest().json(serde_json::json!({
+        "error": {
+            "message": message,
+            "type": "invalid_request_error",
+            "param": param,
+            "code": "validation_error",
+            "errors": issues
+        }
+    }))
+}
 
-    let choice = CompletionChoice {
-        index: 0,
-        text: content,
-        finish_reason: FINISH_REASON_STOP,
+/// Whether the last user message contains the `--refusal-trigger` phrase
+/// (case-insensitive)
+fn refusal_triggered(state: &AppState, req: &ChatCompletionRequest) -> bool {
+    let Some(trigger) = state.refusal_trigger.as_deref() else {
+        return false;
     };
+    req.messages
+        .iter()
+        .flatten()
+        .rev()
+        .find(|m| m.role == "user")
+        .is_some_and(|m| {
+            m.content
+                .text()
+                .to_lowercase()
+                .contains(&trigger.to_lowercase())
+        })
+}
 
-    let resp = CompletionsResponse {
-        id: format!("cmpl-{}", Uuid::new_v4()),
-        object: OBJECT_TEXT_COMPLETION.to_string(),
-        created,
-        model: req.model,
-        usage,
-        choices: vec![choice],
+/// Whether the requested model matches any `--deny-model` pattern
+fn is_model_denied(state: &AppState, model: &str) -> bool {
+    state
+        .deny_models
+        .iter()
+        .any(|pattern| model_matches_pattern(model, pattern))
+}
+
+/// Model name to report for a request for `requested`: the `--model-alias`
+/// target unless `--model-echo requested`, with a random `--model-replicas`
+/// suffix
+fn response_model(state: &AppState, requested: String) -> String {
+    let model = if state.model_echo == ModelEcho::Requested {
+        requested
+    } else {
+        state
+            .model_aliases
+            .iter()
+            .find(|(alias, _)| *alias == requested)
+            .map_or(requested, |(_, target)| target.clone())
     };
+    if state.model_replicas == 0 {
+        return model;
+    }
+    let replica = rand::thread_rng().gen_range(1..=state.model_replicas);
+    format!("{}-replica-{}", model, replica)
+}
 
-    Ok(HttpResponse::Ok().json(resp))
+/// Served model a request for `requested` is answered by, aliases resolved
+fn served_model<'a>(state: &'a AppState, requested: &str) -> Option<&'a ModelInfo> {
+    let model = state
+        .model_aliases
+        .iter()
+        .find(|(alias, _)| alias == requested)
+        .map_or(requested, |(_, target)| target.as_str());
+    state.models.iter().find(|m| m.id == model)
 }
 
-/// POST /v1/embeddings
-pub async fn embeddings_handler(req: web::Json<EmbeddingRequest>) -> impl Responder {
-    let _req = req.into_inner();
-    let dimension = 128usize;
-    HttpResponse::Ok().json(generate_embedding(dimension))
+/// Per-chunk delay for a stream of `requested`: the `X-Mock-Delay-Ms` header,
+/// else the served model's `CHUNK_DELAY_MS` (aliases resolved), else
+/// `--response-delay-ms`
+fn stream_chunk_delay_ms(state: &AppState, http_req: &HttpRequest, requested: &str) -> u64 {
+    if let Some(ms) = delay_override_ms(http_req) {
+        return ms;
+    }
+    served_model(state, requested)
+        .and_then(|m| m.chunk_delay_ms)
+        .unwrap_or(state.response_delay_ms)
 }
 
-/// POST /v1/chat/completions - supports streaming SSE & non-streaming JSON
-pub async fn chat_completions_handler(
-    state: web::Data<AppState>,
-    req: web::Json<ChatCompletionRequest>,
+/// Roll the served model's `ERROR_RATE` (`--model-metadata`), answering a
+/// failed roll with an OpenAI-style 500
+fn sample_model_error(state: &AppState, requested: &str) -> Option<HttpResponse> {
+    let rate = served_model(state, requested).map_or(0.0, |m| m.error_rate);
+    if rate <= 0.0 || !rand::thread_rng().gen_bool(rate.min(1.0)) {
+        return None;
+    }
+    Some(openai_error(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "The server had an error while processing your request. Sorry about that!",
+        None,
+        None,
+    ))
+}
+
+/// JSON response for `resp`; while the body exceeds `--max-response-bytes`,
+/// `shorten` is asked to drop at least the excess bytes of generated content
+/// (it returns false once there is nothing left to drop)
+fn capped_json_response<T: Serialize>(
+    state: &AppState,
+    resp: &mut T,
+    mut shorten: impl FnMut(&mut T, usize) -> bool,
 ) -> Result<HttpResponse, Error> {
-    let req = req.into_inner();
-    if req.model.is_empty() {
-        return Ok(
-            HttpResponse::BadRequest().json(serde_json::json!({ "error": "model_required" }))
+    let mut body = serde_json::to_vec(resp)?;
+    while state.max_response_bytes > 0 && body.len() > state.max_response_bytes {
+        if !shorten(resp, body.len() - state.max_response_bytes) {
+            break;
+        }
+        body = serde_json::to_vec(resp)?;
+    }
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(body))
+}
+
+/// Register the API routes, leaving out every endpoint in `disabled` so
+/// requests to it get a 404
+pub fn configure_routes(cfg: &mut web::ServiceConfig, disabled: &[Endpoint]) {
+    let enabled = |endpoint| !disabled.contains(&endpoint);
+    if enabled(Endpoint::Health) {
+        cfg.route("/health", web::get().to(health_handler));
+    }
+    if enabled(Endpoint::Version) {
+        cfg.route("/version", web::get().to(version_handler));
+    }
+    if enabled(Endpoint::Metrics) {
+        cfg.route("/metrics", web::get().to(metrics_handler));
+    }
+    if enabled(Endpoint::Models) {
+        cfg.route("/v1/models", web::get().to(models_list_handler))
+            .route("/v1/models/{id}", web::get().to(model_get_handler));
+    }
+    if enabled(Endpoint::ChatCompletions) {
+        cfg.route(
+            "/v1/chat/completions",
+            web::post().to(chat_completions_handler),
         );
     }
+    if enabled(Endpoint::Completions) {
+        cfg.route("/v1/completions", web::post().to(completions_handler));
+    }
+    if enabled(Endpoint::Embeddings) {
+        cfg.route("/v1/embeddings", web::post().to(embeddings_handler));
+    }
+    if enabled(Endpoint::Batches) {
+        cfg.route("/v1/batches", web::post().to(batch_create_handler))
+            .route("/v1/batches/{id}", web::get().to(batch_get_handler));
+    }
+    if enabled(Endpoint::Files) {
+        cfg.route("/v1/files", web::post().to(file_upload_handler))
+            .route("/v1/files", web::get().to(files_list_handler))
+            .route("/v1/files/{id}", web::get().to(file_get_handler))
+            .route("/v1/files/{id}", web::delete().to(file_delete_handler))
+            .route(
+                "/v1/files/{id}/content",
+                web::get().to(file_content_handler),
+            );
+    }
+}
 
-    let stream_flag = req.stream.unwrap_or(false);
-    if !stream_flag {
-        // Non-streaming response
-        let mut rng = rand::thread_rng();
-        let mut sampled =
-            sample_normal_f64(&mut rng, state.token_mean, state.token_stddev).round() as isize;
-        if sampled < 1 {
-            sampled = 1;
-        }
-        let mut completion_tokens = sampled as usize;
-        if let Some(max_tokens) = req.max_tokens {
-            if completion_tokens > max_tokens {
-                completion_tokens = max_tokens;
-            }
-        }
+/// Fallback for paths without a route: an OpenAI-shaped 404
+pub async fn unknown_route_handler(req: HttpRequest) -> HttpResponse {
+    openai_error(
+        StatusCode::NOT_FOUND,
+        format!("Unknown request URL: {} {}", req.method(), req.path()),
+        None,
+        None,
+    )
+}
 
-        let article = choose_article(&state.articles, &mut rng);
-        let content = slice_text_by_tokens(&article, completion_tokens);
+/// GET /health - `ok`, unless an outage was set through POST /admin/health
+pub async fn health_handler(state: web::Data<AppState>) -> impl Responder {
+    match state.health_override.load(Ordering::Relaxed) {
+        0 => HttpResponse::Ok().json(serde_json::json!({ "status": "ok" })),
+        code => HttpResponse::build(
+            StatusCode::from_u16(code).unwrap_or(StatusCode::SERVICE_UNAVAILABLE),
+        )
+        .json(serde_json::json!({ "status": "unhealthy" })),
+    }
+}
 
-        let actual_completion_tokens = chars_to_tokens(content.chars().count());
-        let created = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs() as i64;
+/// POST /admin/health body; a missing or 200 `status` restores health
+#[derive(Debug, Deserialize)]
+pub struct HealthOverrideRequest {
+    pub status: Option<u16>,
+}
 
-        let prompt_tokens = req
-            .messages
-            .as_ref()
-            .map(|msgs| {
-                let chars: usize = msgs.iter().map(|m| m.content.chars().count()).sum();
-                chars_to_tokens(chars)
-            })
-            .unwrap_or(0);
+/// Whether `req` carries `Authorization: Bearer <--admin-token>`
+fn admin_authorized(state: &AppState, req: &HttpRequest) -> bool {
+    let Some(token) = state.admin_token.as_deref() else {
+        return false;
+    };
+    req.headers()
+        .get(actix_web::http::header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .is_some_and(|given| given == token)
+}
 
-        let usage = Usage {
-            prompt_tokens,
-            completion_tokens: actual_completion_tokens,
-            total_tokens: prompt_tokens + actual_completion_tokens,
-        };
+/// POST /admin/health (only with `--admin-token`) - set the status GET /health
+/// reports, to simulate an outage
+pub async fn admin_health_handler(
+    state: web::Data<AppState>,
+    http_req: HttpRequest,
+    req: web::Json<HealthOverrideRequest>,
+) -> HttpResponse {
+    if !admin_authorized(&state, &http_req) {
+        return openai_error(
+            StatusCode::UNAUTHORIZED,
+            "Invalid admin token.",
+            None,
+            Some("invalid_admin_token"),
+        );
+    }
+    let code = match req.status {
+        None | Some(200) => 0,
+        Some(code) if StatusCode::from_u16(code).is_ok() => code,
+        Some(code) => {
+            return validation_failed(&[ValidationIssue {
+                param: "status",
+                message: format!("status must be an HTTP status code, got {}", code),
+            }])
+        }
+    };
+    state.health_override.store(code, Ordering::Relaxed);
+    HttpResponse::Ok().json(serde_json::json!({ "status": if code == 0 { 200 } else { code } }))
+}
 
-        let choice = ChatChoice {
-            index: 0,
-            message: ChatMessage {
-                role: ROLE_ASSISTANT,
-                content,
-            },
-            finish_reason: FINISH_REASON_STOP,
-        };
+/// GET /metrics - Prometheus text exposition of the request metrics
+pub async fn metrics_handler(state: web::Data<AppState>) -> impl Responder {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(state.latency_histogram.render())
+}
 
-        let resp = ChatCompletionResponse {
-            id: format!("chatcmpl-{}", Uuid::new_v4()),
-            object: OBJECT_CHAT_COMPLETION.to_string(),
-            created,
-            model: req.model,
-            usage,
-            choices: vec![choice],
+/// GET /version - crate version, build commit and uptime
+pub async fn version_handler(state: web::Data<AppState>) -> impl Responder {
+    let started_at = state
+        .started_at
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    HttpResponse::Ok().json(VersionResponse {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        // Set MOCK_OPENAI_GIT_COMMIT at build time to embed the commit hash
+        commit: option_env!("MOCK_OPENAI_GIT_COMMIT")
+            .unwrap_or("unknown")
+            .to_string(),
+        started_at,
+        uptime_secs: state.started.elapsed().as_secs(),
+    })
+}
+
+/// OpenAI-style 404 for an unknown batch or file id
+fn no_such_object(kind: &str, id: &str) -> HttpResponse {
+    openai_error(
+        StatusCode::NOT_FOUND,
+        format!("No such {} object: {}", kind, id),
+        Some("id"),
+        None,
+    )
+}
+
+/// JSONL output of a completed batch: one synthetic successful response per
+/// request, shaped like the batch's target endpoint
+fn batch_output_jsonl(state: &AppState, batch: &Batch) -> String {
+    let mut rng = rand::thread_rng();
+    let created = created_field(state);
+    // The input file is not inspected, so answer as the first served model
+    let model = state.models.first().map_or("gpt-4-mock", |m| m.id.as_str());
+    let mut out = String::new();
+    for custom_id in &batch.custom_ids {
+        let tokens = sample_completion_tokens(state, &mut rng, 1.0, None);
+        let article = pick_article(state, tokens, &mut rng);
+        let content = slice_text_by_tokens(&article, tokens, state.preserve_whitespace);
+        let usage = serde_json::json!({
+            "prompt_tokens": 0,
+            "completion_tokens": chars_to_tokens(content.chars().count()),
+            "total_tokens": chars_to_tokens(content.chars().count())
+        });
+        let body = if batch.endpoint == "/v1/completions" {
+            serde_json::json!({
+                "id": format!("cmpl-{}", Uuid::new_v4()),
+                "object": OBJECT_TEXT_COMPLETION,
+                "created": created,
+                "model": model,
+                "choices": [{ "text": content, "index": 0, "finish_reason": FINISH_REASON_STOP }],
+                "usage": usage
+            })
+        } else {
+            serde_json::json!({
+                "id": format!("chatcmpl-{}", Uuid::new_v4()),
+                "object": OBJECT_CHAT_COMPLETION,
+                "created": created,
+                "model": model,
+                "choices": [{
+                    "index": 0,
+                    "message": { "role": ROLE_ASSISTANT, "content": content },
+                    "finish_reason": FINISH_REASON_STOP
+                }],
+                "usage": usage
+            })
         };
+        let line = serde_json::json!({
+            "id": format!("batch_req_{}", Uuid::new_v4().simple()),
+            "custom_id": custom_id,
+            "response": {
+                "status_code": 200,
+                "request_id": Uuid::new_v4().simple().to_string(),
+                "body": body
+            },
+            "error": null
+        });
+        out.push_str(&line.to_string());
+        out.push('\n');
+    }
+    out
+}
 
-        return Ok(HttpResponse::Ok().json(resp));
+/// POST /v1/batches - register a batch; it completes over subsequent polls
+pub async fn batch_create_handler(
+    state: web::Data<AppState>,
+    req: web::Json<BatchCreateRequest>,
+) -> HttpResponse {
+    let req = req.into_inner();
+    if !BATCH_ENDPOINTS.contains(&req.endpoint.as_str()) {
+        return openai_error(
+            StatusCode::BAD_REQUEST,
+            format!(
+                "Invalid value for 'endpoint': expected one of {}",
+                BATCH_ENDPOINTS.join(", ")
+            ),
+            Some("endpoint"),
+            None,
+        );
     }
+    let custom_ids = {
+        let files = state.files.lock().unwrap();
+        batch_custom_ids(files.get(&req.input_file_id).map(|f| &f.content[..]))
+    };
+    let batch = state
+        .batches
+        .create(req, custom_ids, created_timestamp(&state));
+    HttpResponse::Ok().json(batch)
+}
 
-    // Streaming mode (SSE)
-    // Sample total tokens to emit
-    let mut rng = StdRng::from_entropy();
-    let mut sampled =
-        sample_normal_f64(&mut rng, state.token_mean, state.token_stddev).round() as isize;
-    if sampled < 1 {
-        sampled = 1;
+/// GET /v1/batches/{id} - each poll advances the batch one status step
+pub async fn batch_get_handler(
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+) -> HttpResponse {
+    let id = path.into_inner();
+    let now = created_timestamp(&state);
+    let store_output = |batch: &Batch| {
+        let output = FileRecord::new(
+            format!("{}_output.jsonl", batch.id),
+            "batch_output".to_string(),
+            Bytes::from(batch_output_jsonl(&state, batch)),
+            now,
+        );
+        let id = output.id.clone();
+        state.files.lock().unwrap().insert(id.clone(), output);
+        id
+    };
+    match state.batches.poll(&id, now, store_output) {
+        Some(batch) => HttpResponse::Ok().json(batch),
+        None => no_such_object("Batch", &id),
     }
-    let mut total_tokens = sampled as usize;
-    if let Some(max_tokens) = req.max_tokens {
-        if total_tokens > max_tokens {
-            total_tokens = max_tokens;
+}
+
+/// OpenAI-style 400 for a malformed /v1/files upload
+fn invalid_upload(message: String, param: &str) -> HttpResponse {
+    openai_error(StatusCode::BAD_REQUEST, message, Some(param), None)
+}
+
+/// OpenAI-style 413 for an upload larger than `--max-file-bytes`
+fn file_too_large(state: &AppState) -> HttpResponse {
+    openai_error(
+        StatusCode::PAYLOAD_TOO_LARGE,
+        format!(
+            "File exceeds the maximum allowed size of {} bytes.",
+            state.max_file_bytes
+        ),
+        Some("file"),
+        Some("file_too_large"),
+    )
+}
+
+/// Room for multipart headers and the other form fields on top of the file
+const MULTIPART_OVERHEAD_BYTES: usize = 64 * 1024;
+
+/// POST /v1/files - multipart upload with `file` and `purpose` fields
+pub async fn file_upload_handler(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    mut payload: web::Payload,
+) -> Result<HttpResponse, Error> {
+    let Some(boundary) = req
+        .headers()
+        .get(actix_web::http::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(multipart_boundary)
+        .map(str::to_string)
+    else {
+        return Ok(invalid_upload(
+            "Expected a multipart/form-data request body.".to_string(),
+            "file",
+        ));
+    };
+
+    let mut body = bytes::BytesMut::new();
+    while let Some(chunk) = payload.next().await {
+        body.extend_from_slice(&chunk?);
+        if body.len()
+            > state
+                .max_file_bytes
+                .saturating_add(MULTIPART_OVERHEAD_BYTES)
+        {
+            return Ok(file_too_large(&state));
         }
     }
+    let fields = match parse_multipart(&body.freeze(), &boundary) {
+        Ok(fields) => fields,
+        Err(e) => {
+            return Ok(invalid_upload(
+                format!("Malformed multipart body: {}", e),
+                "file",
+            ))
+        }
+    };
 
-    let article_arc = choose_article(&state.articles, &mut rng);
-    let article_len_chars = article_arc.chars().count();
-    let chars_remaining = tokens_to_chars(total_tokens);
+    let Some(file) = fields.iter().find(|f| f.name == "file") else {
+        return Ok(invalid_upload(
+            "Missing required parameter: 'file'.".to_string(),
+            "file",
+        ));
+    };
+    let Some(purpose) = fields.iter().find(|f| f.name == "purpose") else {
+        return Ok(invalid_upload(
+            "Missing required parameter: 'purpose'.".to_string(),
+            "purpose",
+        ));
+    };
+    if file.data.len() > state.max_file_bytes {
+        return Ok(file_too_large(&state));
+    }
 
-    // We'll track position in chars (not bytes), because char boundaries matter
-    let initial_char_pos = 0usize;
-    let response_delay_ms = state.response_delay_ms;
+    let record = FileRecord::new(
+        file.filename.clone().unwrap_or_else(|| "file".to_string()),
+        String::from_utf8_lossy(&purpose.data).into_owned(),
+        file.data.clone(),
+        created_timestamp(&state),
+    );
+    state
+        .files
+        .lock()
+        .unwrap()
+        .insert(record.id.clone(), record.clone());
+    Ok(HttpResponse::Ok().json(record))
+}
 
-    // Get the sample stream and sample count (pre-computed at startup)
-    let stream_samples = state.stream_token_samples.clone();
-    let samples_len = stream_samples.len();
+/// GET /v1/files - every stored file, oldest first
+pub async fn files_list_handler(state: web::Data<AppState>) -> HttpResponse {
+    let mut files: Vec<FileRecord> = state.files.lock().unwrap().values().cloned().collect();
+    files.sort_by(|a, b| (a.created_at, &a.id).cmp(&(b.created_at, &b.id)));
+    HttpResponse::Ok().json(serde_json::json!({ "object": "list", "data": files }))
+}
 
-    // Get current index and increment for next request (lock-free)
-    let sample_start_idx = state.stream_samples_idx.fetch_add(1, Ordering::Relaxed);
+/// GET /v1/files/{id} - file metadata
+pub async fn file_get_handler(state: web::Data<AppState>, path: web::Path<String>) -> HttpResponse {
+    let id = path.into_inner();
+    match state.files.lock().unwrap().get(&id) {
+        Some(file) => HttpResponse::Ok().json(file),
+        None => no_such_object("File", &id),
+    }
+}
 
-    // A pinned, boxed stream of chunks (SSE events) which the HTTP response will stream
-    let s = unfold(
-        (
-            article_arc.clone(),
-            chars_remaining,
-            initial_char_pos,
-            sample_start_idx,
-            response_delay_ms,
-            false, // done_sent
-            stream_samples,
-            samples_len,
-        ),
-        move |(
-            article,
-            chars_remaining,
-            char_pos,
-            mut sample_idx,
-            response_delay_ms,
-            done_sent,
-            stream_samples,
-            samples_len,
-        )| async move {
-            // If all characters have been emitted already
-            if chars_remaining == 0 {
-                if done_sent {
-                    return None;
-                } else {
-                    let done_event = "data: [DONE]\n\n".to_string();
-                    return Some((
-                        Ok::<Bytes, actix_web::Error>(Bytes::from(done_event)),
-                        (
-                            article,
-                            0usize,
-                            char_pos,
-                            sample_idx,
-                            response_delay_ms,
-                            true,
-                            stream_samples,
-                            samples_len,
-                        ),
-                    ));
-                }
-            }
+/// GET /v1/files/{id}/content - raw file bytes (e.g. batch results)
+pub async fn file_content_handler(
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+) -> HttpResponse {
+    let id = path.into_inner();
+    let content = state
+        .files
+        .lock()
+        .unwrap()
+        .get(&id)
+        .map(|f| f.content.clone());
+    match content {
+        Some(content) => HttpResponse::Ok()
+            .content_type("application/octet-stream")
+            .body(content),
+        None => no_such_object("File", &id),
+    }
+}
 
-            // This eliminates the RNG call for every SSE event
-            let chunk_tokens = stream_samples[sample_idx % samples_len];
-            sample_idx += 1;
+/// DELETE /v1/files/{id}
+pub async fn file_delete_handler(
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+) -> HttpResponse {
+    let id = path.into_inner();
+    match state.files.lock().unwrap().remove(&id) {
+        Some(_) => HttpResponse::Ok().json(serde_json::json!({
+            "id": id,
+            "object": "file",
+            "deleted": true
+        })),
+        None => no_such_object("File", &id),
+    }
+}
 
-            let mut chunk_chars = tokens_to_chars(chunk_tokens);
-            if chunk_chars > chars_remaining {
-                chunk_chars = chars_remaining;
-            }
+/// GET /v1/models
+pub async fn models_list_handler(
+    state: web::Data<AppState>,
+    http_req: HttpRequest,
+) -> HttpResponse {
+    if !state.models_paginate {
+        // The query string is ignored, as the real endpoint does
+        return HttpResponse::Ok().json(ModelsListResponse::new(state.models.clone()));
+    }
+    match web::Query::<ModelsListQuery>::from_query(http_req.query_string()) {
+        Ok(query) => HttpResponse::Ok().json(ModelsListResponse::page(
+            &state.models,
+            query.limit,
+            query.after.as_deref(),
+        )),
+        Err(e) => openai_error(
+            StatusCode::BAD_REQUEST,
+            format!("Invalid query parameters: {}", e),
+            None,
+            None,
+        ),
+    }
+}
 
-            // Determine byte indices
-            let start_byte = char_pos_to_byte_idx(&article, char_pos);
-            let end_char_pos = std::cmp::min(article_len_chars, char_pos + chunk_chars);
-            let mut end_byte = char_pos_to_byte_idx(&article, end_char_pos);
+/// GET /v1/models/{id}
+pub async fn model_get_handler(
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let id = path.into_inner();
+    if let Some(model) = state.models.iter().find(|m| m.id == id) {
+        HttpResponse::Ok().json(model)
+    } else {
+        model_not_found(&id)
+    }
+}
 
-            // Avoid splitting words - try to find whitespace before end_byte
-            if end_byte < article.len() {
-                if let Some(rel) = article[..end_byte].rfind(' ') {
-                    // Only use the whitespace split if it advances the position
-                    if rel > start_byte {
-                        end_byte = rel;
-                    }
-                }
+/// POST /v1/completions
+pub async fn completions_handler(
+    state: web::Data<AppState>,
+    http_req: HttpRequest,
+    req: web::Json<CompletionsRequest>,
+) -> Result<HttpResponse, Error> {
+    let started = Instant::now();
+    let req = req.into_inner();
+    let mut issues = validate_completions_request(&req);
+    if state.strict_schema {
+        issues.extend(strict_completions_request_issues(&req));
+    }
+    if !issues.is_empty() {
+        return Ok(validation_failed(&issues));
+    }
+    if is_model_denied(&state, &req.model) {
+        return Ok(model_not_found(&req.model));
+    }
+    if let Some(resp) = sample_model_error(&state, &req.model) {
+        return Ok(resp);
+    }
+    let prompt_tokens = req
+        .prompt
+        .as_ref()
+        .map(|p| chars_to_tokens(p.chars().count()))
+        .unwrap_or(0);
+    let prompt_tokens = match check_context_length(&state, prompt_tokens, req.max_tokens, "prompt")
+    {
+        Ok(prompt_tokens) => prompt_tokens,
+        Err(resp) => return Ok(resp),
+    };
+    let in_flight = InFlight::enter(&state);
+    burn_configured_cpu(&state);
+    let thinking_delay_ms = queued_delay_ms(
+        &state,
+        state
+            .thinking_delay_ms
+            .saturating_add(prefill_delay_ms(&state, prompt_tokens)),
+    );
+
+    let mut rng = rand::thread_rng();
+    let completion_tokens = sample_completion_tokens(&state, &mut rng, 1.0, req.max_tokens);
+
+    let article = pick_article(&state, completion_tokens, &mut rng);
+    if req.stream.unwrap_or(false) {
+        let (article, completion_tokens) =
+            shaped_article(&state, article, completion_tokens, req.frequency_penalty);
+        let stream_state = SseStreamState {
+            usage_prompt_tokens: include_usage(req.stream_options.as_ref())
+                .then_some(prompt_tokens),
+            response_delay_ms: stream_chunk_delay_ms(&state, &http_req, &req.model),
+            thinking_delay_ms,
+            _in_flight: in_flight,
+            ..SseStreamState::new(StreamKind::Text, &state, article, completion_tokens)
+        };
+        return Ok(sse_response(stream_state));
+    }
+
+    // `best_of` samples more candidates; every one of them is billed, but only
+    // the `n` longest are returned
+    let n = req.n.unwrap_or(1);
+    let candidates: Vec<(Arc<String>, usize)> = std::iter::once((article, completion_tokens))
+        .chain((1..req.best_of.unwrap_or(n)).map(|_| {
+            let tokens = sample_completion_tokens(&state, &mut rng, 1.0, req.max_tokens);
+            (pick_article(&state, tokens, &mut rng), tokens)
+        }))
+        .collect();
+    let mut contents: Vec<_> = candidates
+        .iter()
+        .map(|(article, tokens)| {
+            shape_content(
+                &state,
+                article_content(&state, article, *tokens),
+                req.frequency_penalty,
+            )
+        })
+        .collect();
+
+    // Recompute actual tokens based on output produced
+    let actual_completion_tokens = contents
+        .iter()
+        .map(|content| chars_to_tokens(content.chars().count()))
+        .sum();
+    contents.sort_by_key(|content| std::cmp::Reverse(content.chars().count()));
+    contents.truncate(n);
+
+    let created = created_field(&state);
+    let usage = Usage {
+        prompt_tokens,
+        completion_tokens: actual_completion_tokens,
+        total_tokens: prompt_tokens + actual_completion_tokens,
+        prompt_tokens_details: prompt_tokens_details(&state, req.prompt.as_deref(), prompt_tokens),
+    };
+
+    let choices = contents
+        .into_iter()
+        .enumerate()
+        .map(|(index, text)| CompletionChoice {
+            index,
+            text,
+            finish_reason: FINISH_REASON_STOP,
+        })
+        .collect();
+
+    let mut resp = CompletionsResponse {
+        id: format!("cmpl-{}", Uuid::new_v4()),
+        object: OBJECT_TEXT_COMPLETION.to_string(),
+        created,
+        model: response_model(&state, req.model),
+        usage,
+        choices,
+    };
+
+    if thinking_delay_ms > 0 {
+        tokio::time::sleep(Duration::from_millis(thinking_delay_ms)).await;
+    }
+    wait_for_delay_override(&http_req).await;
+    wait_for_min_response_time(&state, started).await;
+    capped_json_response(&state, &mut resp, |resp, excess| {
+        let Some(choice) = resp.choices.iter_mut().max_by_key(|c| c.text.len()) else {
+            return false;
+        };
+        let before = chars_to_tokens(choice.text.chars().count());
+        if !truncate_cow_bytes(&mut choice.text, excess) {
+            return false;
+        }
+        choice.finish_reason = FINISH_REASON_LENGTH;
+        // Other `best_of` candidates stay billed
+        resp.usage.completion_tokens -= before - chars_to_tokens(choice.text.chars().count());
+        resp.usage.total_tokens = resp.usage.prompt_tokens + resp.usage.completion_tokens;
+        true
+    })
+}
+
+/// POST /v1/embeddings
+pub async fn embeddings_handler(
+    state: web::Data<AppState>,
+    req: web::Json<EmbeddingRequest>,
+) -> impl Responder {
+    let started = Instant::now();
+    let req = req.into_inner();
+    let issues = validate_embedding_request(&req, state.max_embedding_n);
+    if !issues.is_empty() {
+        return validation_failed(&issues);
+    }
+    let dimension = req
+        .dimensions
+        .map_or(DEFAULT_EMBEDDING_DIMENSIONS, |d| d as usize);
+    // A missing input still answers like a single one
+    let inputs = req.input.as_ref().map_or(1, EmbeddingInput::len);
+    let count = inputs * req.n.unwrap_or(1);
+    let mut resp = generate_embedding(dimension, count, state.embedding_quantize);
+    if state.list_pagination {
+        resp = resp.with_pagination();
+    }
+    // Latency grows with the batch, like a real embedding model
+    let delay_ms = state.embedding_delay_ms.saturating_add(
+        state
+            .embedding_delay_per_input_ms
+            .saturating_mul(inputs as u64),
+    );
+    if delay_ms > 0 {
+        tokio::time::sleep(Duration::from_millis(delay_ms)).await;
+    }
+    wait_for_min_response_time(&state, started).await;
+    HttpResponse::Ok().json(resp)
+}
+
+/// GET /debug/config (only with `--debug`) - the resolved startup configuration
+/// and the state changed at runtime since, e.g. through /admin/* endpoints
+pub async fn debug_config_handler(state: web::Data<AppState>) -> impl Responder {
+    let health_status = match state.health_override.load(Ordering::Relaxed) {
+        0 => 200,
+        code => code,
+    };
+    HttpResponse::Ok().json(serde_json::json!({
+        "config": state.config,
+        "runtime": {
+            "health_status": health_status,
+            "in_flight": state.in_flight.load(Ordering::Relaxed),
+            "completed_requests": state.completed_requests.load(Ordering::Relaxed),
+            "stream_samples_idx": state.stream_samples_idx.load(Ordering::Relaxed),
+        }
+    }))
+}
+
+/// POST /debug/echo-request (only with `--debug`) - echo back how the body was
+/// parsed as a chat request; absent fields show up as `null`
+pub async fn debug_echo_request_handler(req: web::Json<ChatCompletionRequest>) -> impl Responder {
+    HttpResponse::Ok().json(req.into_inner())
+}
+
+/// Event format of an SSE stream
+#[derive(Clone, Copy, PartialEq)]
+enum StreamKind {
+    /// /v1/chat/completions: `delta.content` chunks
+    Chat,
+    /// /v1/completions: `text` chunks
+    Text,
+}
+
+/// Per-request state threaded through a chat or legacy completions SSE stream
+struct SseStreamState {
+    kind: StreamKind,
+    /// Sent as an SSE `retry:` line before anything else (`--sse-retry-ms`)
+    retry_ms: Option<u64>,
+    /// Named in an `event:` line ahead of every data frame (`--sse-event-name`)
+    event_name: Option<Arc<str>>,
+    /// Payload of the closing data frame (`--done-sentinel`)
+    done_sentinel: Arc<str>,
+    article: Arc<String>,
+    chars_remaining: usize,
+    /// Byte offset of the next char to stream; tracked in bytes so chunks are
+    /// sliced without rescanning the article from its start
+    byte_pos: usize,
+    /// Byte offset where the streamed content ends
+    content_end: usize,
+    sample_idx: usize,
+    stream_samples: Arc<Vec<usize>>,
+    /// Content emitted as its own event before the article (`--reflect-prompt`)
+    prefix: Option<String>,
+    /// `delta.tool_calls` frames sent, one per chunk, after the content
+    tool_call_frames: VecDeque<String>,
+    /// Leading content chars still to be sent as `reasoning_content`
+    /// (`--reasoning-tokens`)
+    reasoning_chars: usize,
+    /// Pause once the reasoning phase is over (`--reasoning-gap-ms`)
+    reasoning_gap_ms: u64,
+    response_delay_ms: u64,
+    /// Content chars still sent without a chunk delay (`--stream-burst-tokens`)
+    burst_chars: usize,
+    delay_dist: StreamDelayDist,
+    delay_stddev_ms: f64,
+    /// Chunk by sampled token counts or by sentence (`--stream-granularity`)
+    granularity: StreamGranularity,
+    thinking_delay_ms: u64,
+    /// Interval of `: keep-alive` comments during the thinking delay
+    /// (`--stream-keepalive-ms`, 0 = off)
+    keepalive_ms: u64,
+    /// Keep-alive comments still allowed (`--stream-keepalive-max`, `None` =
+    /// unlimited)
+    keepalives_left: Option<usize>,
+    /// End of a thinking delay being filled with keep-alive comments
+    thinking_until: Option<Instant>,
+    stream_timestamps: bool,
+    /// Yield to the runtime before every event after the first
+    /// (`--stream-flush-each`)
+    flush_each: bool,
+    /// Write some frames in two pieces cut mid-codepoint (`--split-multibyte`)
+    split_multibyte: bool,
+    /// Sent as a final empty-delta frame before `[DONE]` (`content_filter`)
+    finish_reason: Option<&'static str>,
+    /// Prompt tokens for a closing usage frame (`stream_options.include_usage`)
+    usage_prompt_tokens: Option<usize>,
+    /// Content characters emitted so far, counted into the usage frame
+    emitted_chars: usize,
+    /// Server-wide token budget each content chunk waits for (`--global-tps`)
+    global_rate: Option<Arc<crate::throughput::GlobalTokenRate>>,
+    /// Attach logprobs with this many alternatives to every content chunk
+    /// (request `logprobs`/`top_logprobs`)
+    logprobs_top: Option<usize>,
+    /// Past this instant the stream is cut short (`--max-stream-duration-ms`)
+    deadline: Option<Instant>,
+    started: bool,
+    done_sent: bool,
+    /// End the stream before it exceeds this many bytes (0 = unlimited)
+    max_response_bytes: usize,
+    /// Reused for plain chat content frames so they don't allocate per chunk
+    frame_buf: BytesMut,
+    /// Keeps the request counted for `--capacity` while the stream is alive
+    _in_flight: Option<InFlight>,
+}
+
+impl SseStreamState {
+    /// Stream the text a non-streaming response for `total_tokens` would
+    /// contain, with the server-wide streaming settings
+    fn new(kind: StreamKind, state: &AppState, article: Arc<String>, total_tokens: usize) -> Self {
+        let article = if state.copy_articles {
+            Arc::new(String::clone(&article))
+        } else {
+            article
+        };
+        // Stream exactly the text the non-streaming response would contain;
+        // this also never asks for more characters than the article holds
+        let content = slice_text_by_tokens(&article, total_tokens, state.preserve_whitespace);
+        let content_start = content.as_ptr() as usize - article.as_ptr() as usize;
+        let content_end = content_start + content.len();
+        let chars_remaining = content.chars().count();
+
+        SseStreamState {
+            kind,
+            retry_ms: state.sse_retry_ms,
+            event_name: state.sse_event_name.clone(),
+            done_sentinel: Arc::clone(&state.done_sentinel),
+            article,
+            chars_remaining,
+            byte_pos: content_start,
+            content_end,
+            // Get current index and increment for next request (lock-free)
+            sample_idx: state.stream_samples_idx.fetch_add(1, Ordering::Relaxed),
+            // Pre-computed at startup
+            stream_samples: state.stream_token_samples.clone(),
+            prefix: None,
+            tool_call_frames: VecDeque::new(),
+            reasoning_chars: 0,
+            reasoning_gap_ms: 0,
+            response_delay_ms: state.response_delay_ms,
+            burst_chars: tokens_to_chars(state.stream_burst_tokens),
+            delay_dist: state.stream_delay_dist,
+            delay_stddev_ms: state.stream_delay_stddev_ms,
+            granularity: state.stream_granularity,
+            thinking_delay_ms: state.thinking_delay_ms,
+            keepalive_ms: state.stream_keepalive_ms,
+            keepalives_left: (state.stream_keepalive_max > 0).then_some(state.stream_keepalive_max),
+            thinking_until: None,
+            stream_timestamps: state.stream_timestamps,
+            flush_each: state.stream_flush_each,
+            split_multibyte: state.split_multibyte,
+            finish_reason: None,
+            usage_prompt_tokens: None,
+            emitted_chars: 0,
+            global_rate: state.global_tps.clone(),
+            max_response_bytes: state.max_response_bytes,
+            frame_buf: BytesMut::new(),
+            logprobs_top: None,
+            deadline: (state.max_stream_duration_ms > 0)
+                .then(|| Instant::now() + Duration::from_millis(state.max_stream_duration_ms)),
+            started: false,
+            done_sent: false,
+            _in_flight: None,
+        }
+    }
+
+    /// Send the first `tokens` of the content as reasoning, pausing `gap_ms`
+    /// before the answer that follows
+    fn with_reasoning(mut self, tokens: usize, gap_ms: u64) -> Self {
+        self.reasoning_chars = tokens_to_chars(tokens).min(self.chars_remaining);
+        if self.reasoning_chars > 0 {
+            self.reasoning_gap_ms = gap_ms;
+        }
+        self
+    }
+
+    /// Sleep for `duration`, but no longer than until the stream's deadline
+    async fn pause(&self, duration: Duration) {
+        let duration = match self.deadline {
+            Some(deadline) => duration.min(deadline.saturating_duration_since(Instant::now())),
+            None => duration,
+        };
+        tokio::time::sleep(duration).await;
+    }
+
+    /// Pause between chunks, drawn from `--stream-delay-dist`
+    async fn chunk_pause(&self) {
+        if self.response_delay_ms > 0 {
+            let delay_ms = sample_stream_delay_ms(
+                &mut rand::thread_rng(),
+                self.delay_dist,
+                self.response_delay_ms,
+                self.delay_stddev_ms,
+            );
+            self.pause(Duration::from_secs_f64(delay_ms / 1000.0)).await;
+        }
+    }
+
+    /// Format a content chunk as an SSE event, stamping it when enabled
+    fn content_event(&mut self, content: &str) -> Bytes {
+        let ts_ms = self.stream_timestamps.then(unix_millis);
+        if let (StreamKind::Chat, Some(top)) = (self.kind, self.logprobs_top) {
+            let logprobs = mock_logprobs(content, top, &mut rand::thread_rng());
+            return sse_event_with_logprobs(content, &logprobs, ts_ms).into();
+        }
+        match (self.kind, ts_ms) {
+            (StreamKind::Chat, Some(ts_ms)) => sse_event_with_timestamp(content, ts_ms).into(),
+            (StreamKind::Chat, None) => {
+                write_sse_event_from_content(&mut self.frame_buf, content);
+                self.frame_buf.split().freeze()
+            }
+            (StreamKind::Text, ts_ms) => sse_text_event(content, ts_ms).into(),
+        }
+    }
+}
+
+/// Whether a streaming request asked for a closing usage frame
+fn include_usage(stream_options: Option<&StreamOptions>) -> bool {
+    stream_options
+        .and_then(|o| o.include_usage)
+        .unwrap_or(false)
+}
+
+/// Build the SSE response that plays out `stream_state`
+fn sse_response(stream_state: SseStreamState) -> HttpResponse {
+    let event_name = stream_state.event_name.clone();
+    let max_bytes = stream_state.max_response_bytes;
+    let split_multibyte = stream_state.split_multibyte;
+    // A pinned, boxed stream of chunks (SSE events) which the HTTP response will stream
+    let s = unfold(stream_state, |mut st| async move {
+        if let Some(ms) = st.retry_ms.take() {
+            let retry = format!("retry: {}\n\n", ms);
+            return Some((Ok::<Bytes, actix_web::Error>(Bytes::from(retry)), st));
+        }
+
+        // Returning Pending once makes actix write out what it has buffered
+        // instead of coalescing ready events into one write
+        if st.flush_each && st.started {
+            tokio::task::yield_now().await;
+        }
+
+        // Pause once before the first event to emulate a model thinking
+        if !st.started {
+            st.started = true;
+            let thinking = Duration::from_millis(st.thinking_delay_ms);
+            if st.keepalive_ms > 0 {
+                st.thinking_until = Some(Instant::now() + thinking);
+            } else if !thinking.is_zero() {
+                st.pause(thinking).await;
+            }
+        }
+
+        // With keep-alives the pause is slept in intervals, a comment after
+        // each; once they are used up the stream moves on regardless
+        if let Some(until) = st.thinking_until {
+            let remaining = until.saturating_duration_since(Instant::now());
+            let interval = Duration::from_millis(st.keepalive_ms);
+            let timed_out = st
+                .deadline
+                .is_some_and(|deadline| Instant::now() >= deadline);
+            if remaining > interval && st.keepalives_left != Some(0) && !timed_out {
+                st.pause(interval).await;
+                if let Some(left) = st.keepalives_left.as_mut() {
+                    *left -= 1;
+                }
+                let ping = Bytes::from_static(b": keep-alive\n\n");
+                return Some((Ok::<Bytes, actix_web::Error>(ping), st));
+            }
+            if st.keepalives_left != Some(0) {
+                st.pause(remaining).await;
+            }
+            st.thinking_until = None;
+        }
+
+        // Out of time: drop whatever is left and close as a length cut
+        if st
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+            && (st.chars_remaining > 0 || st.prefix.is_some() || !st.tool_call_frames.is_empty())
+        {
+            st.chars_remaining = 0;
+            st.tool_call_frames.clear();
+            st.reasoning_chars = 0;
+            st.reasoning_gap_ms = 0;
+            st.prefix = None;
+            st.finish_reason = Some(FINISH_REASON_LENGTH);
+        }
+
+        // The answer, prefix included, starts only after the reasoning phase
+        if st.reasoning_chars == 0 && st.reasoning_gap_ms > 0 {
+            st.pause(Duration::from_millis(st.reasoning_gap_ms)).await;
+            st.reasoning_gap_ms = 0;
+        }
+
+        let prefix = if st.reasoning_chars == 0 {
+            st.prefix.take()
+        } else {
+            None
+        };
+        if let Some(prefix) = prefix {
+            st.emitted_chars += prefix.chars().count();
+            let sse = st.content_event(&prefix);
+            return Some((Ok::<Bytes, actix_web::Error>(sse), st));
+        }
+
+        // If all characters have been emitted already
+        if st.chars_remaining == 0 {
+            if let Some(frame) = st.tool_call_frames.pop_front() {
+                st.chunk_pause().await;
+                return Some((Ok::<Bytes, actix_web::Error>(Bytes::from(frame)), st));
+            }
+            if let Some(reason) = st.finish_reason.take() {
+                let sse = match st.kind {
+                    StreamKind::Chat => sse_finish_event(reason),
+                    StreamKind::Text => sse_text_finish_event(reason),
+                };
+                return Some((Ok::<Bytes, actix_web::Error>(Bytes::from(sse)), st));
+            }
+            if let Some(prompt_tokens) = st.usage_prompt_tokens.take() {
+                let completion_tokens = chars_to_tokens(st.emitted_chars);
+                let sse = sse_usage_event(&Usage {
+                    prompt_tokens,
+                    completion_tokens,
+                    total_tokens: prompt_tokens + completion_tokens,
+                    prompt_tokens_details: None,
+                });
+                return Some((Ok::<Bytes, actix_web::Error>(Bytes::from(sse)), st));
+            }
+            if st.done_sent {
+                return None;
+            }
+            st.done_sent = true;
+            let done_event = format!("data: {}\n\n", st.done_sentinel);
+            return Some((Ok::<Bytes, actix_web::Error>(Bytes::from(done_event)), st));
+        }
+
+        let chunk_chars = match st.granularity {
+            StreamGranularity::Token => {
+                // This eliminates the RNG call for every SSE event
+                let chunk_tokens = if st.stream_samples.is_empty() {
+                    1
+                } else {
+                    st.stream_samples[st.sample_idx % st.stream_samples.len()]
+                };
+                st.sample_idx += 1;
+                tokens_to_chars(chunk_tokens).min(st.chars_remaining)
+            }
+            StreamGranularity::Sentence => {
+                first_sentence_chars(&st.article[st.byte_pos..st.content_end])
+            }
+        };
+        // A chunk never straddles the reasoning/answer boundary
+        let chunk_chars = match st.reasoning_chars {
+            0 => chunk_chars,
+            reasoning => chunk_chars.min(reasoning),
+        };
+
+        // Owned handle, so `st` stays free for `content_event` below
+        let article = Arc::clone(&st.article);
+        let article = article.as_str();
+
+        // Determine byte indices, scanning only this chunk's chars
+        let start_byte = st.byte_pos;
+        let mut end_byte = byte_idx_after_chars(article, start_byte, chunk_chars);
+
+        // Avoid splitting words - try to find whitespace before end_byte
+        if st.granularity == StreamGranularity::Token && end_byte < article.len() {
+            if let Some(rel) = article[start_byte..end_byte].rfind(' ') {
+                // Only use the whitespace split if it advances the position
+                if rel > 0 {
+                    end_byte = start_byte + rel;
+                }
+            }
+        }
+
+        let slice = if end_byte > start_byte {
+            &article[start_byte..end_byte]
+        } else {
+            // Fallback in case we couldn't find a whitespace; pick a single char
+            &article[start_byte..byte_idx_after_chars(article, start_byte, 1)]
+        };
+
+        let actual_chars_sent = slice.chars().count();
+        // Wait for this chunk's share of the `--global-tps` budget
+        if let Some(rate) = &st.global_rate {
+            let wait = rate.reserve(chars_to_tokens(actual_chars_sent));
+            st.pause(wait).await;
+        }
+        let sse = if st.reasoning_chars > 0 {
+            let ts_ms = st.stream_timestamps.then(unix_millis);
+            Bytes::from(sse_reasoning_event(slice, ts_ms))
+        } else {
+            st.content_event(slice)
+        };
+
+        st.chars_remaining = st.chars_remaining.saturating_sub(actual_chars_sent);
+        st.reasoning_chars = st.reasoning_chars.saturating_sub(actual_chars_sent);
+        st.byte_pos += slice.len();
+        st.emitted_chars += actual_chars_sent;
+
+        // Chunks of the initial burst go out back to back
+        if st.burst_chars > 0 {
+            st.burst_chars = st.burst_chars.saturating_sub(actual_chars_sent);
+        } else {
+            st.chunk_pause().await;
+        }
+
+        Some((Ok::<Bytes, actix_web::Error>(sse), st))
+    });
+
+    let framed = s
+        .map(move |item| match item {
+            Ok(bytes) => Ok(match &event_name {
+                Some(name) => sse_with_event_name(bytes, name),
+                None => bytes,
+            }),
+            Err(e) => Err(e.to_string()),
+        })
+        // `--max-response-bytes`: end the stream before a frame would overshoot
+        .scan(0usize, move |sent, item| {
+            if let Ok(bytes) = &item {
+                *sent += bytes.len();
+                if max_bytes > 0 && *sent > max_bytes {
+                    return futures::future::ready(None);
+                }
+            }
+            futures::future::ready(Some(item))
+        });
+    // `--split-multibyte`: cut about every other frame that has a multibyte
+    // char in two, inside that char; without the flag frames pass untouched
+    let framed = if split_multibyte {
+        framed
+            .flat_map(|item| {
+                let split = match &item {
+                    Ok(bytes) if rand::thread_rng().gen_bool(0.5) => {
+                        split_mid_codepoint(bytes, &mut rand::thread_rng())
+                    }
+                    _ => None,
+                };
+                match split {
+                    Some((head, tail)) => futures::stream::iter([Ok(head), Ok(tail)]).left_stream(),
+                    None => futures::stream::once(futures::future::ready(item)).right_stream(),
+                }
+            })
+            .left_stream()
+    } else {
+        framed.right_stream()
+    };
+
+    // Map the stream output to a boxed stream of results consumed by actix-web
+    let boxed_stream: Pin<Box<dyn futures::Stream<Item = Result<Bytes, Error>> + Send>> =
+        Box::pin(framed.map(|item| item.map_err(actix_web::error::ErrorInternalServerError)));
+
+    HttpResponse::Ok()
+        .append_header((actix_web::http::header::CONTENT_TYPE, "text/event-stream"))
+        .append_header((actix_web::http::header::CACHE_CONTROL, "no-cache"))
+        .append_header((actix_web::http::header::CONNECTION, "keep-alive"))
+        .streaming(boxed_stream)
+}
+
+/// POST /v1/chat/completions - supports streaming SSE & non-streaming JSON
+pub async fn chat_completions_handler(
+    state: web::Data<AppState>,
+    http_req: HttpRequest,
+    req: web::Json<ChatCompletionRequest>,
+) -> Result<HttpResponse, Error> {
+    let started = Instant::now();
+    let mut req = req.into_inner();
+    let mut issues = validate_chat_request(&req);
+    if state.strict_schema {
+        issues.extend(strict_chat_request_issues(&req));
+    }
+    if state.max_messages > 0 {
+        issues.extend(max_messages_issues(&req, state.max_messages));
+    }
+    if !issues.is_empty() {
+        return Ok(validation_failed(&issues));
+    }
+    if is_model_denied(&state, &req.model) {
+        return Ok(model_not_found(&req.model));
+    }
+    if let Some(resp) = sample_model_error(&state, &req.model) {
+        return Ok(resp);
+    }
+    let prompt_tokens =
+        match check_context_length(&state, chat_prompt_tokens(&req), req.max_tokens, "messages") {
+            Ok(prompt_tokens) => prompt_tokens,
+            Err(resp) => return Ok(resp),
+        };
+    let in_flight = InFlight::enter(&state);
+    burn_configured_cpu(&state);
+    let service_tier = req.service_tier.as_deref().map(resolve_service_tier);
+    let tier_factor = service_tier.map_or(1.0, |tier| state.service_tier_factors.factor(tier));
+    let thinking_delay_ms = tier_delay_ms(
+        queued_delay_ms(
+            &state,
+            state
+                .thinking_delay_ms
+                .saturating_add(prefill_delay_ms(&state, prompt_tokens)),
+        ),
+        tier_factor,
+    );
+
+    let stream_flag = req.stream.unwrap_or(false);
+    let verbosity_scale = req
+        .verbosity
+        .as_deref()
+        .map_or(1.0, |v| state.verbosity_factors.factor(v));
+
+    if let (false, Some(canned)) = (stream_flag, &state.canned_chat_response) {
+        if thinking_delay_ms > 0 {
+            tokio::time::sleep(Duration::from_millis(thinking_delay_ms)).await;
+        }
+        wait_for_delay_override(&http_req).await;
+        wait_for_min_response_time(&state, started).await;
+        drop(in_flight);
+
+        return Ok(HttpResponse::Ok()
+            .content_type("application/json")
+            .body(canned.clone()));
+    }
+
+    if !stream_flag {
+        // Non-streaming response; sampling order (tokens, article, filter)
+        // matches the streaming path so a seed yields the same content in both
+        let mut rng = request_rng(req.seed);
+        let completion_tokens =
+            sample_completion_tokens(&state, &mut rng, verbosity_scale, req.max_tokens);
+        let article = pick_article(&state, completion_tokens, &mut rng);
+        // `x_mock_content` is returned as is, without tools, refusal or filter
+        let forced = req.x_mock_content.as_deref();
+
+        let tool_calls = match forced {
+            Some(_) => Vec::new(),
+            None => sample_tool_calls(&req, state.max_tool_calls, &mut rng),
+        };
+        // A refusal replaces whatever would have been generated
+        let refused = forced.is_none() && refusal_triggered(&state, &req);
+        // A filter hit stops generation halfway through the sampled content
+        let filtered = forced.is_none()
+            && !refused
+            && tool_calls.is_empty()
+            && sample_content_filter(&state, &mut rng);
+        let completion_tokens = if filtered {
+            completion_tokens / 2
+        } else {
+            completion_tokens
+        };
+
+        let content = match forced {
+            Some(text) => Cow::Borrowed(text),
+            None => {
+                let mut content = shape_content(
+                    &state,
+                    article_content(&state, &article, completion_tokens),
+                    req.frequency_penalty,
+                );
+                if state.reflect_prompt {
+                    if let Some(prefix) = req.messages.as_deref().and_then(reflect_prompt_prefix) {
+                        content = Cow::Owned(prefix + &content);
+                    }
+                }
+                content
+            }
+        };
+
+        let actual_completion_tokens = if refused {
+            chars_to_tokens(REFUSAL_MESSAGE.chars().count())
+        } else if tool_calls.is_empty() {
+            chars_to_tokens(content.chars().count())
+        } else {
+            let chars: usize = tool_calls
+                .iter()
+                .map(|c| c.function.name.len() + c.function.arguments.len())
+                .sum();
+            chars_to_tokens(chars)
+        };
+        let created = created_field(&state);
+
+        let message_texts: Vec<_> = req
+            .messages
+            .iter()
+            .flatten()
+            .map(|m| (m.role.as_str(), m.content.text()))
+            .collect();
+        let prompt_parts = message_texts
+            .iter()
+            .flat_map(|(role, text)| [*role, text.as_ref()]);
+        let usage = Usage {
+            prompt_tokens,
+            completion_tokens: actual_completion_tokens,
+            total_tokens: prompt_tokens + actual_completion_tokens,
+            prompt_tokens_details: prompt_tokens_details(&state, prompt_parts, prompt_tokens),
+        };
+
+        let choice = if refused {
+            ChatChoice {
+                index: 0,
+                message: ChatMessage {
+                    role: ROLE_ASSISTANT,
+                    content: None,
+                    refusal: Some(REFUSAL_MESSAGE),
+                    annotations: None,
+                    tool_calls: None,
+                },
+                logprobs: None,
+                finish_reason: FINISH_REASON_STOP,
+            }
+        } else if tool_calls.is_empty() {
+            let annotations = (state.url_citations > 0)
+                .then(|| url_citations(&content, state.url_citations, &mut rng));
+            let logprobs = (req.logprobs == Some(true))
+                .then(|| mock_logprobs(&content, req.top_logprobs.unwrap_or(0), &mut rng));
+            ChatChoice {
+                index: 0,
+                message: ChatMessage {
+                    role: ROLE_ASSISTANT,
+                    content: Some(content),
+                    refusal: None,
+                    annotations,
+                    tool_calls: None,
+                },
+                logprobs,
+                finish_reason: if filtered {
+                    FINISH_REASON_CONTENT_FILTER
+                } else {
+                    FINISH_REASON_STOP
+                },
+            }
+        } else {
+            ChatChoice {
+                index: 0,
+                message: ChatMessage {
+                    role: ROLE_ASSISTANT,
+                    content: None,
+                    refusal: None,
+                    annotations: None,
+                    tool_calls: Some(tool_calls),
+                },
+                logprobs: None,
+                finish_reason: FINISH_REASON_TOOL_CALLS,
+            }
+        };
+
+        let mut resp = ChatCompletionResponse {
+            id: format!("chatcmpl-{}", Uuid::new_v4()),
+            object: OBJECT_CHAT_COMPLETION.to_string(),
+            created,
+            system_fingerprint: req
+                .seed
+                .map(|seed| system_fingerprint(&state, seed, &req.model)),
+            service_tier,
+            prompt_filter_results: state.azure_compat.then(|| {
+                let prompt: Vec<_> = message_texts
+                    .iter()
+                    .map(|(_, text)| text.as_ref())
+                    .collect();
+                prompt_filter_results(&prompt.join("\n"))
+            }),
+            model: response_model(&state, req.model),
+            usage,
+            choices: vec![choice],
+        };
+        // A degenerate but well-formed response: nothing generated at all
+        if sample_empty_choices(&state, &mut rng) {
+            resp.choices.clear();
+            resp.usage.completion_tokens = 0;
+            resp.usage.total_tokens = resp.usage.prompt_tokens;
+        }
+
+        if thinking_delay_ms > 0 {
+            tokio::time::sleep(Duration::from_millis(thinking_delay_ms)).await;
+        }
+        wait_for_delay_override(&http_req).await;
+        wait_for_min_response_time(&state, started).await;
+        drop(in_flight);
+
+        return capped_json_response(&state, &mut resp, |resp, excess| {
+            let Some(choice) = resp.choices.first_mut() else {
+                return false;
+            };
+            let Some(content) = choice.message.content.as_mut() else {
+                return false;
+            };
+            if !truncate_cow_bytes(content, excess) {
+                return false;
+            }
+            choice.finish_reason = FINISH_REASON_LENGTH;
+            let chars = content.chars().count();
+            if let Some(annotations) = choice.message.annotations.as_mut() {
+                annotations.retain(|Annotation::UrlCitation { url_citation }| {
+                    url_citation.end_index <= chars
+                });
+            }
+            if let Some(logprobs) = choice.logprobs.as_mut() {
+                let mut covered = 0;
+                logprobs.content.retain(|t| {
+                    covered += t.token.len();
+                    covered <= content.len()
+                });
+            }
+            resp.usage.completion_tokens = chars_to_tokens(chars);
+            resp.usage.total_tokens = resp.usage.prompt_tokens + resp.usage.completion_tokens;
+            true
+        });
+    }
+
+    // Streaming mode (SSE)
+    // Sample total tokens to emit
+    let mut rng = request_rng(req.seed);
+    let total_tokens = sample_completion_tokens(&state, &mut rng, verbosity_scale, req.max_tokens);
+    let article = pick_article(&state, total_tokens, &mut rng);
+    // `x_mock_content` replaces the article and is streamed in full
+    let forced = req.x_mock_content.take();
+    let (article, total_tokens) = match forced.as_ref() {
+        Some(text) => (
+            Arc::new(text.clone()),
+            chars_to_tokens(text.chars().count()),
+        ),
+        None => shaped_article(&state, article, total_tokens, req.frequency_penalty),
+    };
+    // Tool calls replace the content and are streamed as `delta.tool_calls`
+    let tool_calls = match forced {
+        Some(_) => Vec::new(),
+        None => sample_tool_calls(&req, state.max_tool_calls, &mut rng),
+    };
+    // On a filter hit the stream stops halfway and closes with a
+    // `content_filter` frame instead of the remaining content
+    let filtered =
+        forced.is_none() && tool_calls.is_empty() && sample_content_filter(&state, &mut rng);
+    let total_tokens = if !tool_calls.is_empty() {
+        0
+    } else if filtered {
+        total_tokens / 2
+    } else {
+        total_tokens
+    };
+    // Reasoning is streamed on top of the answer, from the same article
+    let reasoning_tokens = if forced.is_some() {
+        0
+    } else {
+        state.reasoning_tokens
+    };
+
+    let reflected = if state.reflect_prompt && forced.is_none() {
+        req.messages.as_deref().and_then(reflect_prompt_prefix)
+    } else {
+        None
+    };
+    // `echo` replays the prompt ahead of everything else, counted as content
+    let echoed = if req.echo == Some(true) {
+        req.messages.as_deref().and_then(echo_prompt)
+    } else {
+        None
+    };
+    let prefix = match (echoed, reflected) {
+        (Some(echoed), Some(reflected)) => Some(echoed + &reflected),
+        (echoed, reflected) => echoed.or(reflected),
+    };
+    let usage_prompt_tokens = include_usage(req.stream_options.as_ref()).then_some(prompt_tokens);
+
+    let logprobs_top = (req.logprobs == Some(true)).then(|| req.top_logprobs.unwrap_or(0));
+    // With logprobs the stream closes on a `logprobs: null` finish frame
+    let finish_reason = if !tool_calls.is_empty() {
+        Some(FINISH_REASON_TOOL_CALLS)
+    } else if filtered {
+        Some(FINISH_REASON_CONTENT_FILTER)
+    } else {
+        logprobs_top.map(|_| FINISH_REASON_STOP)
+    };
+
+    let stream_state = SseStreamState {
+        // Like the content, a prefix gives way to tool calls
+        prefix: prefix.filter(|_| tool_calls.is_empty()),
+        tool_call_frames: sse_tool_call_frames(&tool_calls).into(),
+        // Usage counts tool calls as the non-streaming response does
+        emitted_chars: tool_calls
+            .iter()
+            .map(|c| c.function.name.len() + c.function.arguments.len())
+            .sum(),
+        thinking_delay_ms,
+        finish_reason,
+        usage_prompt_tokens,
+        logprobs_top,
+        response_delay_ms: tier_delay_ms(
+            stream_chunk_delay_ms(&state, &http_req, &req.model),
+            tier_factor,
+        ),
+        _in_flight: in_flight,
+        ..SseStreamState::new(
+            StreamKind::Chat,
+            &state,
+            article,
+            total_tokens + reasoning_tokens,
+        )
+    }
+    .with_reasoning(reasoning_tokens, state.reasoning_gap_ms);
+    Ok(sse_response(stream_state))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, App};
+
+    /// Parse the JSON `data:` frames of an SSE body, skipping `[DONE]`
+    fn sse_frames(body: &[u8]) -> Vec<serde_json::Value> {
+        std::str::from_utf8(body)
+            .unwrap()
+            .lines()
+            .filter_map(|line| line.strip_prefix("data: "))
+            .filter(|data| *data != DEFAULT_DONE_SENTINEL)
+            .map(|data| serde_json::from_str(data).unwrap())
+            .collect()
+    }
+
+    #[actix_web::test]
+    async fn test_completions_capped_by_max_tokens() {
+        let articles = vec![std::sync::Arc::new("hello world test".to_string())];
+        let stream_samples = vec![10, 20, 30];
+        let app_state = web::Data::new(AppState {
+            articles,
+            stream_token_samples: std::sync::Arc::new(stream_samples),
+            stream_samples_idx: std::sync::atomic::AtomicUsize::new(0),
+            token_mean: 100.0,
+            token_stddev: 20.0,
+            response_delay_ms: 0,
+            ..Default::default()
+        });
+
+        let app = test::init_service(
+            App::new()
+                .app_data(app_state)
+                .route("/v1/completions", web::post().to(completions_handler)),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/v1/completions")
+            .set_json(serde_json::json!({
+                "model": "text-davinci-003",
+                "prompt": "hello",
+                "max_tokens": 5
+            }))
+            .to_request();
+
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+    }
+
+    #[actix_web::test]
+    async fn test_completions_content_type_follows_stream_flag() {
+        let app_state = web::Data::new(AppState {
+            articles: vec![std::sync::Arc::new("hello world test".to_string())],
+            stream_token_samples: std::sync::Arc::new(vec![1, 2]),
+            ..Default::default()
+        });
+
+        let app = test::init_service(
+            App::new()
+                .app_data(app_state)
+                .route("/v1/completions", web::post().to(completions_handler)),
+        )
+        .await;
+
+        for stream in [false, true] {
+            let req = test::TestRequest::post()
+                .uri("/v1/completions")
+                .set_json(serde_json::json!({
+                    "model": "text-davinci-003",
+                    "prompt": "hello",
+                    "stream": stream
+                }))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert!(resp.status().is_success());
+            let content_type = resp
+                .headers()
+                .get(actix_web::http::header::CONTENT_TYPE)
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .to_string();
+            let body = test::read_body(resp).await;
+            let body = std::str::from_utf8(&body).unwrap();
+            if stream {
+                assert_eq!(content_type, "text/event-stream");
+                assert!(body.starts_with("data: "));
+                assert!(body.ends_with("data: [DONE]\n\n"));
+            } else {
+                assert_eq!(content_type, "application/json");
+                let body: serde_json::Value = serde_json::from_str(body).unwrap();
+                assert_eq!(body["object"], OBJECT_TEXT_COMPLETION);
+            }
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_chat_streaming_sse() {
+        let articles = vec![std::sync::Arc::new(
+            "Lorem ipsum dolor sit amet consectetur adipiscing elit sed do eiusmod tempor"
+                .to_string(),
+        )];
+        let stream_samples = vec![5, 10, 15, 20, 10, 5];
+        let app_state = web::Data::new(AppState {
+            articles,
+            stream_token_samples: std::sync::Arc::new(stream_samples),
+            stream_samples_idx: std::sync::atomic::AtomicUsize::new(0),
+            token_mean: 50.0,
+            token_stddev: 10.0,
+            response_delay_ms: 0,
+            ..Default::default()
+        });
+
+        let app = test::init_service(App::new().app_data(app_state).route(
+            "/v1/chat/completions",
+            web::post().to(chat_completions_handler),
+        ))
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/v1/chat/completions")
+            .set_json(serde_json::json!({
+                "model": "gpt-4-mock",
+                "messages": [{"role": "user", "content": "hello"}],
+                "stream": true
+            }))
+            .to_request();
+
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+    }
+
+    #[actix_web::test]
+    async fn test_capacity_latency_grows_with_concurrency() {
+        let app_state = web::Data::new(AppState {
+            articles: vec![std::sync::Arc::new("hello world test".to_string())],
+            thinking_delay_ms: 40,
+            capacity: 1,
+            ..Default::default()
+        });
+
+        let app = test::init_service(
+            App::new()
+                .app_data(app_state.clone())
+                .route(
+                    "/v1/chat/completions",
+                    web::post().to(chat_completions_handler),
+                )
+                .route("/v1/completions", web::post().to(completions_handler)),
+        )
+        .await;
+
+        let requests = [
+            (
+                "/v1/chat/completions",
+                serde_json::json!({
+                    "model": "gpt-4-mock",
+                    "messages": [{"role": "user", "content": "hello"}]
+                }),
+            ),
+            (
+                "/v1/completions",
+                serde_json::json!({"model": "gpt-4-mock", "prompt": "hello"}),
+            ),
+        ];
+        for (uri, payload) in requests {
+            let timed_request = || async {
+                let req = test::TestRequest::post()
+                    .uri(uri)
+                    .set_json(&payload)
+                    .to_request();
+                let started = std::time::Instant::now();
+                let resp = test::call_service(&app, req).await;
+                assert!(resp.status().is_success());
+                started.elapsed()
+            };
+
+            let isolated = timed_request().await;
+            let concurrent = futures::future::join_all((0..4).map(|_| timed_request())).await;
+            let slowest = concurrent.into_iter().max().unwrap();
+
+            assert!(
+                slowest > isolated * 2,
+                "{}: isolated {:?}, slowest concurrent {:?}",
+                uri,
+                isolated,
+                slowest
+            );
+            assert_eq!(app_state.in_flight.load(Ordering::Relaxed), 0);
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_completions_stream_holds_capacity() {
+        let app_state = web::Data::new(AppState {
+            articles: vec![std::sync::Arc::new("hello world test".to_string())],
+            capacity: 1,
+            ..Default::default()
+        });
+
+        let app = test::init_service(
+            App::new()
+                .app_data(app_state.clone())
+                .route("/v1/completions", web::post().to(completions_handler)),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/v1/completions")
+            .set_json(serde_json::json!({
+                "model": "gpt-4-mock",
+                "prompt": "hello",
+                "stream": true
+            }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+        // The stream counts against capacity until its last event
+        assert_eq!(app_state.in_flight.load(Ordering::Relaxed), 1);
+        test::read_body(resp).await;
+        assert_eq!(app_state.in_flight.load(Ordering::Relaxed), 0);
+    }
+
+    #[actix_web::test]
+    async fn test_chat_streaming_thinking_delay() {
+        use actix_web::body::MessageBody;
+
+        let app_state = web::Data::new(AppState {
+            articles: vec![std::sync::Arc::new("hello world test".to_string())],
+            stream_token_samples: std::sync::Arc::new(vec![1, 2, 3]),
+            thinking_delay_ms: 100,
+            ..Default::default()
+        });
+
+        let app = test::init_service(App::new().app_data(app_state).route(
+            "/v1/chat/completions",
+            web::post().to(chat_completions_handler),
+        ))
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/v1/chat/completions")
+            .set_json(serde_json::json!({
+                "model": "gpt-4-mock",
+                "messages": [{"role": "user", "content": "hello"}],
+                "stream": true
+            }))
+            .to_request();
+
+        let started = std::time::Instant::now();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+
+        let mut body = std::pin::pin!(resp.into_body());
+        let first = std::future::poll_fn(|cx| body.as_mut().poll_next(cx)).await;
+        assert!(first.is_some());
+        assert!(started.elapsed() >= Duration::from_millis(100));
+    }
+
+    #[actix_web::test]
+    async fn test_completions_thinking_delay() {
+        let app_state = web::Data::new(AppState {
+            articles: vec![std::sync::Arc::new("hello world test".to_string())],
+            thinking_delay_ms: 100,
+            ..Default::default()
+        });
+
+        let app = test::init_service(
+            App::new()
+                .app_data(app_state)
+                .route("/v1/completions", web::post().to(completions_handler)),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/v1/completions")
+            .set_json(serde_json::json!({"model": "gpt-4-mock", "prompt": "hello"}))
+            .to_request();
+
+        let started = std::time::Instant::now();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+        assert!(started.elapsed() >= Duration::from_millis(100));
+    }
+
+    #[actix_web::test]
+    async fn test_chat_reflect_prompt() {
+        let app_state = web::Data::new(AppState {
+            articles: vec![std::sync::Arc::new(
+                "lorem ipsum dolor sit amet".to_string(),
+            )],
+            stream_token_samples: std::sync::Arc::new(vec![1, 2, 3]),
+            reflect_prompt: true,
+            ..Default::default()
+        });
+
+        let app = test::init_service(App::new().app_data(app_state).route(
+            "/v1/chat/completions",
+            web::post().to(chat_completions_handler),
+        ))
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/v1/chat/completions")
+            .set_json(serde_json::json!({
+                "model": "gpt-4-mock",
+                "messages": [{"role": "user", "content": "ping from the test"}]
+            }))
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        let content = body["choices"][0]["message"]["content"].as_str().unwrap();
+        assert!(content.starts_with("You said: 'ping from the test'. "));
+        let expected_tokens = chars_to_tokens(content.chars().count());
+        assert_eq!(body["usage"]["completion_tokens"], expected_tokens);
+
+        let req = test::TestRequest::post()
+            .uri("/v1/chat/completions")
+            .set_json(serde_json::json!({
+                "model": "gpt-4-mock",
+                "messages": [{"role": "user", "content": "ping from the test"}],
+                "stream": true
+            }))
+            .to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        let body = std::str::from_utf8(&body).unwrap();
+        assert!(body.contains("ping from the test"));
+        assert!(body.ends_with("data: [DONE]\n\n"));
+    }
+
+    #[actix_web::test]
+    async fn test_chat_stream_echo() {
+        let app_state = web::Data::new(AppState {
+            articles: vec![std::sync::Arc::new(
+                "lorem ipsum dolor sit amet".to_string(),
+            )],
+            stream_token_samples: std::sync::Arc::new(vec![1, 2, 3]),
+            ..Default::default()
+        });
+
+        let app = test::init_service(App::new().app_data(app_state).route(
+            "/v1/chat/completions",
+            web::post().to(chat_completions_handler),
+        ))
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/v1/chat/completions")
+            .set_json(serde_json::json!({
+                "model": "gpt-4-mock",
+                "messages": [
+                    {"role": "system", "content": "stay quiet"},
+                    {"role": "user", "content": "echo this prompt"}
+                ],
+                "stream": true,
+                "echo": true,
+                "stream_options": {"include_usage": true}
+            }))
+            .to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        let events = sse_frames(&body);
+        let content: String = events
+            .iter()
+            .filter_map(|e| e["choices"][0]["delta"]["content"].as_str())
+            .collect();
+        assert!(content.starts_with("echo this prompt"));
+        assert!(!content.contains("stay quiet"));
+
+        let usage = events.iter().find(|e| e["usage"].is_object()).unwrap();
+        assert_eq!(
+            usage["usage"]["completion_tokens"],
+            chars_to_tokens(content.chars().count())
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_denied_model_not_found() {
+        let app_state = web::Data::new(AppState {
+            articles: vec![std::sync::Arc::new("hello world test".to_string())],
+            deny_models: vec!["*-denied".to_string()],
+            ..Default::default()
+        });
+
+        let app = test::init_service(
+            App::new()
+                .app_data(app_state)
+                .route(
+                    "/v1/chat/completions",
+                    web::post().to(chat_completions_handler),
+                )
+                .route("/v1/completions", web::post().to(completions_handler)),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/v1/chat/completions")
+            .set_json(serde_json::json!({
+                "model": "gpt-4-denied",
+                "messages": [{"role": "user", "content": "hello"}]
+            }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::NOT_FOUND);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["error"]["code"], "model_not_found");
+
+        let req = test::TestRequest::post()
+            .uri("/v1/completions")
+            .set_json(serde_json::json!({ "model": "gpt-4-denied", "prompt": "hi" }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::NOT_FOUND);
+
+        let req = test::TestRequest::post()
+            .uri("/v1/chat/completions")
+            .set_json(serde_json::json!({
+                "model": "gpt-4-mock",
+                "messages": [{"role": "user", "content": "hello"}]
+            }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+    }
+
+    #[actix_web::test]
+    async fn test_chat_streaming_rejects_multiple_choices() {
+        let app_state = web::Data::new(AppState {
+            articles: vec![std::sync::Arc::new("hello world test".to_string())],
+            ..Default::default()
+        });
+
+        let app = test::init_service(App::new().app_data(app_state).route(
+            "/v1/chat/completions",
+            web::post().to(chat_completions_handler),
+        ))
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/v1/chat/completions")
+            .set_json(serde_json::json!({
+                "model": "gpt-4-mock",
+                "messages": [{"role": "user", "content": "hello"}],
+                "stream": true,
+                "n": 2
+            }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["error"]["param"], "n");
+        assert!(body["error"]["message"].as_str().unwrap().contains("n > 1"));
+    }
+
+    #[actix_web::test]
+    async fn test_min_response_time_floor() {
+        let app_state = web::Data::new(AppState {
+            articles: vec![std::sync::Arc::new("hello world test".to_string())],
+            min_response_ms: 80,
+            ..Default::default()
+        });
+
+        let app = test::init_service(
+            App::new()
+                .app_data(app_state)
+                .route(
+                    "/v1/chat/completions",
+                    web::post().to(chat_completions_handler),
+                )
+                .route("/v1/completions", web::post().to(completions_handler))
+                .route("/v1/embeddings", web::post().to(embeddings_handler)),
+        )
+        .await;
+
+        let requests = [
+            (
+                "/v1/chat/completions",
+                serde_json::json!({
+                    "model": "gpt-4-mock",
+                    "messages": [{"role": "user", "content": "hello"}]
+                }),
+            ),
+            (
+                "/v1/completions",
+                serde_json::json!({ "model": "gpt-4-mock", "prompt": "hello" }),
+            ),
+            ("/v1/embeddings", serde_json::json!({ "input": "hello" })),
+        ];
+        for (uri, payload) in requests {
+            let started = std::time::Instant::now();
+            let req = test::TestRequest::post()
+                .uri(uri)
+                .set_json(payload)
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert!(resp.status().is_success());
+            assert!(
+                started.elapsed() >= Duration::from_millis(80),
+                "{} answered too fast",
+                uri
+            );
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_stream_flush_each_separates_frames() {
+        use actix_web::body::MessageBody;
+
+        let app_state = web::Data::new(AppState {
+            articles: vec![std::sync::Arc::new(
+                "Lorem ipsum dolor sit amet consectetur adipiscing elit".to_string(),
+            )],
+            stream_token_samples: std::sync::Arc::new(vec![2]),
+            token_mean: 8.0,
+            token_stddev: 0.0,
+            response_delay_ms: 30,
+            stream_flush_each: true,
+            ..Default::default()
+        });
+
+        let app = test::init_service(App::new().app_data(app_state).route(
+            "/v1/chat/completions",
+            web::post().to(chat_completions_handler),
+        ))
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/v1/chat/completions")
+            .set_json(serde_json::json!({
+                "model": "gpt-4-mock",
+                "messages": [{"role": "user", "content": "hello"}],
+                "stream": true
+            }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+
+        let mut body = std::pin::pin!(resp.into_body());
+        let mut arrivals = Vec::new();
+        while let Some(frame) = std::future::poll_fn(|cx| body.as_mut().poll_next(cx)).await {
+            let frame = frame.unwrap();
+            if frame.starts_with(b"data: {") {
+                // Every frame holds exactly one event
+                assert_eq!(frame.windows(2).filter(|w| w == b"\n\n").count(), 1);
+                arrivals.push(std::time::Instant::now());
+            }
+        }
+
+        assert!(arrivals.len() > 2);
+        for gap in arrivals.windows(2).map(|w| w[1] - w[0]) {
+            assert!(
+                gap >= Duration::from_millis(20),
+                "frames batched: {:?}",
+                gap
+            );
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_split_multibyte_reassembles() {
+        use actix_web::body::MessageBody;
+
+        let app_state = web::Data::new(AppState {
+            articles: vec![std::sync::Arc::new(
+                "你好世界 😀 日本語のテキスト ".repeat(40),
+            )],
+            token_mean: 200.0,
+            token_stddev: 0.0,
+            split_multibyte: true,
+            ..Default::default()
+        });
+
+        let app = test::init_service(App::new().app_data(app_state).route(
+            "/v1/chat/completions",
+            web::post().to(chat_completions_handler),
+        ))
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/v1/chat/completions")
+            .set_json(serde_json::json!({
+                "model": "gpt-4-mock",
+                "messages": [{"role": "user", "content": "hello"}],
+                "stream": true
+            }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+
+        let mut body = std::pin::pin!(resp.into_body());
+        let mut pieces = Vec::new();
+        while let Some(piece) = std::future::poll_fn(|cx| body.as_mut().poll_next(cx)).await {
+            pieces.push(piece.unwrap());
+        }
+        assert!(pieces.iter().any(|p| std::str::from_utf8(p).is_err()));
+
+        let whole = pieces.concat();
+        // The reassembled stream is valid UTF-8 again
+        let content: String = sse_frames(&whole)
+            .iter()
+            .filter_map(|f| f["choices"][0]["delta"]["content"].as_str())
+            .collect();
+        assert!(content.contains("你好世界"));
+    }
+
+    #[actix_web::test]
+    async fn test_stream_burst_tokens() {
+        use actix_web::body::MessageBody;
+
+        let app_state = web::Data::new(AppState {
+            articles: vec![std::sync::Arc::new("abc ".repeat(100))],
+            stream_token_samples: std::sync::Arc::new(vec![1]),
+            token_mean: 12.0,
+            token_stddev: 0.0,
+            response_delay_ms: 50,
+            stream_burst_tokens: 6,
+            ..Default::default()
+        });
+
+        let app = test::init_service(App::new().app_data(app_state).route(
+            "/v1/chat/completions",
+            web::post().to(chat_completions_handler),
+        ))
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/v1/chat/completions")
+            .set_json(serde_json::json!({
+                "model": "gpt-4-mock",
+                "messages": [{"role": "user", "content": "hello"}],
+                "stream": true
+            }))
+            .to_request();
+        let started = std::time::Instant::now();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+
+        // (content chars sent before the frame, arrival time)
+        let mut arrivals = Vec::new();
+        let mut sent_chars = 0;
+        let mut body = std::pin::pin!(resp.into_body());
+        while let Some(frame) = std::future::poll_fn(|cx| body.as_mut().poll_next(cx)).await {
+            let frame = frame.unwrap();
+            let Some(data) = frame.strip_prefix(b"data: {") else {
+                continue;
+            };
+            let json: serde_json::Value = serde_json::from_slice(&[b"{", data].concat()).unwrap();
+            if let Some(content) = json["choices"][0]["delta"]["content"].as_str() {
+                arrivals.push((sent_chars, started.elapsed()));
+                sent_chars += content.chars().count();
+            }
+        }
+
+        let burst_chars = tokens_to_chars(6);
+        let (burst, paced): (Vec<_>, Vec<_>) =
+            arrivals.iter().partition(|(sent, _)| *sent < burst_chars);
+        assert!(burst.len() > 1);
+        for (_, at) in burst {
+            assert!(at < Duration::from_millis(40), "burst frame at {:?}", at);
+        }
+        assert!(paced.len() > 2);
+        let (_, last) = paced.last().unwrap();
+        assert!(
+            *last >= Duration::from_millis(50) * (paced.len() as u32 - 1),
+            "paced frames done by {:?}",
+            last
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_chat_streaming_timestamps() {
+        let app_state = web::Data::new(AppState {
+            articles: vec![std::sync::Arc::new(
+                "Lorem ipsum dolor sit amet consectetur adipiscing elit sed do eiusmod tempor"
+                    .to_string(),
+            )],
+            stream_token_samples: std::sync::Arc::new(vec![1, 2]),
+            response_delay_ms: 2,
+            stream_timestamps: true,
+            ..Default::default()
+        });
+
+        let app = test::init_service(App::new().app_data(app_state).route(
+            "/v1/chat/completions",
+            web::post().to(chat_completions_handler),
+        ))
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/v1/chat/completions")
+            .set_json(serde_json::json!({
+                "model": "gpt-4-mock",
+                "messages": [{"role": "user", "content": "hello"}],
+                "stream": true
+            }))
+            .to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        let stamps: Vec<u64> = sse_frames(&body)
+            .iter()
+            .map(|v| v["_mock_ts_ms"].as_u64().expect("missing _mock_ts_ms"))
+            .collect();
+        assert!(stamps.len() > 1);
+        assert!(stamps.windows(2).all(|w| w[0] < w[1]), "{:?}", stamps);
+    }
+
+    #[actix_web::test]
+    async fn test_stream_delay_normal_gaps_vary() {
+        let app_state = web::Data::new(AppState {
+            articles: vec![std::sync::Arc::new("word ".repeat(200))],
+            stream_token_samples: std::sync::Arc::new(vec![1]),
+            response_delay_ms: 10,
+            stream_delay_dist: StreamDelayDist::Normal,
+            stream_delay_stddev_ms: 5.0,
+            stream_timestamps: true,
+            ..Default::default()
+        });
+
+        let app = test::init_service(App::new().app_data(app_state).route(
+            "/v1/chat/completions",
+            web::post().to(chat_completions_handler),
+        ))
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/v1/chat/completions")
+            .set_json(serde_json::json!({
+                "model": "gpt-4-mock",
+                "messages": [{"role": "user", "content": "hello"}],
+                "max_tokens": 12,
+                "stream": true
+            }))
+            .to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        let stamps: Vec<f64> = sse_frames(&body)
+            .iter()
+            .map(|v| v["_mock_ts_ms"].as_u64().unwrap() as f64)
+            .collect();
+        let gaps: Vec<f64> = stamps.windows(2).map(|w| w[1] - w[0]).collect();
+        assert!(gaps.len() > 4, "{:?}", stamps);
+        let mean = gaps.iter().sum::<f64>() / gaps.len() as f64;
+        let variance = gaps.iter().map(|g| (g - mean).powi(2)).sum::<f64>() / gaps.len() as f64;
+        assert!(variance > 0.0, "gaps {:?}", gaps);
+    }
+
+    #[actix_web::test]
+    async fn test_validation_reports_all_issues() {
+        let app_state = web::Data::new(AppState {
+            articles: vec![std::sync::Arc::new("hello world test".to_string())],
+            ..Default::default()
+        });
+
+        let app = test::init_service(App::new().app_data(app_state).route(
+            "/v1/chat/completions",
+            web::post().to(chat_completions_handler),
+        ))
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/v1/chat/completions")
+            .set_json(serde_json::json!({
+                "model": "",
+                "messages": [{"role": "user", "content": "hello"}],
+                "max_tokens": 0
+            }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        let params: Vec<&str> = body["error"]["errors"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|e| e["param"].as_str().unwrap())
+            .collect();
+        assert_eq!(params, ["model", "max_tokens"]);
+    }
+
+    #[actix_web::test]
+    async fn test_strict_schema_rejects_mixed_up_fields() {
+        for strict_schema in [false, true] {
+            let app_state = web::Data::new(AppState {
+                articles: vec![std::sync::Arc::new("hello world test".to_string())],
+                strict_schema,
+                ..Default::default()
+            });
+
+            let app = test::init_service(
+                App::new()
+                    .app_data(app_state)
+                    .route(
+                        "/v1/chat/completions",
+                        web::post().to(chat_completions_handler),
+                    )
+                    .route("/v1/completions", web::post().to(completions_handler)),
+            )
+            .await;
+
+            // messages sent to the legacy endpoint
+            let req = test::TestRequest::post()
+                .uri("/v1/completions")
+                .set_json(serde_json::json!({
+                    "model": "gpt-4-mock",
+                    "messages": [{"role": "user", "content": "hello"}]
+                }))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            if strict_schema {
+                assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+                let body: serde_json::Value = test::read_body_json(resp).await;
+                assert_eq!(body["error"]["param"], "messages");
+                let message = body["error"]["message"].as_str().unwrap();
+                assert!(message.contains("/v1/chat/completions"));
+            } else {
+                assert!(resp.status().is_success());
+            }
+
+            // prompt sent to the chat endpoint
+            let req = test::TestRequest::post()
+                .uri("/v1/chat/completions")
+                .set_json(serde_json::json!({ "model": "gpt-4-mock", "prompt": "hello" }))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            if strict_schema {
+                assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+                let body: serde_json::Value = test::read_body_json(resp).await;
+                assert_eq!(body["error"]["param"], "prompt");
+                let message = body["error"]["message"].as_str().unwrap();
+                assert!(message.contains("/v1/completions"));
+            } else {
+                assert!(resp.status().is_success());
+            }
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_prompt_cache_reports_cached_tokens() {
+        let app_state = web::Data::new(AppState {
+            articles: vec![std::sync::Arc::new("hello world test".to_string())],
+            prompt_cache: Some(crate::prompt_cache::PromptCache::default()),
+            prompt_cache_fraction: 0.5,
+            ..Default::default()
+        });
+
+        let app = test::init_service(App::new().app_data(app_state).route(
+            "/v1/chat/completions",
+            web::post().to(chat_completions_handler),
+        ))
+        .await;
+
+        let payload = serde_json::json!({
+            "model": "gpt-4-mock",
+            "messages": [{"role": "user", "content": "a reasonably long prompt to be cached"}]
+        });
+        let mut cached = Vec::new();
+        for _ in 0..2 {
+            let req = test::TestRequest::post()
+                .uri("/v1/chat/completions")
+                .set_json(&payload)
+                .to_request();
+            let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+            cached.push(
+                body["usage"]["prompt_tokens_details"]["cached_tokens"]
+                    .as_u64()
+                    .unwrap(),
+            );
+        }
+        assert_eq!(cached[0], 0);
+        assert!(cached[1] > 0);
+    }
+
+    #[actix_web::test]
+    async fn test_chat_multiple_tool_calls() {
+        let app_state = web::Data::new(AppState {
+            articles: vec![std::sync::Arc::new("hello world test".to_string())],
+            max_tool_calls: 4,
+            ..Default::default()
+        });
+
+        let app = test::init_service(App::new().app_data(app_state).route(
+            "/v1/chat/completions",
+            web::post().to(chat_completions_handler),
+        ))
+        .await;
+
+        let payload = serde_json::json!({
+            "model": "gpt-4-mock",
+            "messages": [{"role": "user", "content": "weather and time?"}],
+            "tools": [
+                {"type": "function", "function": {"name": "get_weather",
+                    "parameters": {"type": "object", "properties": {"city": {"type": "string"}}}}},
+                {"type": "function", "function": {"name": "get_time"}}
+            ]
+        });
+
+        let mut saw_multiple = false;
+        for _ in 0..20 {
+            let req = test::TestRequest::post()
+                .uri("/v1/chat/completions")
+                .set_json(&payload)
+                .to_request();
+            let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+            let choice = &body["choices"][0];
+            assert_eq!(choice["finish_reason"], "tool_calls");
+            assert!(choice["message"]["content"].is_null());
+
+            let calls = choice["message"]["tool_calls"].as_array().unwrap();
+            assert!((1..=4).contains(&calls.len()));
+            let ids: std::collections::HashSet<&str> =
+                calls.iter().map(|c| c["id"].as_str().unwrap()).collect();
+            assert_eq!(ids.len(), calls.len(), "tool call ids must be unique");
+            saw_multiple |= calls.len() > 1;
+        }
+        assert!(saw_multiple);
+    }
+
+    #[actix_web::test]
+    async fn test_debug_config_reflects_runtime_overrides() {
+        use clap::Parser;
+        let args = crate::args::Args::parse_from([
+            "mock-openai",
+            "--token-mean",
+            "42",
+            "--admin-token",
+            "s3cret",
+            "--response-header",
+            "X-Test: yes",
+        ]);
+        let app_state = web::Data::new(AppState {
+            admin_token: args.admin_token.clone(),
+            config: serde_json::to_value(&args).unwrap(),
+            ..Default::default()
+        });
+
+        let app = test::init_service(
+            App::new()
+                .app_data(app_state)
+                .route("/debug/config", web::get().to(debug_config_handler))
+                .route("/admin/health", web::post().to(admin_health_handler)),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/debug/config").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["config"]["token_mean"], 42.0);
+        assert_eq!(body["config"]["response_headers"][0], "x-test: yes");
+        assert_eq!(body["config"]["stream_delay_dist"], "constant");
+        // The admin token is a secret and never echoed
+        assert!(body["config"].get("admin_token").is_none());
+        assert_eq!(body["runtime"]["health_status"], 200);
+
+        let req = test::TestRequest::post()
+            .uri("/admin/health")
+            .insert_header(("Authorization", "Bearer s3cret"))
+            .set_json(serde_json::json!({ "status": 503 }))
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+
+        let req = test::TestRequest::get().uri("/debug/config").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["runtime"]["health_status"], 503);
+    }
+
+    #[actix_web::test]
+    async fn test_debug_echo_request() {
+        let app = test::init_service(App::new().route(
+            "/debug/echo-request",
+            web::post().to(debug_echo_request_handler),
+        ))
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/debug/echo-request")
+            .set_json(serde_json::json!({
+                "model": "gpt-4-mock",
+                "max_tokens": 12
+            }))
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["model"], "gpt-4-mock");
+        assert_eq!(body["max_tokens"], 12);
+        // Fields the client did not send are reported explicitly
+        let obj = body.as_object().unwrap();
+        for field in [
+            "messages",
+            "temperature",
+            "n",
+            "stream",
+            "tools",
+            "prompt",
+            "x_mock_content",
+        ] {
+            assert!(obj[field].is_null(), "{} should be null", field);
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_chat_verbosity_tiers() {
+        let app_state = web::Data::new(AppState {
+            articles: vec![std::sync::Arc::new(lipsum::lipsum_words(2000))],
+            token_mean: 100.0,
+            token_stddev: 10.0,
+            ..Default::default()
+        });
+
+        let app = test::init_service(App::new().app_data(app_state).route(
+            "/v1/chat/completions",
+            web::post().to(chat_completions_handler),
+        ))
+        .await;
+
+        let mut average = std::collections::HashMap::new();
+        for verbosity in ["low", "high"] {
+            let mut total = 0;
+            for _ in 0..10 {
+                let req = test::TestRequest::post()
+                    .uri("/v1/chat/completions")
+                    .set_json(serde_json::json!({
+                        "model": "gpt-4-mock",
+                        "messages": [{"role": "user", "content": "hello"}],
+                        "verbosity": verbosity
+                    }))
+                    .to_request();
+                let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+                total += body["usage"]["completion_tokens"].as_u64().unwrap();
+            }
+            average.insert(verbosity, total as f64 / 10.0);
+        }
+        assert!(
+            average["high"] > average["low"] * 4.0,
+            "low={} high={}",
+            average["low"],
+            average["high"]
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_copy_articles_output_identical() {
+        let mut outputs = Vec::new();
+        for copy_articles in [false, true] {
+            let app_state = web::Data::new(AppState {
+                articles: vec![std::sync::Arc::new(lipsum::lipsum_words(200))],
+                token_mean: 40.0,
+                token_stddev: 0.0,
+                copy_articles,
+                ..Default::default()
+            });
+
+            let app = test::init_service(
+                App::new()
+                    .app_data(app_state)
+                    .route(
+                        "/v1/chat/completions",
+                        web::post().to(chat_completions_handler),
+                    )
+                    .route("/v1/completions", web::post().to(completions_handler)),
+            )
+            .await;
+
+            let req = test::TestRequest::post()
+                .uri("/v1/chat/completions")
+                .set_json(serde_json::json!({
+                    "model": "gpt-4-mock",
+                    "messages": [{"role": "user", "content": "hello"}]
+                }))
+                .to_request();
+            let chat: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+
+            let req = test::TestRequest::post()
+                .uri("/v1/completions")
+                .set_json(serde_json::json!({ "model": "gpt-4-mock", "prompt": "hello" }))
+                .to_request();
+            let completion: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+
+            outputs.push((
+                chat["choices"][0]["message"]["content"].clone(),
+                completion["choices"][0]["text"].clone(),
+            ));
+        }
+        assert_eq!(outputs[0], outputs[1]);
+    }
+
+    #[actix_web::test]
+    async fn test_streaming_content_filter_stops_early() {
+        let app_state = web::Data::new(AppState {
+            articles: vec![std::sync::Arc::new(lipsum::lipsum_words(400))],
+            token_mean: 64.0,
+            token_stddev: 0.0,
+            content_filter_rate: 1.0,
+            ..Default::default()
+        });
+
+        let app = test::init_service(App::new().app_data(app_state).route(
+            "/v1/chat/completions",
+            web::post().to(chat_completions_handler),
+        ))
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/v1/chat/completions")
+            .set_json(serde_json::json!({
+                "model": "gpt-4-mock",
+                "messages": [{"role": "user", "content": "hello"}],
+                "stream": true
+            }))
+            .to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        let body = std::str::from_utf8(&body).unwrap();
+
+        let frames = sse_frames(body.as_bytes());
+        let last = frames.last().expect("at least one frame");
+        assert_eq!(last["choices"][0]["finish_reason"], "content_filter");
+        assert!(body.ends_with("data: [DONE]\n\n"));
+
+        let emitted_chars: usize = frames
+            .iter()
+            .filter_map(|f| f["choices"][0]["delta"]["content"].as_str())
+            .map(|c| c.chars().count())
+            .sum();
+        assert!(emitted_chars < tokens_to_chars(64));
+    }
+
+    #[actix_web::test]
+    async fn test_streaming_tool_call_deltas() {
+        let app_state = web::Data::new(AppState {
+            articles: vec![std::sync::Arc::new(lipsum::lipsum_words(400))],
+            max_tool_calls: 3,
+            ..Default::default()
+        });
+
+        let app = test::init_service(App::new().app_data(app_state).route(
+            "/v1/chat/completions",
+            web::post().to(chat_completions_handler),
+        ))
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/v1/chat/completions")
+            .set_json(serde_json::json!({
+                "model": "gpt-4-mock",
+                "messages": [{"role": "user", "content": "weather?"}],
+                "stream": true,
+                "tools": [{"type": "function", "function": {
+                    "name": "get_weather",
+                    "parameters": {"type": "object", "properties": {
+                        "city": {"type": "string"},
+                        "unit": {"type": "string", "enum": ["celsius", "fahrenheit"]}
+                    }}
+                }}]
+            }))
+            .to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        let body = std::str::from_utf8(&body).unwrap();
+
+        let frames = sse_frames(body.as_bytes());
+        assert!(frames
+            .iter()
+            .all(|f| f["choices"][0]["delta"]["content"].is_null()));
+        assert_eq!(
+            frames.last().unwrap()["choices"][0]["finish_reason"],
+            "tool_calls"
+        );
+
+        // Reassemble calls from their fragments, keyed by `index`
+        let mut calls: Vec<(String, String)> = Vec::new();
+        for delta in frames
+            .iter()
+            .filter_map(|f| f["choices"][0]["delta"]["tool_calls"].as_array())
+            .flatten()
+        {
+            let index = delta["index"].as_u64().unwrap() as usize;
+            if index == calls.len() {
+                assert!(delta["id"].as_str().unwrap().starts_with("call_"));
+                calls.push((String::new(), String::new()));
+            }
+            let (name, arguments) = &mut calls[index];
+            if let Some(fragment) = delta["function"]["name"].as_str() {
+                name.push_str(fragment);
+            }
+            arguments.push_str(delta["function"]["arguments"].as_str().unwrap());
+        }
+        assert!(!calls.is_empty());
+        for (name, arguments) in calls {
+            assert_eq!(name, "get_weather");
+            let arguments: serde_json::Value = serde_json::from_str(&arguments).unwrap();
+            assert_eq!(arguments["unit"], "celsius");
+        }
+        // Arguments arrive in more than one fragment per call
+        let fragments = frames
+            .iter()
+            .filter(|f| f["choices"][0]["delta"]["tool_calls"][0]["id"].is_null())
+            .filter(|f| f["choices"][0]["delta"]["tool_calls"].is_array())
+            .count();
+        assert!(fragments > 1);
+    }
+
+    #[actix_web::test]
+    async fn test_streaming_sse_retry_line() {
+        for sse_retry_ms in [None, Some(1500)] {
+            let app_state = web::Data::new(AppState {
+                sse_retry_ms,
+                ..Default::default()
+            });
+
+            let app = test::init_service(App::new().app_data(app_state).route(
+                "/v1/chat/completions",
+                web::post().to(chat_completions_handler),
+            ))
+            .await;
+
+            let req = test::TestRequest::post()
+                .uri("/v1/chat/completions")
+                .set_json(serde_json::json!({
+                    "model": "gpt-4-mock",
+                    "messages": [{"role": "user", "content": "hello"}],
+                    "stream": true
+                }))
+                .to_request();
+            let body = test::call_and_read_body(&app, req).await;
+            let body = std::str::from_utf8(&body).unwrap();
+
+            match sse_retry_ms {
+                Some(ms) => assert!(body.starts_with(&format!("retry: {}\n\n", ms))),
+                None => assert!(!body.contains("retry:")),
+            }
+            assert!(body.ends_with("data: [DONE]\n\n"));
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_stream_keepalive_max() {
+        for (max, thinking_delay_ms) in [(3, 5000), (0, 100)] {
+            let app_state = web::Data::new(AppState {
+                articles: vec![std::sync::Arc::new("hello world test".to_string())],
+                thinking_delay_ms,
+                stream_keepalive_ms: 10,
+                stream_keepalive_max: max,
+                ..Default::default()
+            });
+
+            let app = test::init_service(App::new().app_data(app_state).route(
+                "/v1/chat/completions",
+                web::post().to(chat_completions_handler),
+            ))
+            .await;
+
+            let req = test::TestRequest::post()
+                .uri("/v1/chat/completions")
+                .set_json(serde_json::json!({
+                    "model": "gpt-4-mock",
+                    "messages": [{"role": "user", "content": "hello"}],
+                    "stream": true
+                }))
+                .to_request();
+            let started = std::time::Instant::now();
+            let body = test::call_and_read_body(&app, req).await;
+            let body = std::str::from_utf8(&body).unwrap();
+
+            let first_content = body.find("data: ").unwrap();
+            let pings = body[..first_content].matches(": keep-alive\n\n").count();
+            assert!(!body[first_content..].contains(": keep-alive"));
+            if max > 0 {
+                assert_eq!(pings, max);
+                // The stream moved on without waiting out the whole delay
+                assert!(started.elapsed() < Duration::from_millis(thinking_delay_ms));
+            } else {
+                assert!(pings >= 3, "{} pings", pings);
+            }
+            assert!(body.ends_with("data: [DONE]\n\n"));
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_done_sentinel() {
+        let app_state = web::Data::new(AppState {
+            articles: vec![std::sync::Arc::new("hello world test".to_string())],
+            done_sentinel: "[END]".into(),
+            ..Default::default()
+        });
+
+        let app = test::init_service(
+            App::new()
+                .app_data(app_state)
+                .route(
+                    "/v1/chat/completions",
+                    web::post().to(chat_completions_handler),
+                )
+                .route("/v1/completions", web::post().to(completions_handler)),
+        )
+        .await;
+
+        let requests = [
+            (
+                "/v1/chat/completions",
+                serde_json::json!({
+                    "model": "gpt-4-mock",
+                    "messages": [{"role": "user", "content": "hello"}],
+                    "stream": true
+                }),
+            ),
+            (
+                "/v1/completions",
+                serde_json::json!({ "model": "gpt-4-mock", "prompt": "hello", "stream": true }),
+            ),
+        ];
+        for (uri, payload) in requests {
+            let req = test::TestRequest::post()
+                .uri(uri)
+                .set_json(payload)
+                .to_request();
+            let body = test::call_and_read_body(&app, req).await;
+            let body = std::str::from_utf8(&body).unwrap();
+            let last = body.trim_end().rsplit("\n\n").next().unwrap();
+            assert_eq!(last, "data: [END]", "{}", uri);
+            assert!(!body.contains("[DONE]"), "{}", uri);
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_streaming_sse_event_name() {
+        let app_state = web::Data::new(AppState {
+            articles: vec![std::sync::Arc::new("hello world test".to_string())],
+            stream_token_samples: std::sync::Arc::new(vec![1]),
+            sse_retry_ms: Some(100),
+            sse_event_name: Some("message".into()),
+            ..Default::default()
+        });
+
+        let app = test::init_service(App::new().app_data(app_state).route(
+            "/v1/chat/completions",
+            web::post().to(chat_completions_handler),
+        ))
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/v1/chat/completions")
+            .set_json(serde_json::json!({
+                "model": "gpt-4-mock",
+                "messages": [{"role": "user", "content": "hello"}],
+                "stream": true
+            }))
+            .to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        let body = std::str::from_utf8(&body).unwrap();
+
+        let frames: Vec<_> = body.split_terminator("\n\n").collect();
+        assert_eq!(frames[0], "retry: 100");
+        assert!(frames.len() > 2);
+        for frame in &frames[1..] {
+            let (event, data) = frame.split_once('\n').unwrap();
+            assert_eq!(event, "event: message");
+            assert!(data.starts_with("data: "), "{:?}", frame);
+        }
+        assert!(body.ends_with("event: message\ndata: [DONE]\n\n"));
+    }
+
+    #[actix_web::test]
+    async fn test_system_fingerprint_follows_seed() {
+        let app_state = web::Data::new(AppState {
+            articles: vec![std::sync::Arc::new("hello world test".to_string())],
+            ..Default::default()
+        });
+
+        let app = test::init_service(App::new().app_data(app_state).route(
+            "/v1/chat/completions",
+            web::post().to(chat_completions_handler),
+        ))
+        .await;
+
+        let fingerprint = |seed: Option<u64>| {
+            let app = &app;
+            async move {
+                let mut payload = serde_json::json!({
+                    "model": "gpt-4-mock",
+                    "messages": [{"role": "user", "content": "hello"}]
+                });
+                if let Some(seed) = seed {
+                    payload["seed"] = seed.into();
+                }
+                let req = test::TestRequest::post()
+                    .uri("/v1/chat/completions")
+                    .set_json(payload)
+                    .to_request();
+                let body: serde_json::Value = test::call_and_read_body_json(app, req).await;
+                body.get("system_fingerprint").cloned()
+            }
+        };
+
+        let first = fingerprint(Some(7)).await.expect("seeded fingerprint");
+        assert_eq!(fingerprint(Some(7)).await, Some(first.clone()));
+        assert_ne!(fingerprint(Some(8)).await, Some(first));
+        assert_eq!(fingerprint(None).await, None);
+
+        let state = AppState::default();
+        let other_config = AppState {
+            token_mean: state.token_mean + 1.0,
+            ..Default::default()
+        };
+        assert_ne!(
+            system_fingerprint(&state, 7, "gpt-4-mock"),
+            system_fingerprint(&other_config, 7, "gpt-4-mock")
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_seeded_stream_matches_non_streaming() {
+        let app_state = web::Data::new(AppState {
+            articles: (0..16)
+                .map(|_| std::sync::Arc::new(lipsum::lipsum_words(300)))
+                .collect(),
+            stream_token_samples: std::sync::Arc::new(vec![1, 3, 7]),
+            ..Default::default()
+        });
+
+        let app = test::init_service(App::new().app_data(app_state).route(
+            "/v1/chat/completions",
+            web::post().to(chat_completions_handler),
+        ))
+        .await;
+
+        for seed in [1u64, 42, 9001] {
+            let payload = |stream: bool| {
+                serde_json::json!({
+                    "model": "gpt-4-mock",
+                    "messages": [{"role": "user", "content": "hello"}],
+                    "seed": seed,
+                    "stream": stream
+                })
+            };
+
+            let req = test::TestRequest::post()
+                .uri("/v1/chat/completions")
+                .set_json(payload(false))
+                .to_request();
+            let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+            let expected = body["choices"][0]["message"]["content"]
+                .as_str()
+                .unwrap()
+                .to_string();
+
+            let req = test::TestRequest::post()
+                .uri("/v1/chat/completions")
+                .set_json(payload(true))
+                .to_request();
+            let body = test::call_and_read_body(&app, req).await;
+            let streamed: String = sse_frames(&body)
+                .iter()
+                .filter_map(|v| v["choices"][0]["delta"]["content"].as_str())
+                .collect();
+
+            assert_eq!(streamed.as_bytes(), expected.as_bytes());
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_cjk_content_streams_whole_chars() {
+        let config = ArticleGenConfig {
+            charset: ContentCharset::Cjk,
+            ..ArticleGenConfig::new(8, 256.0, 64.0)
+        };
+        let articles =
+            pregenerate_articles_with_progress(&config, &mut request_rng(Some(3)), |_| {});
+        let app_state = web::Data::new(AppState {
+            articles,
+            stream_token_samples: std::sync::Arc::new(vec![1, 2, 5]),
+            ..Default::default()
+        });
+        let app = test::init_service(App::new().app_data(app_state).route(
+            "/v1/chat/completions",
+            web::post().to(chat_completions_handler),
+        ))
+        .await;
+
+        for max_tokens in [1, 3, 17, 4096] {
+            let payload = |stream: bool| {
+                serde_json::json!({
+                    "model": "gpt-4-mock",
+                    "messages": [{"role": "user", "content": "hello"}],
+                    "max_tokens": max_tokens,
+                    "seed": max_tokens,
+                    "stream": stream
+                })
+            };
+            let req = test::TestRequest::post()
+                .uri("/v1/chat/completions")
+                .set_json(payload(false))
+                .to_request();
+            let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+            let content = body["choices"][0]["message"]["content"].as_str().unwrap();
+            assert!(content.chars().any(|c| c.len_utf8() == 3), "{:?}", content);
+
+            let req = test::TestRequest::post()
+                .uri("/v1/chat/completions")
+                .set_json(payload(true))
+                .to_request();
+            let body = test::call_and_read_body(&app, req).await;
+            // Every chunk is valid UTF-8 JSON, so no char was split
+            let streamed: String = sse_frames(&body)
+                .iter()
+                .filter_map(|v| v["choices"][0]["delta"]["content"].as_str())
+                .collect();
+            assert_eq!(streamed, content);
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_streaming_logprobs_match_delta_tokens() {
+        let app_state = web::Data::new(AppState {
+            articles: vec![std::sync::Arc::new(
+                "lorem ipsum dolor sit amet ".repeat(20),
+            )],
+            stream_token_samples: std::sync::Arc::new(vec![1, 3, 5]),
+            ..Default::default()
+        });
+        let app = test::init_service(App::new().app_data(app_state).route(
+            "/v1/chat/completions",
+            web::post().to(chat_completions_handler),
+        ))
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/v1/chat/completions")
+            .set_json(serde_json::json!({
+                "model": "gpt-4-mock",
+                "messages": [{"role": "user", "content": "hello"}],
+                "max_tokens": 20,
+                "logprobs": true,
+                "top_logprobs": 2,
+                "stream": true
+            }))
+            .to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        let frames = sse_frames(&body);
+
+        let (last, chunks) = frames.split_last().unwrap();
+        assert!(chunks.len() > 1);
+        for chunk in chunks {
+            let choice = &chunk["choices"][0];
+            let delta = choice["delta"]["content"].as_str().unwrap();
+            let tokens = choice["logprobs"]["content"].as_array().unwrap();
+            let joined: String = tokens
+                .iter()
+                .map(|t| t["token"].as_str().unwrap())
+                .collect();
+            assert_eq!(joined, delta);
+            assert!(tokens
+                .iter()
+                .all(|t| t["top_logprobs"].as_array().unwrap().len() == 2));
+        }
+        assert!(last["choices"][0]["logprobs"].is_null());
+        assert_eq!(last["choices"][0]["finish_reason"], "stop");
+    }
+
+    #[actix_web::test]
+    async fn test_global_tps_caps_concurrent_streams() {
+        let tps = 400.0;
+        let app_state = web::Data::new(AppState {
+            articles: vec![std::sync::Arc::new(
+                "lorem ipsum dolor sit amet ".repeat(40),
+            )],
+            stream_token_samples: std::sync::Arc::new(vec![2, 3]),
+            global_tps: Some(Arc::new(crate::throughput::GlobalTokenRate::new(tps))),
+            ..Default::default()
+        });
+        let app = test::init_service(App::new().app_data(app_state).route(
+            "/v1/chat/completions",
+            web::post().to(chat_completions_handler),
+        ))
+        .await;
+
+        let stream_tokens = || async {
+            let req = test::TestRequest::post()
+                .uri("/v1/chat/completions")
+                .set_json(serde_json::json!({
+                    "model": "gpt-4-mock",
+                    "messages": [{"role": "user", "content": "hello"}],
+                    "max_tokens": 40,
+                    "stream": true,
+                    "stream_options": {"include_usage": true}
+                }))
+                .to_request();
+            let body = test::call_and_read_body(&app, req).await;
+            let usage = sse_frames(&body)
+                .into_iter()
+                .find(|v| v["usage"].is_object())
+                .unwrap();
+            usage["usage"]["completion_tokens"].as_u64().unwrap()
+        };
+
+        let started = Instant::now();
+        let (a, b) = futures::join!(stream_tokens(), stream_tokens());
+        let elapsed = started.elapsed().as_secs_f64();
+        // Both streams share one budget, so together they take at least as
+        // long as their combined tokens at the global rate
+        let min_secs = (a + b) as f64 / tps;
+        assert!(a + b >= 60, "{} + {}", a, b);
+        assert!(elapsed >= min_secs * 0.95, "{}s < {}s", elapsed, min_secs);
+    }
+
+    #[actix_web::test]
+    async fn test_delay_header_overrides_response_delay() {
+        let app_state = web::Data::new(AppState {
+            articles: vec![std::sync::Arc::new("alpha beta gamma delta ".repeat(10))],
+            stream_token_samples: std::sync::Arc::new(vec![4]),
+            response_delay_ms: 0,
+            ..Default::default()
+        });
+        let app = test::init_service(App::new().app_data(app_state).route(
+            "/v1/chat/completions",
+            web::post().to(chat_completions_handler),
+        ))
+        .await;
+
+        let timed = |stream: bool, delay: Option<&'static str>| {
+            let mut req = test::TestRequest::post()
+                .uri("/v1/chat/completions")
+                .set_json(serde_json::json!({
+                    "model": "gpt-4-mock",
+                    "messages": [{"role": "user", "content": "hi"}],
+                    "max_tokens": 12,
+                    "stream": stream
+                }));
+            if let Some(delay) = delay {
+                req = req.insert_header((DELAY_HEADER, delay));
+            }
+            let app = &app;
+            async move {
+                let started = Instant::now();
+                let resp = test::call_service(app, req.to_request()).await;
+                assert!(resp.status().is_success());
+                test::read_body(resp).await;
+                started.elapsed()
+            }
+        };
+
+        assert!(timed(false, None).await < Duration::from_millis(100));
+        assert!(timed(false, Some("150")).await >= Duration::from_millis(150));
+        assert!(timed(false, Some("soon")).await < Duration::from_millis(100));
+        // Three content chunks of 4 tokens, each followed by the delay
+        assert!(timed(true, None).await < Duration::from_millis(100));
+        assert!(timed(true, Some("60")).await >= Duration::from_millis(150));
+    }
+
+    #[actix_web::test]
+    async fn test_max_messages_rejects_longer_conversations() {
+        let app_state = web::Data::new(AppState {
+            articles: vec![std::sync::Arc::new("hello world".to_string())],
+            max_messages: 3,
+            ..Default::default()
+        });
+        let app = test::init_service(App::new().app_data(app_state).route(
+            "/v1/chat/completions",
+            web::post().to(chat_completions_handler),
+        ))
+        .await;
+
+        let post = |count: usize| {
+            let messages: Vec<_> = (0..count)
+                .map(|i| serde_json::json!({"role": "user", "content": format!("m{}", i)}))
+                .collect();
+            test::TestRequest::post()
+                .uri("/v1/chat/completions")
+                .set_json(serde_json::json!({"model": "gpt-4-mock", "messages": messages}))
+                .to_request()
+        };
+
+        let resp = test::call_service(&app, post(3)).await;
+        assert_eq!(resp.status(), 200);
+
+        let resp = test::call_service(&app, post(4)).await;
+        assert_eq!(resp.status(), 400);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["error"]["param"], "messages");
+        assert!(body["error"]["message"]
+            .as_str()
+            .unwrap()
+            .contains("at most 3 messages, got 4"));
+    }
+
+    #[actix_web::test]
+    async fn test_stream_granularity_sentence() {
+        let text = "First one. Second, with a comma! Is this third? trailing words";
+        let app_state = web::Data::new(AppState {
+            articles: vec![std::sync::Arc::new(text.to_string())],
+            stream_token_samples: std::sync::Arc::new(vec![1]),
+            stream_granularity: StreamGranularity::Sentence,
+            ..Default::default()
+        });
+        let app = test::init_service(App::new().app_data(app_state).route(
+            "/v1/chat/completions",
+            web::post().to(chat_completions_handler),
+        ))
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/v1/chat/completions")
+            .set_json(serde_json::json!({
+                "model": "gpt-4-mock",
+                "messages": [{"role": "user", "content": "hi"}],
+                "max_tokens": 1000,
+                "stream": true
+            }))
+            .to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        let frames: Vec<String> = sse_frames(&body)
+            .iter()
+            .filter_map(|v| v["choices"][0]["delta"]["content"].as_str())
+            .map(String::from)
+            .collect();
+        assert_eq!(
+            frames,
+            [
+                "First one.",
+                " Second, with a comma!",
+                " Is this third?",
+                " trailing words"
+            ]
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_preserve_whitespace_in_completions() {
+        let article = "\n  first line\nsecond   line of the article with plenty more words";
+        let mut texts = Vec::new();
+        for preserve_whitespace in [false, true] {
+            let app_state = web::Data::new(AppState {
+                articles: vec![std::sync::Arc::new(article.to_string())],
+                token_mean: 4.0,
+                token_stddev: 0.0,
+                preserve_whitespace,
+                ..Default::default()
+            });
+            let app = test::init_service(
+                App::new()
+                    .app_data(app_state)
+                    .route("/v1/completions", web::post().to(completions_handler)),
+            )
+            .await;
+            let req = test::TestRequest::post()
+                .uri("/v1/completions")
+                .set_json(serde_json::json!({"model": "gpt-4-mock", "prompt": "go"}))
+                .to_request();
+            let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+            texts.push(body["choices"][0]["text"].as_str().unwrap().to_string());
+        }
+        assert_eq!(texts[0], "first");
+        assert_eq!(texts[1], "\n  first ");
+    }
+
+    #[actix_web::test]
+    async fn test_service_tier_echo_and_latency() {
+        let app_state = web::Data::new(AppState {
+            articles: vec![std::sync::Arc::new("hello world".to_string())],
+            thinking_delay_ms: 40,
+            service_tier_factors: ServiceTierFactors {
+                default: 1.0,
+                flex: 4.0,
+            },
+            ..Default::default()
+        });
+        let app = test::init_service(App::new().app_data(app_state).route(
+            "/v1/chat/completions",
+            web::post().to(chat_completions_handler),
+        ))
+        .await;
+
+        let timed = |tier: &'static str| {
+            let req = test::TestRequest::post()
+                .uri("/v1/chat/completions")
+                .set_json(serde_json::json!({
+                    "model": "gpt-4-mock",
+                    "messages": [{"role": "user", "content": "hi"}],
+                    "service_tier": tier
+                }))
+                .to_request();
+            let app = &app;
+            async move {
+                let started = Instant::now();
+                let body: serde_json::Value = test::call_and_read_body_json(app, req).await;
+                (body["service_tier"].clone(), started.elapsed())
+            }
+        };
+
+        let (tier, default_elapsed) = timed("default").await;
+        assert_eq!(tier, "default");
+        let (tier, _) = timed("auto").await;
+        assert_eq!(tier, "default");
+        let (tier, flex_elapsed) = timed("flex").await;
+        assert_eq!(tier, "flex");
+        assert!(default_elapsed >= Duration::from_millis(40));
+        assert!(flex_elapsed >= Duration::from_millis(160));
+        assert!(flex_elapsed > default_elapsed + Duration::from_millis(80));
+
+        let req = test::TestRequest::post()
+            .uri("/v1/chat/completions")
+            .set_json(serde_json::json!({
+                "model": "gpt-4-mock",
+                "messages": [{"role": "user", "content": "hi"}],
+                "service_tier": "turbo"
+            }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 400);
+    }
+
+    #[actix_web::test]
+    async fn test_article_selection_fit_reaches_max_tokens() {
+        let mut articles: Vec<_> = (0..20)
+            .map(|_| std::sync::Arc::new("short article text".to_string()))
+            .collect();
+        articles.push(std::sync::Arc::new("lorem ipsum dolor ".repeat(200)));
+        let app_state = web::Data::new(AppState {
+            articles,
+            token_mean: 2000.0,
+            token_stddev: 0.0,
+            article_selection: ArticleSelection::Fit,
+            ..Default::default()
+        });
+        let app = test::init_service(App::new().app_data(app_state).route(
+            "/v1/chat/completions",
+            web::post().to(chat_completions_handler),
+        ))
+        .await;
+
+        for _ in 0..10 {
+            let req = test::TestRequest::post()
+                .uri("/v1/chat/completions")
+                .set_json(serde_json::json!({
+                    "model": "gpt-4-mock",
+                    "messages": [{"role": "user", "content": "write a lot"}],
+                    "max_tokens": 500
+                }))
+                .to_request();
+            let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+            let tokens = body["usage"]["completion_tokens"].as_u64().unwrap();
+            assert!((490..=500).contains(&tokens), "{} tokens", tokens);
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_cpu_burn_occupies_worker() {
+        // Two concurrent requests on the single-threaded test runtime: sleeps
+        // overlap, CPU burns cannot
+        let mut elapsed = Vec::new();
+        for (cpu_burn_ms, thinking_delay_ms) in [(0, 60), (60, 0)] {
+            let app_state = web::Data::new(AppState {
+                articles: vec![std::sync::Arc::new("hello world".to_string())],
+                cpu_burn_ms,
+                thinking_delay_ms,
+                ..Default::default()
+            });
+            let app = test::init_service(App::new().app_data(app_state).route(
+                "/v1/chat/completions",
+                web::post().to(chat_completions_handler),
+            ))
+            .await;
+
+            let call = || {
+                let req = test::TestRequest::post()
+                    .uri("/v1/chat/completions")
+                    .set_json(serde_json::json!({
+                        "model": "gpt-4-mock",
+                        "messages": [{"role": "user", "content": "hi"}]
+                    }))
+                    .to_request();
+                test::call_and_read_body(&app, req)
+            };
+            let started = Instant::now();
+            futures::join!(call(), call());
+            elapsed.push(started.elapsed());
+        }
+        let (slept, burned) = (elapsed[0], elapsed[1]);
+        assert!(slept < Duration::from_millis(110), "{:?}", slept);
+        assert!(burned >= Duration::from_millis(120), "{:?}", burned);
+    }
+
+    #[actix_web::test]
+    async fn test_azure_compat_prompt_filter_results() {
+        for azure_compat in [false, true] {
+            let app_state = web::Data::new(AppState {
+                articles: vec![std::sync::Arc::new("hello world".to_string())],
+                azure_compat,
+                ..Default::default()
+            });
+            let app = test::init_service(App::new().app_data(app_state).route(
+                "/v1/chat/completions",
+                web::post().to(chat_completions_handler),
+            ))
+            .await;
+
+            let req = test::TestRequest::post()
+                .uri("/v1/chat/completions")
+                .set_json(serde_json::json!({
+                    "model": "gpt-4-mock",
+                    "messages": [
+                        {"role": "system", "content": "You are helpful."},
+                        {"role": "user", "content": "Which weapon wins?"}
+                    ]
+                }))
+                .to_request();
+            let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+            if !azure_compat {
+                assert!(body.get("prompt_filter_results").is_none());
+                continue;
+            }
+            let results = &body["prompt_filter_results"][0];
+            assert_eq!(results["prompt_index"], 0);
+            let categories = &results["content_filter_results"];
+            assert_eq!(categories["violence"]["severity"], "low");
+            assert_eq!(categories["violence"]["filtered"], false);
+            assert_eq!(categories["hate"]["severity"], "safe");
+            assert_eq!(categories["self_harm"]["severity"], "safe");
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_model_chunk_delay_paces_streams_per_model() {
+        use actix_web::body::MessageBody;
+
+        let app_state = web::Data::new(AppState {
+            articles: vec![std::sync::Arc::new(
+                "Lorem ipsum dolor sit amet consectetur adipiscing elit".to_string(),
+            )],
+            stream_token_samples: std::sync::Arc::new(vec![2]),
+            token_mean: 8.0,
+            token_stddev: 0.0,
+            models: vec![
+                ModelInfo {
+                    chunk_delay_ms: Some(5),
+                    ..ModelInfo::new("fast-mock")
+                },
+                ModelInfo {
+                    chunk_delay_ms: Some(60),
+                    ..ModelInfo::new("slow-mock")
+                },
+            ],
+            ..Default::default()
+        });
+        let app = test::init_service(App::new().app_data(app_state).route(
+            "/v1/chat/completions",
+            web::post().to(chat_completions_handler),
+        ))
+        .await;
+
+        let stream = |model: &'static str| {
+            let req = test::TestRequest::post()
+                .uri("/v1/chat/completions")
+                .set_json(serde_json::json!({
+                    "model": model,
+                    "messages": [{"role": "user", "content": "hello"}],
+                    "stream": true
+                }))
+                .to_request();
+            let app = &app;
+            async move {
+                let resp = test::call_service(app, req).await;
+                let mut body = std::pin::pin!(resp.into_body());
+                let mut arrivals = Vec::new();
+                while let Some(frame) = std::future::poll_fn(|cx| body.as_mut().poll_next(cx)).await
+                {
+                    if frame.unwrap().starts_with(b"data: {") {
+                        arrivals.push(std::time::Instant::now());
+                    }
+                }
+                let gaps: Vec<_> = arrivals.windows(2).map(|w| w[1] - w[0]).collect();
+                gaps.iter().sum::<Duration>() / gaps.len() as u32
+            }
+        };
+
+        let (fast, slow) = futures::join!(stream("fast-mock"), stream("slow-mock"));
+        assert!(slow >= Duration::from_millis(50), "slow gap {:?}", slow);
+        assert!(fast < Duration::from_millis(40), "fast gap {:?}", fast);
+    }
+
+    #[actix_web::test]
+    async fn test_x_mock_content_is_returned_verbatim() {
+        let forced = "Exactly   this, with  odd spacing.\nAnd a second line.";
+        let app_state = web::Data::new(AppState {
+            articles: vec![std::sync::Arc::new("hello world test".to_string())],
+            stream_token_samples: std::sync::Arc::new(vec![2]),
+            ..Default::default()
+        });
+        let app = test::init_service(App::new().app_data(app_state).route(
+            "/v1/chat/completions",
+            web::post().to(chat_completions_handler),
+        ))
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/v1/chat/completions")
+            .set_json(serde_json::json!({
+                "model": "gpt-4-mock",
+                "messages": [{"role": "user", "content": "hello"}],
+                "x_mock_content": forced
+            }))
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["choices"][0]["message"]["content"], forced);
+        assert_eq!(
+            body["usage"]["completion_tokens"],
+            chars_to_tokens(forced.chars().count())
+        );
+
+        let req = test::TestRequest::post()
+            .uri("/v1/chat/completions")
+            .set_json(serde_json::json!({
+                "model": "gpt-4-mock",
+                "messages": [{"role": "user", "content": "hello"}],
+                "x_mock_content": forced,
+                "stream": true
+            }))
+            .to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        let streamed: String = sse_frames(&body)
+            .iter()
+            .filter_map(|event| event["choices"][0]["delta"]["content"].as_str())
+            .collect();
+        assert_eq!(streamed, forced);
+    }
+
+    #[actix_web::test]
+    async fn test_max_response_bytes_caps_large_responses() {
+        const MAX_BYTES: usize = 600;
+        let app_state = web::Data::new(AppState {
+            articles: vec![std::sync::Arc::new("lorem ipsum dolor ".repeat(500))],
+            token_mean: 1000.0,
+            token_stddev: 0.0,
+            max_response_bytes: MAX_BYTES,
+            ..Default::default()
+        });
+        let app = test::init_service(
+            App::new()
+                .app_data(app_state)
+                .route(
+                    "/v1/chat/completions",
+                    web::post().to(chat_completions_handler),
+                )
+                .route("/v1/completions", web::post().to(completions_handler)),
+        )
+        .await;
+
+        let chat = serde_json::json!({
+            "model": "gpt-4-mock",
+            "messages": [{"role": "user", "content": "hello"}]
+        });
+        let completion = serde_json::json!({"model": "gpt-4-mock", "prompt": "hello"});
+        for (uri, body, content) in [
+            ("/v1/chat/completions", &chat, "/choices/0/message/content"),
+            ("/v1/completions", &completion, "/choices/0/text"),
+        ] {
+            let req = test::TestRequest::post()
+                .uri(uri)
+                .set_json(body)
+                .to_request();
+            let raw = test::call_and_read_body(&app, req).await;
+            assert!(
+                raw.len() <= MAX_BYTES,
+                "{} body is {} bytes",
+                uri,
+                raw.len()
+            );
+            let json: serde_json::Value = serde_json::from_slice(&raw).unwrap();
+            assert_eq!(json["choices"][0]["finish_reason"], "length");
+            let content = json.pointer(content).unwrap().as_str().unwrap();
+            assert!(!content.is_empty());
+            assert_eq!(
+                json["usage"]["completion_tokens"],
+                chars_to_tokens(content.chars().count())
+            );
+        }
+
+        let req = test::TestRequest::post()
+            .uri("/v1/chat/completions")
+            .set_json(serde_json::json!({
+                "model": "gpt-4-mock",
+                "messages": [{"role": "user", "content": "hello"}],
+                "stream": true
+            }))
+            .to_request();
+        let raw = test::call_and_read_body(&app, req).await;
+        assert!(raw.len() <= MAX_BYTES, "stream is {} bytes", raw.len());
+        assert!(raw.starts_with(b"data: {"));
+    }
+
+    #[actix_web::test]
+    async fn test_reasoning_deltas_precede_content() {
+        let app_state = web::Data::new(AppState {
+            articles: vec![std::sync::Arc::new(
+                "Lorem ipsum dolor sit amet consectetur adipiscing elit sed do eiusmod tempor"
+                    .to_string(),
+            )],
+            stream_token_samples: std::sync::Arc::new(vec![2]),
+            token_mean: 8.0,
+            token_stddev: 0.0,
+            reasoning_tokens: 6,
+            reasoning_gap_ms: 50,
+            ..Default::default()
+        });
+        let app = test::init_service(App::new().app_data(app_state).route(
+            "/v1/chat/completions",
+            web::post().to(chat_completions_handler),
+        ))
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/v1/chat/completions")
+            .set_json(serde_json::json!({
+                "model": "gpt-4-mock",
+                "messages": [{"role": "user", "content": "hello"}],
+                "stream": true
+            }))
+            .to_request();
+        let started = std::time::Instant::now();
+        let body = test::call_and_read_body(&app, req).await;
+        assert!(started.elapsed() >= Duration::from_millis(50));
+
+        let phases: Vec<&str> = sse_frames(&body)
+            .iter()
+            .map(|frame| {
+                if frame["choices"][0]["delta"]
+                    .get("reasoning_content")
+                    .is_some()
+                {
+                    "reasoning"
+                } else {
+                    "content"
+                }
+            })
+            .collect();
+        let first_content = phases.iter().position(|p| *p == "content").unwrap();
+        assert!(first_content > 0, "{:?}", phases);
+        assert!(
+            phases[first_content..].iter().all(|p| *p == "content"),
+            "{:?}",
+            phases
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_max_stream_duration_cuts_stream_short() {
+        let app_state = web::Data::new(AppState {
+            articles: vec![std::sync::Arc::new("lorem ipsum dolor ".repeat(200))],
+            stream_token_samples: std::sync::Arc::new(vec![1]),
+            token_mean: 500.0,
+            token_stddev: 0.0,
+            response_delay_ms: 20,
+            max_stream_duration_ms: 150,
+            ..Default::default()
+        });
+        let app = test::init_service(
+            App::new()
+                .app_data(app_state)
+                .route(
+                    "/v1/chat/completions",
+                    web::post().to(chat_completions_handler),
+                )
+                .route("/v1/completions", web::post().to(completions_handler)),
+        )
+        .await;
+
+        let chat = serde_json::json!({
+            "model": "gpt-4-mock",
+            "messages": [{"role": "user", "content": "hello"}],
+            "stream": true
+        });
+        let completion = serde_json::json!({
+            "model": "gpt-4-mock",
+            "prompt": "hello",
+            "stream": true
+        });
+        for (uri, body) in [
+            ("/v1/chat/completions", chat),
+            ("/v1/completions", completion),
+        ] {
+            let req = test::TestRequest::post()
+                .uri(uri)
+                .set_json(body)
+                .to_request();
+            let started = std::time::Instant::now();
+            let body = test::call_and_read_body(&app, req).await;
+            // 500 tokens at 20ms a chunk would take ~10s
+            let elapsed = started.elapsed();
+            assert!(
+                elapsed < Duration::from_millis(300),
+                "{} took {:?}",
+                uri,
+                elapsed
+            );
+
+            let text = std::str::from_utf8(&body).unwrap();
+            assert!(text.ends_with("data: [DONE]\n\n"));
+            let frames = sse_frames(&body);
+            let finish = frames.last().unwrap();
+            assert_eq!(
+                finish["choices"][0]["finish_reason"], "length",
+                "{}",
+                finish
+            );
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_completions_best_of_bills_all_candidates() {
+        let app_state = web::Data::new(AppState {
+            articles: vec![std::sync::Arc::new(
+                "lorem ipsum dolor sit amet ".repeat(40),
+            )],
+            token_mean: 20.0,
+            token_stddev: 8.0,
+            ..Default::default()
+        });
+        let app = test::init_service(
+            App::new()
+                .app_data(app_state)
+                .route("/v1/completions", web::post().to(completions_handler)),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/v1/completions")
+            .set_json(serde_json::json!({
+                "model": "gpt-4-mock",
+                "prompt": "hello",
+                "best_of": 5
+            }))
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        let choices = body["choices"].as_array().unwrap();
+        assert_eq!(choices.len(), 1);
+        let returned = chars_to_tokens(choices[0]["text"].as_str().unwrap().chars().count());
+        let billed = body["usage"]["completion_tokens"].as_u64().unwrap() as usize;
+        // Five candidates of at least one token each, the longest returned
+        assert!(
+            billed >= returned + 4,
+            "billed {} returned {}",
+            billed,
+            returned
+        );
+        assert!(billed <= returned * 5);
+        assert_eq!(
+            body["usage"]["total_tokens"].as_u64().unwrap() as usize,
+            billed + body["usage"]["prompt_tokens"].as_u64().unwrap() as usize
+        );
+
+        let req = test::TestRequest::post()
+            .uri("/v1/completions")
+            .set_json(serde_json::json!({
+                "model": "gpt-4-mock",
+                "prompt": "hello",
+                "n": 2,
+                "best_of": 1
+            }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["error"]["param"], "best_of");
+
+        let req = test::TestRequest::post()
+            .uri("/v1/completions")
+            .set_json(serde_json::json!({
+                "model": "gpt-4-mock",
+                "prompt": "hello",
+                "n": 2,
+                "best_of": 3
+            }))
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        let choices = body["choices"].as_array().unwrap();
+        assert_eq!(choices.len(), 2);
+        assert_eq!(choices[0]["index"], 0);
+        assert_eq!(choices[1]["index"], 1);
+        let lens: Vec<usize> = choices
+            .iter()
+            .map(|c| c["text"].as_str().unwrap().chars().count())
+            .collect();
+        // Longest first, and the unreturned third candidate is still billed
+        assert!(lens[0] >= lens[1]);
+        let returned = chars_to_tokens(lens[0]) + chars_to_tokens(lens[1]);
+        let billed = body["usage"]["completion_tokens"].as_u64().unwrap() as usize;
+        assert!(billed > returned, "billed {} returned {}", billed, returned);
+    }
+
+    #[actix_web::test]
+    async fn test_prefill_delay_scales_with_prompt() {
+        let app_state = web::Data::new(AppState {
+            articles: vec![std::sync::Arc::new("hello world".to_string())],
+            prefill_ms_per_token: 0.5,
+            ..Default::default()
+        });
+        let app = test::init_service(App::new().app_data(app_state).route(
+            "/v1/chat/completions",
+            web::post().to(chat_completions_handler),
+        ))
+        .await;
+
+        let mut elapsed = Vec::new();
+        for prompt in ["hi".to_string(), "word ".repeat(400)] {
+            let req = test::TestRequest::post()
+                .uri("/v1/chat/completions")
+                .set_json(serde_json::json!({
+                    "model": "gpt-4-mock",
+                    "messages": [{"role": "user", "content": prompt}]
+                }))
+                .to_request();
+            let started = Instant::now();
+            let resp = test::call_service(&app, req).await;
+            assert!(resp.status().is_success());
+            elapsed.push(started.elapsed());
+        }
+        // ~500 prompt tokens at 0.5ms each
+        let (short, long) = (elapsed[0], elapsed[1]);
+        assert!(short < Duration::from_millis(50), "{:?}", short);
+        assert!(long >= Duration::from_millis(240), "{:?}", long);
+    }
+
+    #[actix_web::test]
+    async fn test_frequency_penalty_reduces_repetition() {
+        let app_state = web::Data::new(AppState {
+            articles: vec![std::sync::Arc::new("the cat and the dog. ".repeat(200))],
+            ..Default::default()
+        });
+        let app = test::init_service(App::new().app_data(app_state).route(
+            "/v1/chat/completions",
+            web::post().to(chat_completions_handler),
+        ))
+        .await;
+
+        let mut repeats = Vec::new();
+        for penalty in [0.0, 2.0] {
+            let req = test::TestRequest::post()
+                .uri("/v1/chat/completions")
+                .set_json(serde_json::json!({
+                    "model": "gpt-4-mock",
+                    "messages": [{"role": "user", "content": "hi"}],
+                    "max_tokens": 50,
+                    "frequency_penalty": penalty,
+                    "presence_penalty": penalty
+                }))
+                .to_request();
+            let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+            let content = body["choices"][0]["message"]["content"].as_str().unwrap();
+            repeats.push(content.matches("the ").count());
+        }
+        assert!(repeats[0] > 10, "{:?}", repeats);
+        assert_eq!(repeats[1], 1);
+
+        let req = test::TestRequest::post()
+            .uri("/v1/chat/completions")
+            .set_json(serde_json::json!({
+                "model": "gpt-4-mock",
+                "messages": [{"role": "user", "content": "hi"}],
+                "frequency_penalty": 2.5
+            }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_web::test]
+    async fn test_inject_control_chars_stays_valid_json() {
+        let app_state = web::Data::new(AppState {
+            articles: vec![std::sync::Arc::new(
+                "lorem ipsum dolor sit amet ".repeat(50),
+            )],
+            inject_control_chars: true,
+            ..Default::default()
+        });
+        let app = test::init_service(App::new().app_data(app_state).route(
+            "/v1/chat/completions",
+            web::post().to(chat_completions_handler),
+        ))
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/v1/chat/completions")
+            .set_json(serde_json::json!({
+                "model": "gpt-4-mock",
+                "messages": [{"role": "user", "content": "hi"}],
+                "max_tokens": 40
+            }))
+            .to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        assert!(std::str::from_utf8(&body).unwrap().contains("\\u0000"));
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let content = json["choices"][0]["message"]["content"].as_str().unwrap();
+        for c in ['\u{0}', '\u{200B}', '\u{1F980}', '\u{1B}'] {
+            assert!(content.contains(c), "missing {:?} in {:?}", c, content);
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_model_error_rate() {
+        let app_state = web::Data::new(AppState {
+            articles: vec![std::sync::Arc::new("hello world".to_string())],
+            models: vec![
+                ModelInfo {
+                    error_rate: 1.0,
+                    ..ModelInfo::new("flaky-mock")
+                },
+                ModelInfo::new("steady-mock"),
+            ],
+            ..Default::default()
+        });
+        let app = test::init_service(App::new().app_data(app_state).route(
+            "/v1/chat/completions",
+            web::post().to(chat_completions_handler),
+        ))
+        .await;
+
+        for _ in 0..10 {
+            for (model, expected) in [
+                ("flaky-mock", StatusCode::INTERNAL_SERVER_ERROR),
+                ("steady-mock", StatusCode::OK),
+            ] {
+                let req = test::TestRequest::post()
+                    .uri("/v1/chat/completions")
+                    .set_json(serde_json::json!({
+                        "model": model,
+                        "messages": [{"role": "user", "content": "hi"}]
+                    }))
+                    .to_request();
+                let resp = test::call_service(&app, req).await;
+                assert_eq!(resp.status(), expected, "{}", model);
+            }
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_empty_choices_rate() {
+        for rate in [0.0, 1.0] {
+            let app_state = web::Data::new(AppState {
+                articles: vec![std::sync::Arc::new("hello world test".to_string())],
+                empty_choices_rate: rate,
+                ..Default::default()
+            });
+            let app = test::init_service(App::new().app_data(app_state).route(
+                "/v1/chat/completions",
+                web::post().to(chat_completions_handler),
+            ))
+            .await;
+
+            for _ in 0..10 {
+                let req = test::TestRequest::post()
+                    .uri("/v1/chat/completions")
+                    .set_json(serde_json::json!({
+                        "model": "gpt-4-mock",
+                        "messages": [{"role": "user", "content": "hello"}]
+                    }))
+                    .to_request();
+                let resp = test::call_service(&app, req).await;
+                assert_eq!(resp.status(), StatusCode::OK);
+                let body: serde_json::Value = test::read_body_json(resp).await;
+                let choices = body["choices"].as_array().unwrap();
+                if rate == 1.0 {
+                    assert!(choices.is_empty());
+                    assert_eq!(body["usage"]["completion_tokens"], 0);
+                    assert_eq!(
+                        body["usage"]["total_tokens"],
+                        body["usage"]["prompt_tokens"]
+                    );
+                } else {
+                    assert_eq!(choices.len(), 1);
+                }
+            }
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_created_extreme_offset_saturates() {
+        for precision in [TimestampPrecision::Seconds, TimestampPrecision::Millis] {
+            for offset in [i64::MAX, i64::MIN] {
+                let state = AppState {
+                    created_offset_secs: offset,
+                    timestamp_precision: precision,
+                    ..Default::default()
+                };
+                let created = created_field(&state);
+                assert_eq!(created.signum(), offset.signum());
+            }
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_created_offset() {
+        let offset = -3600;
+        let app_state = web::Data::new(AppState {
+            articles: vec![std::sync::Arc::new("hello world test".to_string())],
+            created_offset_secs: offset,
+            ..Default::default()
+        });
+
+        let app = test::init_service(
+            App::new()
+                .app_data(app_state)
+                .route(
+                    "/v1/chat/completions",
+                    web::post().to(chat_completions_handler),
+                )
+                .route("/v1/completions", web::post().to(completions_handler)),
+        )
+        .await;
+
+        let requests = [
+            (
+                "/v1/chat/completions",
+                serde_json::json!({
+                    "model": "gpt-4-mock",
+                    "messages": [{"role": "user", "content": "hello"}]
+                }),
+            ),
+            (
+                "/v1/completions",
+                serde_json::json!({ "model": "gpt-4-mock", "prompt": "hello" }),
+            ),
+        ];
+        for (uri, payload) in requests {
+            let req = test::TestRequest::post()
+                .uri(uri)
+                .set_json(payload)
+                .to_request();
+            let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs() as i64;
+            let created = body["created"].as_i64().unwrap();
+            assert!(
+                (created - (now + offset)).abs() <= 2,
+                "{}: created {} vs now {}",
+                uri,
+                created,
+                now
+            );
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_timestamp_precision() {
+        for precision in [TimestampPrecision::Seconds, TimestampPrecision::Millis] {
+            let app_state = web::Data::new(AppState {
+                articles: vec![std::sync::Arc::new("hello world test".to_string())],
+                timestamp_precision: precision,
+                ..Default::default()
+            });
+            let app = test::init_service(App::new().app_data(app_state).route(
+                "/v1/chat/completions",
+                web::post().to(chat_completions_handler),
+            ))
+            .await;
+
+            let req = test::TestRequest::post()
+                .uri("/v1/chat/completions")
+                .set_json(serde_json::json!({
+                    "model": "gpt-4-mock",
+                    "messages": [{"role": "user", "content": "hello"}]
+                }))
+                .to_request();
+            let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+            let created = body["created"].as_i64().unwrap();
+            // Seconds have 10 digits until 2286, milliseconds 13
+            let expected = match precision {
+                TimestampPrecision::Seconds => 1_000_000_000..10_000_000_000,
+                TimestampPrecision::Millis => 1_000_000_000_000..10_000_000_000_000,
+            };
+            assert!(expected.contains(&created), "{:?}: {}", precision, created);
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_empty_pool_uses_fallback_text() {
+        let fallback = "the pool is empty so this text is served";
+        let app_state = web::Data::new(AppState {
+            articles: Vec::new(),
+            empty_pool_text: std::sync::Arc::new(fallback.to_string()),
+            token_mean: 1000.0,
+            token_stddev: 0.0,
+            ..Default::default()
+        });
+
+        let app = test::init_service(
+            App::new()
+                .app_data(app_state)
+                .route(
+                    "/v1/chat/completions",
+                    web::post().to(chat_completions_handler),
+                )
+                .route("/v1/completions", web::post().to(completions_handler)),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/v1/chat/completions")
+            .set_json(serde_json::json!({
+                "model": "gpt-4-mock",
+                "messages": [{"role": "user", "content": "hello"}]
+            }))
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["choices"][0]["message"]["content"], fallback);
+        assert_eq!(
+            body["usage"]["completion_tokens"],
+            chars_to_tokens(fallback.chars().count())
+        );
+
+        let req = test::TestRequest::post()
+            .uri("/v1/completions")
+            .set_json(serde_json::json!({ "model": "gpt-4-mock", "prompt": "hello" }))
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["choices"][0]["text"], fallback);
+
+        let req = test::TestRequest::post()
+            .uri("/v1/chat/completions")
+            .set_json(serde_json::json!({
+                "model": "gpt-4-mock",
+                "messages": [{"role": "user", "content": "hello"}],
+                "stream": true
+            }))
+            .to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        let streamed: String = sse_frames(&body)
+            .iter()
+            .filter_map(|v| v["choices"][0]["delta"]["content"].as_str())
+            .collect();
+        assert_eq!(streamed, fallback);
+    }
+
+    #[actix_web::test]
+    async fn test_disabled_endpoints_return_404() {
+        let app_state = web::Data::new(AppState {
+            articles: vec![std::sync::Arc::new("hello world test".to_string())],
+            ..Default::default()
+        });
+
+        let app = test::init_service(App::new().app_data(app_state).configure(|cfg| {
+            configure_routes(cfg, &[Endpoint::Embeddings, Endpoint::Completions])
+        }))
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/v1/embeddings")
+            .set_json(serde_json::json!({ "model": "text-embedding-3-small", "input": "hi" }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::NOT_FOUND);
+
+        let req = test::TestRequest::post()
+            .uri("/v1/completions")
+            .set_json(serde_json::json!({ "model": "gpt-4-mock", "prompt": "hello" }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::NOT_FOUND);
+
+        let req = test::TestRequest::post()
+            .uri("/v1/chat/completions")
+            .set_json(serde_json::json!({
+                "model": "gpt-4-mock",
+                "messages": [{"role": "user", "content": "hello"}]
+            }))
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+
+        let req = test::TestRequest::get().uri("/health").to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+    }
+
+    #[actix_web::test]
+    async fn test_unknown_route_error_shape() {
+        let app = test::init_service(
+            App::new()
+                .route("/health", web::get().to(health_handler))
+                .default_service(web::to(unknown_route_handler)),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/v1/does-not-exist")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::NOT_FOUND);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(
+            body["error"]["message"],
+            "Unknown request URL: POST /v1/does-not-exist"
+        );
+        assert_eq!(body["error"]["type"], "invalid_request_error");
+        assert!(body["error"]["code"].is_null());
+    }
+
+    #[actix_web::test]
+    async fn test_context_overflow_modes() {
+        for overflow in [ContextOverflow::Error, ContextOverflow::Truncate] {
+            let app_state = web::Data::new(AppState {
+                articles: vec![std::sync::Arc::new("hello world test".to_string())],
+                max_context_tokens: 100,
+                context_overflow: overflow,
+                ..Default::default()
+            });
+            let app = test::init_service(
+                App::new()
+                    .app_data(app_state)
+                    .route(
+                        "/v1/chat/completions",
+                        web::post().to(chat_completions_handler),
+                    )
+                    .route("/v1/completions", web::post().to(completions_handler)),
+            )
+            .await;
+
+            let oversized = "word ".repeat(200);
+            let chat = serde_json::json!({
+                "model": "gpt-4-mock",
+                "messages": [{"role": "user", "content": oversized}],
+                "max_tokens": 10
+            });
+            let completion = serde_json::json!({
+                "model": "gpt-4-mock",
+                "prompt": oversized,
+                "max_tokens": 10
+            });
+            for (uri, body) in [
+                ("/v1/chat/completions", chat),
+                ("/v1/completions", completion),
+            ] {
+                let req = test::TestRequest::post()
+                    .uri(uri)
+                    .set_json(body)
+                    .to_request();
+                let resp = test::call_service(&app, req).await;
+                let status = resp.status();
+                let body: serde_json::Value = test::read_body_json(resp).await;
+                match overflow {
+                    ContextOverflow::Error => {
+                        assert_eq!(status, actix_web::http::StatusCode::BAD_REQUEST);
+                        assert_eq!(body["error"]["code"], "context_length_exceeded");
+                    }
+                    // The prompt is cut to leave room for max_tokens
+                    ContextOverflow::Truncate => {
+                        assert!(status.is_success(), "{}", uri);
+                        assert_eq!(body["usage"]["prompt_tokens"], 90, "{}", uri);
+                    }
+                }
             }
 
-            let slice = if end_byte > start_byte {
-                &article[start_byte..end_byte]
-            } else {
-                // Fallback in case we couldn't find a whitespace; pick a single char
-                let next_byte = char_pos_to_byte_idx(&article, char_pos + 1).min(article.len());
-                &article[start_byte..next_byte]
+            // max_tokens alone fills the window: both modes reject the request
+            let req = test::TestRequest::post()
+                .uri("/v1/chat/completions")
+                .set_json(serde_json::json!({
+                    "model": "gpt-4-mock",
+                    "messages": [{"role": "user", "content": "hello"}],
+                    "max_tokens": 100
+                }))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{:?}", overflow);
+            let body: serde_json::Value = test::read_body_json(resp).await;
+            assert_eq!(body["error"]["code"], "context_length_exceeded");
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_context_length_exceeded() {
+        let app_state = web::Data::new(AppState {
+            articles: vec![std::sync::Arc::new("hello world test".to_string())],
+            max_context_tokens: 100,
+            ..Default::default()
+        });
+
+        let app = test::init_service(
+            App::new()
+                .app_data(app_state)
+                .route(
+                    "/v1/chat/completions",
+                    web::post().to(chat_completions_handler),
+                )
+                .route("/v1/completions", web::post().to(completions_handler)),
+        )
+        .await;
+
+        let oversized = "word ".repeat(200);
+        let req = test::TestRequest::post()
+            .uri("/v1/chat/completions")
+            .set_json(serde_json::json!({
+                "model": "gpt-4-mock",
+                "messages": [{"role": "user", "content": oversized}]
+            }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["error"]["code"], "context_length_exceeded");
+        assert_eq!(body["error"]["param"], "messages");
+        let message = body["error"]["message"].as_str().unwrap();
+        assert!(message.starts_with("This model's maximum context length is 100 tokens."));
+
+        // A short prompt fits until max_tokens pushes it over the limit
+        let req = test::TestRequest::post()
+            .uri("/v1/completions")
+            .set_json(serde_json::json!({ "model": "gpt-4-mock", "prompt": "hello" }))
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+
+        let req = test::TestRequest::post()
+            .uri("/v1/completions")
+            .set_json(serde_json::json!({
+                "model": "gpt-4-mock",
+                "prompt": "hello",
+                "max_tokens": 100
+            }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["error"]["code"], "context_length_exceeded");
+        assert_eq!(body["error"]["param"], "prompt");
+    }
+
+    #[actix_web::test]
+    async fn test_context_length_huge_max_tokens() {
+        for max_context_tokens in [0, 100] {
+            let app_state = web::Data::new(AppState {
+                articles: vec![std::sync::Arc::new("hello world test".to_string())],
+                max_context_tokens,
+                ..Default::default()
+            });
+            let app = test::init_service(
+                App::new()
+                    .app_data(app_state)
+                    .route(
+                        "/v1/chat/completions",
+                        web::post().to(chat_completions_handler),
+                    )
+                    .route("/v1/completions", web::post().to(completions_handler)),
+            )
+            .await;
+
+            let chat = serde_json::json!({
+                "model": "gpt-4-mock",
+                "messages": [{"role": "user", "content": "hello"}],
+                "max_tokens": usize::MAX
+            });
+            let completion = serde_json::json!({
+                "model": "gpt-4-mock",
+                "prompt": "hello",
+                "max_tokens": usize::MAX
+            });
+            for (uri, body) in [
+                ("/v1/chat/completions", chat),
+                ("/v1/completions", completion),
+            ] {
+                let req = test::TestRequest::post()
+                    .uri(uri)
+                    .set_json(body)
+                    .to_request();
+                let resp = test::call_service(&app, req).await;
+                if max_context_tokens == 0 {
+                    assert!(resp.status().is_success(), "{}", uri);
+                } else {
+                    assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{}", uri);
+                    let body: serde_json::Value = test::read_body_json(resp).await;
+                    assert_eq!(body["error"]["code"], "context_length_exceeded");
+                }
+            }
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_streaming_include_usage() {
+        let app_state = web::Data::new(AppState {
+            articles: vec![std::sync::Arc::new(lipsum::lipsum_words(300))],
+            stream_token_samples: std::sync::Arc::new(vec![2, 5]),
+            token_mean: 50.0,
+            token_stddev: 0.0,
+            ..Default::default()
+        });
+
+        let app = test::init_service(
+            App::new()
+                .app_data(app_state)
+                .route(
+                    "/v1/chat/completions",
+                    web::post().to(chat_completions_handler),
+                )
+                .route("/v1/completions", web::post().to(completions_handler)),
+        )
+        .await;
+
+        let prompt = "Say something";
+        let requests = [
+            (
+                "/v1/completions",
+                serde_json::json!({
+                    "model": "gpt-4-mock",
+                    "prompt": prompt,
+                    "stream": true,
+                    "stream_options": { "include_usage": true }
+                }),
+                "/choices/0/text",
+            ),
+            (
+                "/v1/chat/completions",
+                serde_json::json!({
+                    "model": "gpt-4-mock",
+                    "messages": [{"role": "user", "content": prompt}],
+                    "stream": true,
+                    "stream_options": { "include_usage": true }
+                }),
+                "/choices/0/delta/content",
+            ),
+        ];
+
+        for (uri, payload, text_pointer) in requests {
+            let req = test::TestRequest::post()
+                .uri(uri)
+                .set_json(payload)
+                .to_request();
+            let body = test::call_and_read_body(&app, req).await;
+            let body = std::str::from_utf8(&body).unwrap();
+            assert!(body.ends_with("data: [DONE]\n\n"));
+
+            let frames = sse_frames(body.as_bytes());
+            let text: String = frames
+                .iter()
+                .filter_map(|f| f.pointer(text_pointer).and_then(|t| t.as_str()))
+                .collect();
+            assert!(!text.is_empty());
+
+            let usage_frame = frames.last().unwrap();
+            assert_eq!(usage_frame["choices"], serde_json::json!([]));
+            let usage = &usage_frame["usage"];
+            let prompt_tokens = chars_to_tokens(prompt.chars().count());
+            let completion_tokens = chars_to_tokens(text.chars().count());
+            assert_eq!(usage["prompt_tokens"], prompt_tokens, "{}", uri);
+            assert_eq!(usage["completion_tokens"], completion_tokens, "{}", uri);
+            assert_eq!(usage["total_tokens"], prompt_tokens + completion_tokens);
+            assert_eq!(
+                frames.iter().filter(|f| f.get("usage").is_some()).count(),
+                1
+            );
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_model_replicas_suffix() {
+        let app_state = web::Data::new(AppState {
+            articles: vec![std::sync::Arc::new("hello world".to_string())],
+            model_replicas: 3,
+            ..Default::default()
+        });
+        let app = test::init_service(App::new().app_data(app_state).route(
+            "/v1/chat/completions",
+            web::post().to(chat_completions_handler),
+        ))
+        .await;
+
+        let mut seen = std::collections::HashSet::new();
+        for _ in 0..60 {
+            let req = test::TestRequest::post()
+                .uri("/v1/chat/completions")
+                .set_json(serde_json::json!({
+                    "model": "gpt-4-mock",
+                    "messages": [{"role": "user", "content": "hi"}]
+                }))
+                .to_request();
+            let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+            let model = body["model"].as_str().unwrap().to_string();
+            let replica: usize = model
+                .strip_prefix("gpt-4-mock-replica-")
+                .unwrap()
+                .parse()
+                .unwrap();
+            assert!((1..=3).contains(&replica), "{}", model);
+            seen.insert(replica);
+        }
+        assert!(seen.len() > 1, "only saw replicas {:?}", seen);
+    }
+
+    #[actix_web::test]
+    async fn test_model_alias_echo_policy() {
+        for (model_echo, expected) in [
+            (ModelEcho::Canonical, "gpt-4-mock"),
+            (ModelEcho::Requested, "gpt-4"),
+        ] {
+            let app_state = web::Data::new(AppState {
+                articles: vec![std::sync::Arc::new("hello world test".to_string())],
+                model_aliases: vec![("gpt-4".to_string(), "gpt-4-mock".to_string())],
+                model_echo,
+                ..Default::default()
+            });
+
+            let app = test::init_service(
+                App::new()
+                    .app_data(app_state)
+                    .route(
+                        "/v1/chat/completions",
+                        web::post().to(chat_completions_handler),
+                    )
+                    .route("/v1/completions", web::post().to(completions_handler)),
+            )
+            .await;
+
+            let req = test::TestRequest::post()
+                .uri("/v1/chat/completions")
+                .set_json(serde_json::json!({
+                    "model": "gpt-4",
+                    "messages": [{"role": "user", "content": "hello"}]
+                }))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert!(resp.status().is_success());
+            let body: serde_json::Value = test::read_body_json(resp).await;
+            assert_eq!(body["model"], expected);
+
+            let req = test::TestRequest::post()
+                .uri("/v1/completions")
+                .set_json(serde_json::json!({ "model": "gpt-4", "prompt": "hello" }))
+                .to_request();
+            let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+            assert_eq!(body["model"], expected);
+
+            // Unaliased names are echoed unchanged either way
+            let req = test::TestRequest::post()
+                .uri("/v1/completions")
+                .set_json(serde_json::json!({ "model": "other-mock", "prompt": "hello" }))
+                .to_request();
+            let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+            assert_eq!(body["model"], "other-mock");
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_token_bounds_clamp_sampled_counts() {
+        let state = AppState {
+            token_mean: 100.0,
+            token_stddev: 200.0,
+            token_min: 40,
+            token_max: Some(120),
+            ..Default::default()
+        };
+        let mut rng = request_rng(Some(1));
+        for scale in [0.3, 1.0, 2.5] {
+            for _ in 0..500 {
+                let tokens = sample_completion_tokens(&state, &mut rng, scale, None);
+                assert!((40..=120).contains(&tokens), "{} out of bounds", tokens);
+            }
+        }
+        // The request's max_tokens still caps below the floor
+        assert_eq!(sample_completion_tokens(&state, &mut rng, 1.0, Some(5)), 5);
+    }
+
+    #[actix_web::test]
+    async fn test_token_count_jitter() {
+        let lengths = |jitter: f64| {
+            let state = AppState {
+                token_count_jitter: jitter,
+                ..Default::default()
             };
+            (0..20)
+                .map(|_| sample_completion_tokens(&state, &mut request_rng(Some(7)), 1.0, None))
+                .collect::<std::collections::HashSet<_>>()
+        };
+
+        // Same seed without jitter: every request gets the same length
+        assert_eq!(lengths(0.0).len(), 1);
+        // With jitter the seed no longer pins the length
+        assert!(lengths(0.5).len() > 1);
+    }
+
+    #[actix_web::test]
+    async fn test_batch_polls_to_completion() {
+        let app_state = web::Data::new(AppState {
+            articles: vec![std::sync::Arc::new("hello world test".to_string())],
+            ..Default::default()
+        });
+        let app = test::init_service(
+            App::new()
+                .app_data(app_state)
+                .configure(|cfg| configure_routes(cfg, &[])),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/v1/batches")
+            .set_json(serde_json::json!({
+                "input_file_id": "file-abc",
+                "endpoint": "/v1/chat/completions",
+                "completion_window": "24h"
+            }))
+            .to_request();
+        let batch: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(batch["status"], "validating");
+        assert_eq!(batch["input_file_id"], "file-abc");
+        let id = batch["id"].as_str().unwrap().to_string();
+
+        let mut batch = batch;
+        for _ in 0..10 {
+            if batch["status"] == "completed" {
+                break;
+            }
+            let req = test::TestRequest::get()
+                .uri(&format!("/v1/batches/{}", id))
+                .to_request();
+            batch = test::call_and_read_body_json(&app, req).await;
+        }
+        assert_eq!(batch["status"], "completed");
+        assert_eq!(batch["request_counts"]["completed"], 3);
+
+        let output_file_id = batch["output_file_id"].as_str().unwrap();
+        let req = test::TestRequest::get()
+            .uri(&format!("/v1/files/{}/content", output_file_id))
+            .to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        let lines: Vec<serde_json::Value> = std::str::from_utf8(&body)
+            .unwrap()
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0]["custom_id"], "request-1");
+        assert_eq!(lines[0]["response"]["status_code"], 200);
+        assert_eq!(
+            lines[0]["response"]["body"]["object"],
+            OBJECT_CHAT_COMPLETION
+        );
+
+        let req = test::TestRequest::get()
+            .uri("/v1/batches/batch_missing")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::NOT_FOUND);
+    }
+
+    #[actix_web::test]
+    async fn test_files_upload_retrieve_delete() {
+        let app_state = web::Data::new(AppState {
+            max_file_bytes: 64,
+            ..Default::default()
+        });
+        let app = test::init_service(
+            App::new()
+                .app_data(app_state)
+                .configure(|cfg| configure_routes(cfg, &[])),
+        )
+        .await;
+        let multipart = |content: &str| {
+            format!(
+                "--B\r\nContent-Disposition: form-data; name=\"purpose\"\r\n\r\nbatch\r\n\
+                 --B\r\nContent-Disposition: form-data; name=\"file\"; filename=\"in.jsonl\"\r\n\r\n\
+                 {}\r\n--B--\r\n",
+                content
+            )
+        };
+        let upload = |body: String| {
+            test::TestRequest::post()
+                .uri("/v1/files")
+                .insert_header(("content-type", "multipart/form-data; boundary=B"))
+                .set_payload(body)
+                .to_request()
+        };
+
+        let file: serde_json::Value =
+            test::call_and_read_body_json(&app, upload(multipart("{\"custom_id\":\"x\"}"))).await;
+        let id = file["id"].as_str().unwrap().to_string();
+        assert!(id.starts_with("file-"));
+        assert_eq!(file["object"], "file");
+        assert_eq!(file["bytes"], 17);
+        assert_eq!(file["filename"], "in.jsonl");
+        assert_eq!(file["purpose"], "batch");
+
+        let req = test::TestRequest::get().uri("/v1/files").to_request();
+        let list: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(list["data"][0]["id"], id.as_str());
+
+        let req = test::TestRequest::get()
+            .uri(&format!("/v1/files/{}", id))
+            .to_request();
+        let meta: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(meta, file);
+
+        let req = test::TestRequest::get()
+            .uri(&format!("/v1/files/{}/content", id))
+            .to_request();
+        let content = test::call_and_read_body(&app, req).await;
+        assert_eq!(&content[..], b"{\"custom_id\":\"x\"}");
+
+        let req = test::TestRequest::delete()
+            .uri(&format!("/v1/files/{}", id))
+            .to_request();
+        let deleted: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(deleted["deleted"], true);
+
+        let req = test::TestRequest::get()
+            .uri(&format!("/v1/files/{}", id))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::NOT_FOUND);
+
+        // Larger than --max-file-bytes
+        let resp = test::call_service(&app, upload(multipart(&"x".repeat(65)))).await;
+        assert_eq!(
+            resp.status(),
+            actix_web::http::StatusCode::PAYLOAD_TOO_LARGE
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_canned_chat_response_verbatim() {
+        let canned = bytes::Bytes::from_static(b"{ \"id\": \"canned\",\n  \"choices\": [] }\n");
+        let app_state = web::Data::new(AppState {
+            canned_chat_response: Some(canned.clone()),
+            ..Default::default()
+        });
+        let app = test::init_service(App::new().app_data(app_state).route(
+            "/v1/chat/completions",
+            web::post().to(chat_completions_handler),
+        ))
+        .await;
 
-            let actual_chars_sent = slice.chars().count();
+        let req = test::TestRequest::post()
+            .uri("/v1/chat/completions")
+            .set_json(serde_json::json!({
+                "model": "gpt-4-mock",
+                "messages": [{"role": "user", "content": "hello"}]
+            }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(
+            resp.headers().get("content-type").unwrap(),
+            "application/json"
+        );
+        assert_eq!(test::read_body(resp).await, canned);
+    }
 
-            let chars_remaining_next = chars_remaining.saturating_sub(actual_chars_sent);
-            let char_pos_next = char_pos + actual_chars_sent;
+    #[actix_web::test]
+    async fn test_chat_multimodal_message() {
+        let app_state = web::Data::new(AppState {
+            articles: vec![std::sync::Arc::new("hello world test".to_string())],
+            ..Default::default()
+        });
+        let app = test::init_service(App::new().app_data(app_state).route(
+            "/v1/chat/completions",
+            web::post().to(chat_completions_handler),
+        ))
+        .await;
 
-            // Delay if requested
-            if response_delay_ms > 0 {
-                tokio::time::sleep(Duration::from_millis(response_delay_ms)).await;
-            }
+        let req = test::TestRequest::post()
+            .uri("/v1/chat/completions")
+            .set_json(serde_json::json!({
+                "model": "gpt-4-mock",
+                "messages": [{
+                    "role": "user",
+                    "content": [
+                        {"type": "text", "text": "What is in this image?"},
+                        {"type": "image_url", "image_url": {"url": "https://example.com/cat.png"}},
+                        {"type": "image_url", "image_url": {"url": "data:image/png;base64,AAAA", "detail": "low"}}
+                    ]
+                }]
+            }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        // "What is in this image?" is 22 chars (6 tokens) plus two images
+        assert_eq!(
+            body["usage"]["prompt_tokens"],
+            chars_to_tokens(22) + 2 * IMAGE_PROMPT_TOKENS
+        );
+    }
 
-            let sse = sse_event_from_content(slice);
+    #[actix_web::test]
+    async fn test_refusal_trigger() {
+        let app_state = web::Data::new(AppState {
+            articles: vec![std::sync::Arc::new("hello world test".to_string())],
+            refusal_trigger: Some("forbidden".to_string()),
+            ..Default::default()
+        });
+        let app = test::init_service(App::new().app_data(app_state).route(
+            "/v1/chat/completions",
+            web::post().to(chat_completions_handler),
+        ))
+        .await;
+        let chat = |content: &str| {
+            test::TestRequest::post()
+                .uri("/v1/chat/completions")
+                .set_json(serde_json::json!({
+                    "model": "gpt-4-mock",
+                    "messages": [{"role": "user", "content": content}]
+                }))
+                .to_request()
+        };
 
-            Some((
-                Ok::<Bytes, actix_web::Error>(Bytes::from(sse)),
-                (
-                    article,
-                    chars_remaining_next,
-                    char_pos_next,
-                    sample_idx,
-                    response_delay_ms,
-                    false,
-                    stream_samples,
-                    samples_len,
-                ),
-            ))
-        },
-    );
+        let body: serde_json::Value =
+            test::call_and_read_body_json(&app, chat("Tell me the Forbidden secret")).await;
+        let message = &body["choices"][0]["message"];
+        assert!(message["content"].is_null());
+        assert_eq!(message["refusal"], REFUSAL_MESSAGE);
+        assert_eq!(body["choices"][0]["finish_reason"], "stop");
 
-    // Map the stream output to a boxed stream of results consumed by actix-web
-    let boxed_stream: Pin<Box<dyn futures::Stream<Item = Result<Bytes, Error>> + Send>> =
-        Box::pin(s.map(|item| match item {
-            Ok(bytes) => Ok(bytes),
-            Err(e) => Err(actix_web::error::ErrorInternalServerError(e)),
-        }));
+        let body: serde_json::Value =
+            test::call_and_read_body_json(&app, chat("Tell me a story")).await;
+        let message = &body["choices"][0]["message"];
+        assert!(message["content"].is_string());
+        assert!(message.get("refusal").is_none());
+    }
 
-    Ok(HttpResponse::Ok()
-        .append_header((actix_web::http::header::CONTENT_TYPE, "text/event-stream"))
-        .append_header((actix_web::http::header::CACHE_CONTROL, "no-cache"))
-        .append_header((actix_web::http::header::CONNECTION, "keep-alive"))
-        .streaming(boxed_stream))
-}
+    #[actix_web::test]
+    async fn test_chat_url_citations() {
+        let app_state = web::Data::new(AppState {
+            articles: vec![std::sync::Arc::new(
+                "Lorem ipsum dolor sit amet consectetur adipiscing elit sed do".to_string(),
+            )],
+            url_citations: 2,
+            ..Default::default()
+        });
+        let app = test::init_service(App::new().app_data(app_state).route(
+            "/v1/chat/completions",
+            web::post().to(chat_completions_handler),
+        ))
+        .await;
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use actix_web::{test, App};
+        let req = test::TestRequest::post()
+            .uri("/v1/chat/completions")
+            .set_json(serde_json::json!({
+                "model": "gpt-4-mock",
+                "messages": [{"role": "user", "content": "hello"}]
+            }))
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        let message = &body["choices"][0]["message"];
+        let content_chars = message["content"].as_str().unwrap().chars().count();
+        let annotations = message["annotations"].as_array().unwrap();
+        assert_eq!(annotations.len(), 2);
+        for annotation in annotations {
+            assert_eq!(annotation["type"], "url_citation");
+            let citation = &annotation["url_citation"];
+            assert!(citation["url"].as_str().unwrap().starts_with("https://"));
+            let start = citation["start_index"].as_u64().unwrap() as usize;
+            let end = citation["end_index"].as_u64().unwrap() as usize;
+            assert!(start < end && end <= content_chars, "{}..{}", start, end);
+        }
+    }
 
     #[actix_web::test]
-    async fn test_completions_capped_by_max_tokens() {
-        let articles = vec![std::sync::Arc::new("hello world test".to_string())];
-        let stream_samples = vec![10, 20, 30];
+    async fn test_embeddings_endpoint() {
         let app_state = web::Data::new(AppState {
-            articles,
-            stream_token_samples: std::sync::Arc::new(stream_samples),
+            articles: Vec::new(),
+            stream_token_samples: std::sync::Arc::new(vec![]),
             stream_samples_idx: std::sync::atomic::AtomicUsize::new(0),
             token_mean: 100.0,
             token_stddev: 20.0,
             response_delay_ms: 0,
+            ..Default::default()
         });
 
         let app = test::init_service(
             App::new()
                 .app_data(app_state)
-                .route("/v1/completions", web::post().to(completions_handler)),
+                .route("/v1/embeddings", web::post().to(embeddings_handler)),
         )
         .await;
 
         let req = test::TestRequest::post()
-            .uri("/v1/completions")
+            .uri("/v1/embeddings")
             .set_json(serde_json::json!({
-                "model": "text-davinci-003",
-                "prompt": "hello",
-                "max_tokens": 5
+                "model": "text-embedding-3-small",
+                "input": "hello world"
             }))
             .to_request();
 
@@ -375,49 +5276,166 @@ mod tests {
     }
 
     #[actix_web::test]
-    async fn test_chat_streaming_sse() {
-        let articles = vec![std::sync::Arc::new(
-            "Lorem ipsum dolor sit amet consectetur adipiscing elit sed do eiusmod tempor"
-                .to_string(),
-        )];
-        let stream_samples = vec![5, 10, 15, 20, 10, 5];
+    async fn test_embeddings_delay_scales_with_batch() {
         let app_state = web::Data::new(AppState {
-            articles,
-            stream_token_samples: std::sync::Arc::new(stream_samples),
-            stream_samples_idx: std::sync::atomic::AtomicUsize::new(0),
-            token_mean: 50.0,
-            token_stddev: 10.0,
-            response_delay_ms: 0,
+            embedding_delay_ms: 5,
+            embedding_delay_per_input_ms: 20,
+            ..Default::default()
         });
 
-        let app = test::init_service(App::new().app_data(app_state).route(
-            "/v1/chat/completions",
-            web::post().to(chat_completions_handler),
-        ))
+        let app = test::init_service(
+            App::new()
+                .app_data(app_state)
+                .route("/v1/embeddings", web::post().to(embeddings_handler)),
+        )
+        .await;
+
+        let timed = |input: serde_json::Value| {
+            let req = test::TestRequest::post()
+                .uri("/v1/embeddings")
+                .set_json(serde_json::json!({
+                    "model": "text-embedding-3-small",
+                    "input": input
+                }))
+                .to_request();
+            let app = &app;
+            async move {
+                let started = Instant::now();
+                let body: EmbeddingResponse = test::call_and_read_body_json(app, req).await;
+                (body.data.len(), started.elapsed())
+            }
+        };
+
+        let (single_len, single) = timed("hello".into()).await;
+        let batch: Vec<_> = (0..10).map(|i| format!("input {}", i)).collect();
+        let (batch_len, batched) = timed(batch.into()).await;
+        assert_eq!(single_len, 1);
+        assert_eq!(batch_len, 10);
+        assert!(single >= Duration::from_millis(25));
+        assert!(batched >= Duration::from_millis(205));
+        // Nine more inputs cost roughly nine more per-input delays
+        let extra = batched - single;
+        assert!(extra >= Duration::from_millis(170), "{:?}", extra);
+        assert!(extra < Duration::from_millis(400), "{:?}", extra);
+    }
+
+    #[actix_web::test]
+    async fn test_embeddings_n_fan_out() {
+        let app_state = web::Data::new(AppState {
+            max_embedding_n: 4,
+            ..Default::default()
+        });
+
+        let app = test::init_service(
+            App::new()
+                .app_data(app_state)
+                .route("/v1/embeddings", web::post().to(embeddings_handler)),
+        )
         .await;
 
         let req = test::TestRequest::post()
-            .uri("/v1/chat/completions")
+            .uri("/v1/embeddings")
             .set_json(serde_json::json!({
-                "model": "gpt-4-mock",
-                "messages": [{"role": "user", "content": "hello"}],
-                "stream": true
+                "model": "text-embedding-3-small",
+                "input": "hello world",
+                "n": 2
             }))
             .to_request();
+        let body: EmbeddingResponse = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body.data.len(), 2);
+        assert_eq!(body.data[0].index, 0);
+        assert_eq!(body.data[1].index, 1);
+        assert_ne!(body.data[0].embedding, body.data[1].embedding);
 
+        let req = test::TestRequest::post()
+            .uri("/v1/embeddings")
+            .set_json(serde_json::json!({
+                "model": "text-embedding-3-small",
+                "input": "hello world",
+                "n": 5
+            }))
+            .to_request();
         let resp = test::call_service(&app, req).await;
-        assert!(resp.status().is_success());
+        assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["error"]["param"], "n");
     }
 
     #[actix_web::test]
-    async fn test_embeddings_endpoint() {
+    async fn test_embeddings_dimensions() {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(AppState::default()))
+                .route("/v1/embeddings", web::post().to(embeddings_handler)),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/v1/embeddings")
+            .set_json(serde_json::json!({"input": "hello", "dimensions": 256}))
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["data"][0]["embedding"].as_array().unwrap().len(), 256);
+
+        // Rejected before anything is allocated for them
+        for dimensions in [
+            serde_json::json!(0),
+            serde_json::json!(-1),
+            serde_json::json!(1e9 as i64),
+        ] {
+            let req = test::TestRequest::post()
+                .uri("/v1/embeddings")
+                .set_json(serde_json::json!({"input": "hello", "dimensions": dimensions}))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+            let body: serde_json::Value = test::read_body_json(resp).await;
+            assert_eq!(body["error"]["param"], "dimensions");
+            assert_eq!(body["error"]["type"], "invalid_request_error");
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_embeddings_list_pagination() {
+        for list_pagination in [false, true] {
+            let app_state = web::Data::new(AppState {
+                list_pagination,
+                ..Default::default()
+            });
+
+            let app = test::init_service(
+                App::new()
+                    .app_data(app_state)
+                    .route("/v1/embeddings", web::post().to(embeddings_handler)),
+            )
+            .await;
+
+            let req = test::TestRequest::post()
+                .uri("/v1/embeddings")
+                .set_json(serde_json::json!({
+                    "model": "text-embedding-3-small",
+                    "input": "hello world"
+                }))
+                .to_request();
+            let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+            assert_eq!(body["object"], "list");
+
+            if list_pagination {
+                assert_eq!(body["first_id"], "emb-0");
+                assert_eq!(body["last_id"], "emb-0");
+                assert_eq!(body["has_more"], false);
+            } else {
+                let keys: Vec<&String> = body.as_object().unwrap().keys().collect();
+                assert_eq!(keys, ["data", "object"]);
+            }
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_embeddings_int8_quantization() {
         let app_state = web::Data::new(AppState {
-            articles: Vec::new(),
-            stream_token_samples: std::sync::Arc::new(vec![]),
-            stream_samples_idx: std::sync::atomic::AtomicUsize::new(0),
-            token_mean: 100.0,
-            token_stddev: 20.0,
-            response_delay_ms: 0,
+            embedding_quantize: Some(EmbeddingQuantization::Int8),
+            ..Default::default()
         });
 
         let app = test::init_service(
@@ -434,9 +5452,23 @@ mod tests {
                 "input": "hello world"
             }))
             .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        let item = &body["data"][0];
+        assert_eq!(item["object"], "embedding.int8");
 
-        let resp = test::call_service(&app, req).await;
-        assert!(resp.status().is_success());
+        let values = item["embedding"].as_array().unwrap();
+        assert_eq!(values.len(), 128);
+        let values: Vec<i64> = values
+            .iter()
+            .map(|v| v.as_i64().expect("int8 embeddings are integers"))
+            .collect();
+        for &v in &values {
+            assert!((-127..=127).contains(&v), "{} out of int8 range", v);
+        }
+        // Both halves of the range are used, not a few small integers
+        assert!(values.iter().any(|&v| v < -32), "{:?}", values);
+        assert!(values.iter().any(|&v| v > 32), "{:?}", values);
+        assert_eq!(values.iter().map(|v| v.abs()).max(), Some(127));
     }
 
     #[actix_web::test]
@@ -448,6 +5480,7 @@ mod tests {
             token_mean: 100.0,
             token_stddev: 20.0,
             response_delay_ms: 0,
+            ..Default::default()
         });
 
         let app = test::init_service(
@@ -463,6 +5496,106 @@ mod tests {
         assert!(resp.status().is_success());
     }
 
+    #[actix_web::test]
+    async fn test_admin_health_override() {
+        let app_state = web::Data::new(AppState {
+            admin_token: Some("s3cret".to_string()),
+            ..Default::default()
+        });
+
+        let app = test::init_service(
+            App::new()
+                .app_data(app_state)
+                .route("/health", web::get().to(health_handler))
+                .route("/admin/health", web::post().to(admin_health_handler)),
+        )
+        .await;
+
+        let set_status = |token: &str, status: serde_json::Value| {
+            test::TestRequest::post()
+                .uri("/admin/health")
+                .insert_header(("Authorization", format!("Bearer {}", token)))
+                .set_json(serde_json::json!({ "status": status }))
+                .to_request()
+        };
+        let health = || test::TestRequest::get().uri("/health").to_request();
+
+        let resp = test::call_service(&app, set_status("wrong", 503.into())).await;
+        assert_eq!(resp.status(), 401);
+        assert_eq!(test::call_service(&app, health()).await.status(), 200);
+
+        let resp = test::call_service(&app, set_status("s3cret", 503.into())).await;
+        assert_eq!(resp.status(), 200);
+        let resp = test::call_service(&app, health()).await;
+        assert_eq!(resp.status(), 503);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["status"], "unhealthy");
+
+        let resp = test::call_service(&app, set_status("s3cret", 42.into())).await;
+        assert_eq!(resp.status(), 400);
+
+        let resp = test::call_service(&app, set_status("s3cret", 200.into())).await;
+        assert_eq!(resp.status(), 200);
+        let resp = test::call_service(&app, health()).await;
+        assert_eq!(resp.status(), 200);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["status"], "ok");
+    }
+
+    #[actix_web::test]
+    async fn test_version_endpoint() {
+        let app_state = web::Data::new(AppState::default());
+
+        let app = test::init_service(
+            App::new()
+                .app_data(app_state)
+                .route("/version", web::get().to(version_handler)),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/version").to_request();
+        let body: VersionResponse = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body.version, env!("CARGO_PKG_VERSION"));
+        assert!(!body.commit.is_empty());
+        assert!(body.started_at > 0);
+        assert!(body.uptime_secs < 60);
+    }
+
+    #[actix_web::test]
+    async fn test_metrics_latency_buckets() {
+        let buckets = crate::metrics::parse_latency_buckets("20,150,3000").unwrap();
+        let app_state = web::Data::new(AppState {
+            latency_histogram: crate::metrics::LatencyHistogram::new(&buckets),
+            ..Default::default()
+        });
+
+        let app = test::init_service(
+            App::new()
+                .app_data(app_state)
+                .wrap(actix_web::middleware::from_fn(
+                    crate::middleware::record_latency,
+                ))
+                .route("/health", web::get().to(health_handler))
+                .route("/metrics", web::get().to(metrics_handler)),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/health").to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+
+        let req = test::TestRequest::get().uri("/metrics").to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        let body = std::str::from_utf8(&body).unwrap();
+        for le in ["0.02", "0.15", "3", "+Inf"] {
+            let line = format!(
+                "mock_openai_request_duration_seconds_bucket{{le=\"{}\"}} 1",
+                le
+            );
+            assert!(body.contains(&line), "missing {:?} in {}", line, body);
+        }
+        assert!(!body.contains("le=\"0.005\""));
+    }
+
     #[actix_web::test]
     async fn test_models_endpoint() {
         let app_state = web::Data::new(AppState {
@@ -472,6 +5605,7 @@ mod tests {
             token_mean: 100.0,
             token_stddev: 20.0,
             response_delay_ms: 0,
+            ..Default::default()
         });
 
         let app = test::init_service(
@@ -486,5 +5620,117 @@ mod tests {
 
         let resp = test::call_service(&app, req).await;
         assert!(resp.status().is_success());
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["object"], "list");
+        assert!(body.get("has_more").is_none());
+
+        // Without --models-paginate the query string is not parsed at all
+        let req = test::TestRequest::get()
+            .uri("/v1/models?limit=abc")
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+
+        let req = test::TestRequest::get()
+            .uri("/v1/models/no-such-model")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::NOT_FOUND);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["error"]["code"], "model_not_found");
+        assert_eq!(body["error"]["type"], "invalid_request_error");
+    }
+
+    #[actix_web::test]
+    async fn test_models_paginate() {
+        let app_state = web::Data::new(AppState {
+            models: ["a-mock", "b-mock", "c-mock"]
+                .into_iter()
+                .map(ModelInfo::new)
+                .collect(),
+            models_paginate: true,
+            ..Default::default()
+        });
+
+        let app = test::init_service(
+            App::new()
+                .app_data(app_state)
+                .route("/v1/models", web::get().to(models_list_handler)),
+        )
+        .await;
+
+        let page = |uri: &'static str| {
+            let req = test::TestRequest::get().uri(uri).to_request();
+            test::call_and_read_body_json::<_, _, serde_json::Value>(&app, req)
+        };
+
+        let body = page("/v1/models?limit=2").await;
+        assert_eq!(body["object"], "list");
+        assert_eq!(body["data"].as_array().unwrap().len(), 2);
+        assert_eq!(body["first_id"], "a-mock");
+        assert_eq!(body["last_id"], "b-mock");
+        assert_eq!(body["has_more"], true);
+
+        let body = page("/v1/models?limit=2&after=b-mock").await;
+        assert_eq!(body["data"][0]["id"], "c-mock");
+        assert_eq!(body["first_id"], "c-mock");
+        assert_eq!(body["has_more"], false);
+
+        let body = page("/v1/models").await;
+        assert_eq!(body["data"].as_array().unwrap().len(), 3);
+        assert_eq!(body["has_more"], false);
+
+        let body = page("/v1/models?limit=abc").await;
+        assert_eq!(body["error"]["type"], "invalid_request_error");
+    }
+
+    #[actix_web::test]
+    async fn test_model_metadata() {
+        let app_state = web::Data::new(AppState {
+            models: vec![
+                ModelInfo {
+                    context_window: Some(128000),
+                    max_output_tokens: Some(16384),
+                    pricing: Some(ModelPricing {
+                        input_per_1k: 0.0025,
+                        output_per_1k: 0.01,
+                    }),
+                    ..ModelInfo::new("gpt-4o-mock")
+                },
+                ModelInfo::new("bare-mock"),
+            ],
+            ..Default::default()
+        });
+
+        let app = test::init_service(
+            App::new()
+                .app_data(app_state)
+                .route("/v1/models", web::get().to(models_list_handler))
+                .route("/v1/models/{id}", web::get().to(model_get_handler)),
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/v1/models/gpt-4o-mock")
+            .to_request();
+        let model: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(model["context_window"], 128000);
+        assert_eq!(model["max_output_tokens"], 16384);
+        assert_eq!(model["pricing"]["input_per_1k"], 0.0025);
+        assert_eq!(model["pricing"]["output_per_1k"], 0.01);
+
+        let req = test::TestRequest::get().uri("/v1/models").to_request();
+        let list: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        let bare = &list["data"][1];
+        assert_eq!(bare["id"], "bare-mock");
+        for field in ["context_window", "max_output_tokens", "pricing"] {
+            assert!(bare.get(field).is_none(), "{} should be omitted", field);
+        }
+        assert_eq!(list["data"][0], model);
+
+        let req = test::TestRequest::get()
+            .uri("/v1/models/gpt-4-mock")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::NOT_FOUND);
     }
 }
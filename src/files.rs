@@ -0,0 +1,145 @@
+//! In-memory file objects for the /v1/files stub, plus the minimal
+//! `multipart/form-data` parsing its upload endpoint needs
+
+use bytes::Bytes;
+use serde::Serialize;
+use uuid::Uuid;
+
+/// Stored file as returned by /v1/files; the content itself is not serialized
+#[derive(Debug, Clone, Serialize)]
+pub struct FileRecord {
+    pub id: String,
+    pub object: &'static str,
+    pub bytes: usize,
+    pub created_at: i64,
+    pub filename: String,
+    pub purpose: String,
+    #[serde(skip)]
+    pub content: Bytes,
+}
+
+impl FileRecord {
+    pub fn new(filename: String, purpose: String, content: Bytes, created_at: i64) -> Self {
+        FileRecord {
+            id: format!("file-{}", Uuid::new_v4().simple()),
+            object: "file",
+            bytes: content.len(),
+            created_at,
+            filename,
+            purpose,
+            content,
+        }
+    }
+}
+
+/// One part of a `multipart/form-data` body
+#[derive(Debug)]
+pub struct MultipartField {
+    pub name: String,
+    pub filename: Option<String>,
+    pub data: Bytes,
+}
+
+/// Boundary parameter of a `multipart/form-data` content type
+pub fn multipart_boundary(content_type: &str) -> Option<&str> {
+    let (mime, params) = content_type.split_once(';')?;
+    if !mime.trim().eq_ignore_ascii_case("multipart/form-data") {
+        return None;
+    }
+    params.split(';').find_map(|param| {
+        let (key, value) = param.trim().split_once('=')?;
+        key.eq_ignore_ascii_case("boundary")
+            .then(|| value.trim_matches('"'))
+    })
+}
+
+/// Split a `multipart/form-data` body into its fields. Part data is sliced
+/// from `body` without copying.
+pub fn parse_multipart(body: &Bytes, boundary: &str) -> Result<Vec<MultipartField>, String> {
+    let delimiter = format!("--{}", boundary);
+    let delimiter = delimiter.as_bytes();
+    let mut pos = find(body, delimiter, 0).ok_or("missing multipart boundary")? + delimiter.len();
+    let mut fields = Vec::new();
+
+    while !body[pos..].starts_with(b"--") {
+        let part_start = pos + 2; // CRLF after the delimiter
+        let next = find(body, delimiter, part_start).ok_or("unterminated multipart body")?;
+        let headers_end =
+            find(&body[..next], b"\r\n\r\n", part_start).ok_or("multipart part without headers")?;
+        let headers = std::str::from_utf8(&body[part_start..headers_end])
+            .map_err(|_| "multipart headers are not UTF-8")?;
+
+        let disposition = headers
+            .lines()
+            .find_map(|line| {
+                let (name, value) = line.split_once(':')?;
+                name.trim()
+                    .eq_ignore_ascii_case("content-disposition")
+                    .then_some(value)
+            })
+            .ok_or("multipart part without Content-Disposition")?;
+        let name = disposition_param(disposition, "name").ok_or("multipart part without a name")?;
+
+        // The data ends at the CRLF that precedes the next delimiter
+        let data_end = next.saturating_sub(2).max(headers_end + 4);
+        fields.push(MultipartField {
+            name,
+            filename: disposition_param(disposition, "filename"),
+            data: body.slice(headers_end + 4..data_end),
+        });
+        pos = next + delimiter.len();
+    }
+    Ok(fields)
+}
+
+/// Value of `key="..."` in a Content-Disposition header value
+fn disposition_param(disposition: &str, key: &str) -> Option<String> {
+    disposition.split(';').find_map(|param| {
+        let (k, v) = param.trim().split_once('=')?;
+        k.trim()
+            .eq_ignore_ascii_case(key)
+            .then(|| v.trim().trim_matches('"').to_string())
+    })
+}
+
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    haystack
+        .get(from..)?
+        .windows(needle.len())
+        .position(|w| w == needle)
+        .map(|p| p + from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_multipart() {
+        let content_type = "multipart/form-data; boundary=\"XyZ\"";
+        let boundary = multipart_boundary(content_type).unwrap();
+        assert_eq!(boundary, "XyZ");
+        assert!(multipart_boundary("application/json").is_none());
+
+        let body = Bytes::from_static(
+            b"--XyZ\r\n\
+              Content-Disposition: form-data; name=\"purpose\"\r\n\r\n\
+              batch\r\n\
+              --XyZ\r\n\
+              Content-Disposition: form-data; name=\"file\"; filename=\"in.jsonl\"\r\n\
+              Content-Type: application/jsonl\r\n\r\n\
+              line one\r\nline two\r\n\
+              --XyZ--\r\n",
+        );
+        let fields = parse_multipart(&body, boundary).unwrap();
+        assert_eq!(fields.len(), 2);
+        assert_eq!(fields[0].name, "purpose");
+        assert_eq!(fields[0].filename, None);
+        assert_eq!(&fields[0].data[..], b"batch");
+        assert_eq!(fields[1].name, "file");
+        assert_eq!(fields[1].filename.as_deref(), Some("in.jsonl"));
+        assert_eq!(&fields[1].data[..], b"line one\r\nline two");
+
+        assert!(parse_multipart(&Bytes::from_static(b"no boundary here"), "XyZ").is_err());
+    }
+}
@@ -0,0 +1,55 @@
+//! Response cache behind `--idempotent`: identical request bodies are answered
+//! with byte-identical responses for the lifetime of the server
+
+use bytes::Bytes;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+
+/// Serialized responses keyed by [`request_key`]. Entries are never evicted.
+#[derive(Default)]
+pub struct ResponseCache {
+    responses: Mutex<HashMap<u64, Bytes>>,
+}
+
+impl ResponseCache {
+    pub fn get(&self, key: u64) -> Option<Bytes> {
+        self.responses.lock().unwrap().get(&key).cloned()
+    }
+
+    /// Cache a response and return the one stored under `key`, which is an
+    /// earlier response if a concurrent identical request finished first
+    pub fn insert(&self, key: u64, response: Bytes) -> Bytes {
+        self.responses
+            .lock()
+            .unwrap()
+            .entry(key)
+            .or_insert(response)
+            .clone()
+    }
+}
+
+/// Hash of a request's path and full body
+pub fn request_key(path: &str, body: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    path.hash(&mut hasher);
+    body.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_first_response_wins() {
+        let cache = ResponseCache::default();
+        let key = request_key("/v1/chat/completions", b"{}");
+        assert!(cache.get(key).is_none());
+        assert_eq!(cache.insert(key, Bytes::from_static(b"first")), "first");
+        assert_eq!(cache.insert(key, Bytes::from_static(b"second")), "first");
+        assert_eq!(cache.get(key).unwrap(), "first");
+        assert_ne!(key, request_key("/v1/completions", b"{}"));
+    }
+}
@@ -3,10 +3,21 @@
 //! This library exposes the internal modules for use in benchmarks and tests.
 
 pub mod args;
+pub mod batches;
+pub mod compression;
 pub mod endpoints;
+pub mod files;
+pub mod idempotency;
+pub mod metrics;
+pub mod middleware;
+pub mod net;
+pub mod prompt_cache;
+pub mod throughput;
 pub mod tls;
+pub mod tools;
 pub mod types;
 pub mod utils;
+pub mod validation;
 
 pub use types::AppState;
 pub use endpoints::*;
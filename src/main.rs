@@ -10,6 +10,7 @@
 //! - GET /v1/models
 //! - GET /v1/models/{id}
 //! - GET /health
+//! - GET /metrics
 //! - HTTP/2 support with TLS certificates
 //!
 //! This implementation is intentionally minimal and optimized for benchmarking.
@@ -22,36 +23,62 @@
 //!   Run (HTTPS/HTTP2):
 //!     ./target/release/mock-openai --port 3000 --tls-cert cert.pem --tls-key key.pem
 
-mod args;
-mod endpoints;
-mod tls;
-mod types;
-mod utils;
-
-use actix_web::{web, App, HttpServer};
-use args::Args;
-use clap::Parser;
-use endpoints::{
-    chat_completions_handler, completions_handler, embeddings_handler, health_handler,
-    model_get_handler, models_list_handler,
+use actix_web::{http::header::HeaderValue, middleware::from_fn, web, App, HttpServer};
+use clap::{Parser, ValueEnum};
+use mock_openai::args::Args;
+use mock_openai::endpoints::{
+    admin_health_handler, configure_routes, debug_config_handler, debug_echo_request_handler,
+    unknown_route_handler,
 };
-use lipsum::lipsum_words;
+use mock_openai::idempotency::ResponseCache;
+use mock_openai::metrics::LatencyHistogram;
+use mock_openai::prompt_cache::PromptCache;
+use mock_openai::throughput::GlobalTokenRate;
+use mock_openai::types::{default_models, AppState};
+use mock_openai::utils::{
+    allocator_name, generate_stream_token_samples, load_canned_response,
+    pregenerate_articles_with_progress, ArticleGenConfig,
+};
+use mock_openai::{middleware, net, tls};
 use rand::{rngs::StdRng, SeedableRng};
 use std::sync::Arc;
-use types::AppState;
-use utils::{generate_stream_token_samples, sample_normal_f64, tokens_to_chars};
+use std::time::Duration;
+
+// jemalloc does not build for MSVC targets; fail early with a useful hint
+#[cfg(all(feature = "jemalloc", target_env = "msvc"))]
+compile_error!(
+    "the `jemalloc` feature is not supported on MSVC targets; build with --no-default-features"
+);
 
+#[cfg(feature = "jemalloc")]
 extern crate jemallocator;
 
+#[cfg(feature = "jemalloc")]
 #[global_allocator]
 static GLOBAL: jemallocator::Jemalloc = jemallocator::Jemalloc;
 
+/// Initial delay between `--bind-retries` attempts
+const BIND_RETRY_BACKOFF: Duration = Duration::from_millis(250);
+
+/// Article counts at or above this always log pre-generation progress
+const LARGE_PREGEN_COUNT: usize = 16_384;
+
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     let mut args = Args::parse();
     // Allow environment variables to override parameters set on the CLI
     args.apply_env_overrides();
 
+    if args.token_max.is_some_and(|max| max < args.token_min) {
+        eprintln!("Configuration error: --token-max must be at least --token-min");
+        std::process::exit(1);
+    }
+
+    if let Err(e) = args.validate_distribution() {
+        eprintln!("Configuration error: {}", e);
+        std::process::exit(1);
+    }
+
     // Validate TLS configuration
     if let Err(e) = args.validate_tls_config() {
         eprintln!("Configuration error: {}", e);
@@ -65,49 +92,243 @@ async fn main() -> std::io::Result<()> {
     };
 
     println!("Starting mock-openai on port {} ({})", args.port, protocol);
+    println!("Allocator: {}", allocator_name());
     if args.verbose {
         println!("Configuration: {:?}", args);
     }
 
     // Pre-generate mock articles
     println!("Pre-generating {} mock articles...", args.pregen_count);
+    let count = args.pregen_count;
+    let log_progress = args.verbose || count >= LARGE_PREGEN_COUNT;
+    // Default to logging every 10%
+    let interval = args.pregen_progress_interval.unwrap_or(count / 10).max(1);
     let mut rng = StdRng::from_entropy();
-    let mut articles: Vec<Arc<String>> = Vec::with_capacity(args.pregen_count);
-    for _ in 0..args.pregen_count {
-        let mut sampled =
-            sample_normal_f64(&mut rng, args.token_mean, args.token_stddev).round() as isize;
-        if sampled < 1 {
-            sampled = 1;
+    let gen_config = ArticleGenConfig {
+        min_words: args.min_article_words,
+        charset: args.content_charset,
+        word_length_mean: args.word_length_mean,
+        word_length_stddev: args.word_length_stddev,
+        max_corpus_bytes: (args.max_corpus_bytes > 0).then_some(args.max_corpus_bytes),
+        ..ArticleGenConfig::new(count, args.token_mean, args.token_stddev)
+    };
+    let articles = pregenerate_articles_with_progress(&gen_config, &mut rng, |i| {
+        if log_progress && i % interval == 0 && i < count {
+            println!("  ... {}/{} articles ({}%)", i, count, i * 100 / count);
         }
-        let tokens = sampled as usize;
-        let chars = tokens_to_chars(tokens);
-        // approximate words needed: chars / (avg word size + space ~ 6)
-        let words = std::cmp::max(1, (chars as f64 / 6.0).round() as usize);
-        let article_str = lipsum_words(words);
-        articles.push(Arc::new(article_str));
-    }
+    });
 
     println!("Pre-generated {} articles", articles.len());
+    if articles.len() < count {
+        println!(
+            "Stopped at {} of {} articles: --max-corpus-bytes {} reached",
+            articles.len(),
+            count,
+            args.max_corpus_bytes
+        );
+    }
+    if articles.is_empty() {
+        eprintln!(
+            "Warning: article pool is empty; every response will be {:?} (--empty-pool-text)",
+            args.empty_pool_text
+        );
+    }
 
     // Pre-generate token samples for SSE streaming
     println!("Pre-generating token sample stream...");
     let stream_sample_count = 20_000;
-    let stream_token_samples =
-        generate_stream_token_samples(stream_sample_count, args.token_mean, args.token_stddev);
+    let stream_token_samples = generate_stream_token_samples(
+        stream_sample_count,
+        args.token_mean,
+        args.token_stddev,
+        args.token_min,
+        args.token_max,
+    );
+
+    let organization = match HeaderValue::from_str(&args.organization) {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("Configuration error: invalid --organization: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let canned_chat_response = match args
+        .canned_chat_response
+        .as_deref()
+        .map(load_canned_response)
+    {
+        None => None,
+        Some(Ok(body)) => {
+            println!(
+                "Serving canned chat completions response ({} bytes)",
+                body.len()
+            );
+            Some(body)
+        }
+        Some(Err(e)) => {
+            eprintln!("Configuration error: --canned-chat-response {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let mut models = default_models();
+    for model in &args.model_metadata {
+        match models.iter_mut().find(|m| m.id == model.id) {
+            Some(existing) => *existing = model.clone(),
+            None => models.push(model.clone()),
+        }
+    }
 
     let app_state = web::Data::new(AppState {
         articles,
         stream_token_samples: Arc::new(stream_token_samples),
         stream_samples_idx: std::sync::atomic::AtomicUsize::new(0),
+        empty_pool_text: Arc::new(args.empty_pool_text.clone()),
         token_mean: args.token_mean,
         token_stddev: args.token_stddev,
+        token_count_jitter: args.token_count_jitter.clamp(0.0, 1.0),
+        token_min: args.token_min,
+        token_max: args.token_max,
+        verbosity_factors: args.verbosity_factors,
+        service_tier_factors: args.service_tier_factors,
         response_delay_ms: args.response_delay_ms,
+        stream_burst_tokens: args.stream_burst_tokens,
+        stream_delay_dist: args.stream_delay_dist,
+        stream_granularity: args.stream_granularity,
+        stream_delay_stddev_ms: args.stream_delay_stddev_ms,
+        pre_parse_delay_ms: args.pre_parse_delay_ms,
+        header_delay_ms: args.header_delay_ms,
+        thinking_delay_ms: args.thinking_delay_ms,
+        reasoning_tokens: args.reasoning_tokens,
+        reasoning_gap_ms: args.reasoning_gap_ms,
+        max_stream_duration_ms: args.max_stream_duration_ms,
+        cpu_burn_ms: args.cpu_burn_ms,
+        prefill_ms_per_token: args.prefill_ms_per_token,
+        capacity: args.capacity,
+        global_tps: (args.global_tps > 0.0 && args.global_tps.is_finite())
+            .then(|| Arc::new(GlobalTokenRate::new(args.global_tps))),
+        stream_timestamps: args.stream_timestamps,
+        stream_flush_each: args.stream_flush_each,
+        split_multibyte: args.split_multibyte,
+        sse_retry_ms: args.sse_retry_ms,
+        sse_event_name: args.sse_event_name.as_deref().map(Arc::from),
+        done_sentinel: Arc::from(args.done_sentinel.as_str()),
+        stream_keepalive_ms: args.stream_keepalive_ms,
+        stream_keepalive_max: args.stream_keepalive_max,
+        min_response_ms: args.min_response_ms,
+        copy_articles: args.copy_articles,
+        article_selection: args.article_selection,
+        preserve_whitespace: args.preserve_whitespace,
+        reflect_prompt: args.reflect_prompt,
+        inject_control_chars: args.inject_control_chars,
+        refusal_trigger: args.refusal_trigger.clone(),
+        canned_chat_response,
+        max_tool_calls: args.max_tool_calls.max(1),
+        url_citations: args.url_citations,
+        prompt_cache: args.simulate_prompt_cache.then(PromptCache::default),
+        prompt_cache_fraction: args.prompt_cache_fraction.clamp(0.0, 1.0),
+        response_cache: args.idempotent.then(ResponseCache::default),
+        content_filter_rate: args.content_filter_rate,
+        corrupt_response_rate: args.corrupt_response_rate,
+        empty_choices_rate: args.empty_choices_rate,
+        embedding_quantize: args.embedding_quantize,
+        max_embedding_n: args.max_embedding_n,
+        embedding_delay_ms: args.embedding_delay_ms,
+        embedding_delay_per_input_ms: args.embedding_delay_per_input_ms,
+        max_messages: args.max_messages,
+        max_response_bytes: args.max_response_bytes,
+        max_file_bytes: args.max_file_bytes,
+        list_pagination: args.list_pagination,
+        models_paginate: args.models_paginate,
+        models,
+        deny_models: args.deny_models.clone(),
+        model_aliases: args.model_aliases.clone(),
+        model_echo: args.model_echo,
+        model_replicas: args.model_replicas,
+        strict_schema: args.strict_schema,
+        azure_compat: args.azure_compat,
+        max_context_tokens: args.max_context_tokens,
+        context_overflow: args.context_overflow,
+        compression_level: args.compress.then_some(args.compression_level),
+        force_content_length: args.force_content_length,
+        connection_close: args.connection_close,
+        response_headers: args.response_headers.clone(),
+        organization,
+        created_offset_secs: args.created_offset_secs,
+        timestamp_precision: args.timestamp_precision,
+        max_requests: args.max_requests,
+        admin_token: args.admin_token.clone(),
+        api_key: args.api_key.clone(),
+        config: serde_json::to_value(&args).unwrap_or_default(),
+        latency_histogram: LatencyHistogram::new(&args.metrics_latency_buckets),
+        ..Default::default()
     });
 
+    let shutdown_state = app_state.clone();
+
     let bind_addr = format!("0.0.0.0:{}", args.port);
+    // Without retries the server binds the address itself, with its own
+    // socket setup (listen backlog)
+    let listener = if args.bind_retries > 0 {
+        Some(net::bind_with_retry(&bind_addr, args.bind_retries, BIND_RETRY_BACKOFF).await?)
+    } else {
+        None
+    };
+
+    let debug = args.debug;
+    if debug {
+        println!("Debug endpoints enabled (POST /debug/echo-request, GET /debug/config)");
+    }
+
+    let admin = args.admin_token.is_some();
+    if admin {
+        println!("Admin endpoints enabled (POST /admin/health)");
+    }
+
+    let disabled_endpoints = args.disabled_endpoints.clone();
+    if !disabled_endpoints.is_empty() {
+        let names: Vec<String> = disabled_endpoints
+            .iter()
+            .filter_map(|e| e.to_possible_value())
+            .map(|v| v.get_name().to_string())
+            .collect();
+        println!("Disabled endpoints: {}", names.join(", "));
+    }
+
+    let server = HttpServer::new(move || {
+        App::new()
+            .app_data(app_state.clone())
+            .wrap(from_fn(middleware::idempotent_responses))
+            .wrap(from_fn(middleware::api_key_auth))
+            .wrap(from_fn(middleware::pre_parse_delay))
+            .wrap(from_fn(middleware::request_limit))
+            .wrap(from_fn(middleware::record_latency))
+            .wrap(from_fn(middleware::corrupt_response))
+            .wrap(from_fn(middleware::compress))
+            .wrap(from_fn(middleware::force_content_length))
+            .wrap(from_fn(middleware::connection_close))
+            .wrap(from_fn(middleware::openai_headers))
+            .wrap(from_fn(middleware::response_headers))
+            .wrap(from_fn(middleware::header_delay))
+            .configure(|cfg| configure_routes(cfg, &disabled_endpoints))
+            .configure(|cfg| {
+                if debug {
+                    cfg.route(
+                        "/debug/echo-request",
+                        web::post().to(debug_echo_request_handler),
+                    )
+                    .route("/debug/config", web::get().to(debug_config_handler));
+                }
+                if admin {
+                    cfg.route("/admin/health", web::post().to(admin_health_handler));
+                }
+            })
+            .default_service(web::to(unknown_route_handler))
+    });
 
     // Configure and run the server with optional TLS
-    if let (Some(cert_path), Some(key_path)) = (&args.tls_cert, &args.tls_key) {
+    let server = if let (Some(cert_path), Some(key_path)) = (&args.tls_cert, &args.tls_key) {
         println!(
             "Loading TLS certificates from {} and {}",
             cert_path.display(),
@@ -130,49 +351,32 @@ async fn main() -> std::io::Result<()> {
                 println!("✓ TLS configuration loaded successfully");
                 println!("✓ HTTP/2 enabled (ALPN protocols: h2, http/1.1)");
 
-                HttpServer::new(move || {
-                    App::new()
-                        .app_data(app_state.clone())
-                        .route("/health", web::get().to(health_handler))
-                        .route("/v1/models", web::get().to(models_list_handler))
-                        .route("/v1/models/{id}", web::get().to(model_get_handler))
-                        .route(
-                            "/v1/chat/completions",
-                            web::post().to(chat_completions_handler),
-                        )
-                        .route("/v1/completions", web::post().to(completions_handler))
-                        .route("/v1/embeddings", web::post().to(embeddings_handler))
-                })
-                .bind_rustls_0_23(&bind_addr, server_config)?
-                .run()
-                .await
+                match listener {
+                    Some(listener) => server.listen_rustls_0_23(listener, server_config)?,
+                    None => server.bind_rustls_0_23(&bind_addr, server_config)?,
+                }
             }
             Err(e) => {
                 eprintln!("Failed to load TLS configuration: {}", e);
-                Err(std::io::Error::new(
+                return Err(std::io::Error::new(
                     std::io::ErrorKind::InvalidInput,
                     format!("TLS configuration error: {}", e),
-                ))
+                ));
             }
         }
     } else {
         println!("Running without TLS (HTTP only)");
 
-        HttpServer::new(move || {
-            App::new()
-                .app_data(app_state.clone())
-                .route("/health", web::get().to(health_handler))
-                .route("/v1/models", web::get().to(models_list_handler))
-                .route("/v1/models/{id}", web::get().to(model_get_handler))
-                .route(
-                    "/v1/chat/completions",
-                    web::post().to(chat_completions_handler),
-                )
-                .route("/v1/completions", web::post().to(completions_handler))
-                .route("/v1/embeddings", web::post().to(embeddings_handler))
-        })
-        .bind(&bind_addr)?
-        .run()
-        .await
+        match listener {
+            Some(listener) => server.listen(listener)?,
+            None => server.bind(&bind_addr)?,
+        }
+    };
+
+    let server = server.run();
+    if args.max_requests > 0 {
+        println!("Shutting down after {} /v1/* requests", args.max_requests);
+        middleware::stop_on_request_limit(shutdown_state, server.handle());
     }
+    server.await
 }
@@ -0,0 +1,138 @@
+//! Prometheus-style request metrics exposed on GET /metrics
+
+use serde::Serialize;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Name of the request latency histogram in the exposition output
+const LATENCY_METRIC: &str = "mock_openai_request_duration_seconds";
+
+/// Upper bounds (in milliseconds) of the latency histogram buckets
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LatencyBuckets(pub Vec<f64>);
+
+impl Default for LatencyBuckets {
+    fn default() -> Self {
+        Self(vec![
+            5.0, 10.0, 25.0, 50.0, 100.0, 250.0, 500.0, 1000.0, 2500.0, 5000.0, 10000.0,
+        ])
+    }
+}
+
+/// Parse comma-separated, strictly ascending bucket bounds in milliseconds
+pub fn parse_latency_buckets(s: &str) -> Result<LatencyBuckets, String> {
+    let bounds = s
+        .split(',')
+        .map(|b| b.trim().parse::<f64>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("invalid bucket bound in {:?}: {}", s, e))?;
+    if !bounds.iter().all(|b| b.is_finite() && *b > 0.0) {
+        return Err(format!("bucket bounds must be positive, got {:?}", s));
+    }
+    if bounds.windows(2).any(|w| w[0] >= w[1]) {
+        return Err(format!("bucket bounds must be ascending, got {:?}", s));
+    }
+    Ok(LatencyBuckets(bounds))
+}
+
+/// Lock-free cumulative latency histogram
+#[derive(Debug)]
+pub struct LatencyHistogram {
+    bounds_ms: Vec<f64>,
+    /// One counter per bound plus a final `+Inf` counter (non-cumulative)
+    counts: Vec<AtomicU64>,
+    sum_micros: AtomicU64,
+}
+
+impl LatencyHistogram {
+    pub fn new(buckets: &LatencyBuckets) -> Self {
+        Self {
+            bounds_ms: buckets.0.clone(),
+            counts: (0..=buckets.0.len()).map(|_| AtomicU64::new(0)).collect(),
+            sum_micros: AtomicU64::new(0),
+        }
+    }
+
+    /// Record one request duration
+    pub fn observe(&self, elapsed: Duration) {
+        let ms = elapsed.as_secs_f64() * 1000.0;
+        let idx = self
+            .bounds_ms
+            .iter()
+            .position(|b| ms <= *b)
+            .unwrap_or(self.bounds_ms.len());
+        self.counts[idx].fetch_add(1, Ordering::Relaxed);
+        self.sum_micros
+            .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// Render the histogram in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "# HELP {} Time to produce the response head.",
+            LATENCY_METRIC
+        );
+        let _ = writeln!(out, "# TYPE {} histogram", LATENCY_METRIC);
+
+        let mut cumulative = 0;
+        for (bound, count) in self.bounds_ms.iter().zip(&self.counts) {
+            cumulative += count.load(Ordering::Relaxed);
+            let _ = writeln!(
+                out,
+                "{}_bucket{{le=\"{}\"}} {}",
+                LATENCY_METRIC,
+                bound / 1000.0,
+                cumulative
+            );
+        }
+        cumulative += self.counts[self.bounds_ms.len()].load(Ordering::Relaxed);
+        let _ = writeln!(
+            out,
+            "{}_bucket{{le=\"+Inf\"}} {}",
+            LATENCY_METRIC, cumulative
+        );
+
+        let sum_secs = self.sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+        let _ = writeln!(out, "{}_sum {}", LATENCY_METRIC, sum_secs);
+        let _ = writeln!(out, "{}_count {}", LATENCY_METRIC, cumulative);
+        out
+    }
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self::new(&LatencyBuckets::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_latency_buckets() {
+        let buckets = parse_latency_buckets("1, 2.5,10").unwrap();
+        assert_eq!(buckets.0, vec![1.0, 2.5, 10.0]);
+        assert!(parse_latency_buckets("10,5").is_err());
+        assert!(parse_latency_buckets("5,5").is_err());
+        assert!(parse_latency_buckets("0,5").is_err());
+        assert!(parse_latency_buckets("5,x").is_err());
+    }
+
+    #[test]
+    fn test_histogram_buckets_are_cumulative() {
+        let histogram = LatencyHistogram::new(&LatencyBuckets(vec![10.0, 100.0]));
+        histogram.observe(Duration::from_millis(5));
+        histogram.observe(Duration::from_millis(50));
+        histogram.observe(Duration::from_millis(500));
+
+        let out = histogram.render();
+        assert!(out.contains("mock_openai_request_duration_seconds_bucket{le=\"0.01\"} 1\n"));
+        assert!(out.contains("mock_openai_request_duration_seconds_bucket{le=\"0.1\"} 2\n"));
+        assert!(out.contains("mock_openai_request_duration_seconds_bucket{le=\"+Inf\"} 3\n"));
+        assert!(out.contains("mock_openai_request_duration_seconds_count 3\n"));
+    }
+}
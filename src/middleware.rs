@@ -0,0 +1,1005 @@
+//! Middleware applied to every response served by the mock server
+
+use crate::compression;
+use crate::endpoints::openai_error;
+use crate::idempotency::request_key;
+use crate::types::AppState;
+use actix_web::{
+    body::{self, BoxBody, MessageBody},
+    dev::{Payload, ServerHandle, ServiceRequest, ServiceResponse},
+    http::header::{self, HeaderName, HeaderValue},
+    http::{ConnectionType, Method, StatusCode},
+    middleware::Next,
+    mime, web, Error, HttpMessage, HttpResponse,
+};
+use bytes::BytesMut;
+use futures::StreamExt;
+use rand::Rng;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+/// Static API version reported by the real OpenAI API
+const OPENAI_VERSION: &str = "2020-10-01";
+
+/// POST endpoints that generate a fresh response on every call
+const GENERATION_PATHS: [&str; 3] = ["/v1/chat/completions", "/v1/completions", "/v1/embeddings"];
+
+/// Largest request body `--idempotent` buffers to key its cache; matches
+/// actix's default JSON payload limit, so larger bodies fail the same way
+const IDEMPOTENT_MAX_BODY_BYTES: usize = 2 * 1024 * 1024;
+
+/// Whether `req` is a POST to one of the [`GENERATION_PATHS`]
+fn is_generation_request(req: &ServiceRequest) -> bool {
+    req.method() == Method::POST && GENERATION_PATHS.contains(&req.path())
+}
+
+/// Attach the configured `--response-header` entries to every response; the
+/// first entry for a name replaces what the handler set, later ones add values
+pub async fn response_headers(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let state = req.app_data::<web::Data<AppState>>().cloned();
+    let mut res = next.call(req).await?;
+    if let Some(state) = state {
+        let headers = res.headers_mut();
+        for (i, (name, value)) in state.response_headers.iter().enumerate() {
+            let repeated = state.response_headers[..i].iter().any(|(n, _)| n == name);
+            if repeated {
+                headers.append(name.clone(), value.clone());
+            } else {
+                headers.insert(name.clone(), value.clone());
+            }
+        }
+    }
+    Ok(res)
+}
+
+/// Attach `openai-organization`, `openai-processing-ms` and `openai-version`
+/// headers to every /v1/* response, mirroring the real API
+pub async fn openai_headers(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let started = Instant::now();
+    let is_api = req.path().starts_with("/v1/");
+    let state = req.app_data::<web::Data<AppState>>().cloned();
+    let mut res = next.call(req).await?;
+    if !is_api {
+        return Ok(res);
+    }
+
+    let processing_ms = started.elapsed().as_millis();
+    let headers = res.headers_mut();
+    headers.insert(
+        HeaderName::from_static("openai-processing-ms"),
+        HeaderValue::from(processing_ms as u64),
+    );
+    headers.insert(
+        HeaderName::from_static("openai-version"),
+        HeaderValue::from_static(OPENAI_VERSION),
+    );
+    if let Some(state) = state {
+        headers.insert(
+            HeaderName::from_static("openai-organization"),
+            state.organization.clone(),
+        );
+    }
+    Ok(res)
+}
+
+/// Sleep for `--pre-parse-delay-ms` before the handler runs, i.e. before the
+/// request body is read and deserialized
+pub async fn pre_parse_delay(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let delay_ms = req
+        .app_data::<web::Data<AppState>>()
+        .map_or(0, |state| state.pre_parse_delay_ms);
+    if delay_ms > 0 {
+        tokio::time::sleep(Duration::from_millis(delay_ms)).await;
+    }
+    next.call(req).await
+}
+
+/// Wait `--header-delay-ms` before the handler builds a response, so not even
+/// the status line reaches the client before the delay. Stream deadlines and
+/// `--capacity` accounting start only after the wait.
+pub async fn header_delay(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let delay_ms = req
+        .app_data::<web::Data<AppState>>()
+        .map_or(0, |state| state.header_delay_ms);
+    if delay_ms > 0 {
+        tokio::time::sleep(Duration::from_millis(delay_ms)).await;
+    }
+    next.call(req).await
+}
+
+/// Reject /v1/* requests without the `--api-key`, accepted either as
+/// `Authorization: Bearer <KEY>` (scheme in any case) or as Azure's
+/// `api-key: <KEY>`. Any other `Authorization` scheme counts as a wrong key. A
+/// request carrying both headers with different keys is rejected even if one
+/// matches.
+pub async fn api_key_auth(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let expected = req
+        .app_data::<web::Data<AppState>>()
+        .and_then(|state| state.api_key.clone());
+    let Some(expected) = expected.filter(|_| req.path().starts_with("/v1/")) else {
+        return Ok(next.call(req).await?.map_into_boxed_body());
+    };
+
+    let header_str = |name| req.headers().get(name).and_then(|v| v.to_str().ok());
+    // `Some(None)` is an Authorization header without a Bearer key
+    let bearer =
+        header_str(header::AUTHORIZATION.as_str()).map(|v| match v.trim().split_once(' ') {
+            Some((scheme, key)) if scheme.eq_ignore_ascii_case("bearer") => Some(key.trim()),
+            _ => None,
+        });
+    let api_key = header_str("api-key").map(str::trim);
+    let rejection = match (bearer, api_key) {
+        (None, None) => Some((
+            "You didn't provide an API key. You need to provide your API key in an Authorization header using Bearer auth (i.e. Authorization: Bearer YOUR_KEY), or in an api-key header.",
+            None,
+        )),
+        (Some(Some(a)), Some(b)) if a != b => Some((
+            "Conflicting API keys provided in the Authorization and api-key headers.",
+            Some("invalid_api_key"),
+        )),
+        (Some(None), _) => Some(("Incorrect API key provided.", Some("invalid_api_key"))),
+        (Some(Some(key)), _) | (None, Some(key)) if key != expected => Some((
+            "Incorrect API key provided.",
+            Some("invalid_api_key"),
+        )),
+        _ => None,
+    };
+    let Some((message, code)) = rejection else {
+        return Ok(next.call(req).await?.map_into_boxed_body());
+    };
+    let res = openai_error(StatusCode::UNAUTHORIZED, message, None, code);
+    Ok(req.into_response(res))
+}
+
+/// Count completed /v1/* requests and signal shutdown once `--max-requests`
+/// is reached
+pub async fn request_limit(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let counted = req.path().starts_with("/v1/");
+    let state = req.app_data::<web::Data<AppState>>().cloned();
+    let res = next.call(req).await?;
+    if let Some(state) = state.filter(|s| counted && s.max_requests > 0) {
+        let completed = state.completed_requests.fetch_add(1, Ordering::Relaxed) + 1;
+        if completed == state.max_requests {
+            state.shutdown.notify_one();
+        }
+    }
+    Ok(res)
+}
+
+/// Record how long each request takes to produce its response head in the
+/// /metrics latency histogram (streams are timed up to their first byte)
+pub async fn record_latency(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let started = Instant::now();
+    let state = req.app_data::<web::Data<AppState>>().cloned();
+    let res = next.call(req).await?;
+    if let Some(state) = state {
+        state.latency_histogram.observe(started.elapsed());
+    }
+    Ok(res)
+}
+
+/// Gracefully stop the server behind `handle` once [`request_limit`] reports
+/// that `--max-requests` has been reached
+pub fn stop_on_request_limit(state: web::Data<AppState>, handle: ServerHandle) {
+    actix_rt::spawn(async move {
+        state.shutdown.notified().await;
+        println!(
+            "Served {} requests; shutting down",
+            state.completed_requests.load(Ordering::Relaxed)
+        );
+        handle.stop(true).await;
+    });
+}
+
+/// Compress responses with the best encoding the client accepts (br, zstd,
+/// gzip or deflate) when `--compress` is enabled. SSE streams are passed
+/// through untouched so events are not held back by the encoder.
+pub async fn compress(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let level = req
+        .app_data::<web::Data<AppState>>()
+        .and_then(|state| state.compression_level);
+    let encoding = level.and_then(|_| {
+        req.headers()
+            .get(header::ACCEPT_ENCODING)
+            .and_then(|v| v.to_str().ok())
+            .and_then(compression::negotiate)
+    });
+    let res = next.call(req).await?;
+
+    let (Some(level), Some(encoding)) = (level, encoding) else {
+        return Ok(res.map_into_boxed_body());
+    };
+    let is_event_stream = res
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|v| v.as_bytes().starts_with(b"text/event-stream"));
+    if is_event_stream || res.headers().contains_key(header::CONTENT_ENCODING) {
+        return Ok(res.map_into_boxed_body());
+    }
+
+    let (req, res) = res.into_parts();
+    let (mut res, body) = res.into_parts();
+    let bytes = body::to_bytes(body)
+        .await
+        .map_err(|e| actix_web::error::ErrorInternalServerError(e.into().to_string()))?;
+    let compressed = compression::compress(encoding, level, &bytes)?;
+
+    let headers = res.headers_mut();
+    headers.insert(
+        header::CONTENT_ENCODING,
+        HeaderValue::from_static(encoding.as_str()),
+    );
+    headers.insert(header::VARY, HeaderValue::from_static("accept-encoding"));
+    headers.remove(header::CONTENT_LENGTH);
+    let res = res.set_body(BoxBody::new(compressed));
+    Ok(ServiceResponse::new(req, res))
+}
+
+/// With probability `--corrupt-response-rate`, cut a successful non-streaming
+/// chat, completions or embeddings body off at a random byte so clients
+/// receive a 200 with unparseable JSON; other endpoints are left intact
+pub async fn corrupt_response(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let rate = req
+        .app_data::<web::Data<AppState>>()
+        .filter(|_| is_generation_request(&req))
+        .map_or(0.0, |state| state.corrupt_response_rate);
+    let res = next.call(req).await?;
+    let is_json = res.status().is_success()
+        && res
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .is_some_and(|v| v.as_bytes().starts_with(b"application/json"));
+    if rate <= 0.0 || !is_json || !rand::thread_rng().gen_bool(rate.min(1.0)) {
+        return Ok(res.map_into_boxed_body());
+    }
+
+    let (req, res) = res.into_parts();
+    let (mut res, body) = res.into_parts();
+    let bytes = body::to_bytes(body)
+        .await
+        .map_err(|e| actix_web::error::ErrorInternalServerError(e.into().to_string()))?;
+    // Dropping at least the final byte (the closing brace) always breaks the JSON
+    let cut = rand::thread_rng().gen_range(0..bytes.len().max(1));
+    res.headers_mut().remove(header::CONTENT_LENGTH);
+    let res = res.set_body(BoxBody::new(bytes.slice(..cut.min(bytes.len()))));
+    Ok(ServiceResponse::new(req, res))
+}
+
+/// Buffer non-streaming bodies and set an explicit `Content-Length` when
+/// `--force-content-length` is enabled, for clients that can't handle chunked
+/// transfer. SSE streams stay chunked.
+pub async fn force_content_length(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let enabled = req
+        .app_data::<web::Data<AppState>>()
+        .is_some_and(|state| state.force_content_length);
+    let res = next.call(req).await?;
+    let is_event_stream = res
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|v| v.as_bytes().starts_with(b"text/event-stream"));
+    if !enabled || is_event_stream {
+        return Ok(res.map_into_boxed_body());
+    }
+
+    let (req, res) = res.into_parts();
+    let (mut res, body) = res.into_parts();
+    let bytes = body::to_bytes(body)
+        .await
+        .map_err(|e| actix_web::error::ErrorInternalServerError(e.into().to_string()))?;
+    res.headers_mut()
+        .insert(header::CONTENT_LENGTH, HeaderValue::from(bytes.len()));
+    let res = res.set_body(BoxBody::new(bytes));
+    Ok(ServiceResponse::new(req, res))
+}
+
+/// With `--idempotent`, answer a chat, completions or embeddings request whose
+/// body was seen before with the exact bytes of the first response. Only
+/// successful JSON responses are cached; streams and errors are generated
+/// fresh every time. Bodies over [`IDEMPOTENT_MAX_BODY_BYTES`] are not buffered.
+pub async fn idempotent_responses(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let enabled = req
+        .app_data::<web::Data<AppState>>()
+        .is_some_and(|state| state.response_cache.is_some());
+    let declared_len = req
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<usize>().ok());
+    if !enabled
+        || !is_generation_request(&req)
+        || declared_len.is_some_and(|len| len > IDEMPOTENT_MAX_BODY_BYTES)
+    {
+        return Ok(next.call(req).await?.map_into_boxed_body());
+    }
+
+    // Buffer the body to key the cache, then hand it on to the handler
+    let mut payload = req.take_payload();
+    let mut body = BytesMut::new();
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk?;
+        if body.len() + chunk.len() > IDEMPOTENT_MAX_BODY_BYTES {
+            return Err(actix_web::error::ErrorPayloadTooLarge(
+                "request body is too large",
+            ));
+        }
+        body.extend_from_slice(&chunk);
+    }
+    let body = body.freeze();
+    let key = request_key(req.path(), &body);
+    let state = req.app_data::<web::Data<AppState>>().unwrap().clone();
+    let cache = state.response_cache.as_ref().unwrap();
+    if let Some(cached) = cache.get(key) {
+        let res = HttpResponse::Ok()
+            .content_type(mime::APPLICATION_JSON)
+            .body(cached);
+        return Ok(req.into_response(res));
+    }
+
+    req.set_payload(Payload::from(body));
+    let res = next.call(req).await?;
+    let is_json = res.status().is_success()
+        && res
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .is_some_and(|v| v.as_bytes().starts_with(b"application/json"));
+    if !is_json {
+        return Ok(res.map_into_boxed_body());
+    }
+    let (req, res) = res.into_parts();
+    let (res, body) = res.into_parts();
+    let bytes = body::to_bytes(body)
+        .await
+        .map_err(|e| actix_web::error::ErrorInternalServerError(e.into().to_string()))?;
+    let res = res.set_body(BoxBody::new(cache.insert(key, bytes)));
+    Ok(ServiceResponse::new(req, res))
+}
+
+/// With `--connection-close`, answer every request with `Connection: close`
+/// so HTTP/1.1 clients must reconnect (measures connection-setup overhead)
+pub async fn connection_close(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let enabled = req
+        .app_data::<web::Data<AppState>>()
+        .is_some_and(|state| state.connection_close);
+    let mut res = next.call(req).await?;
+    if enabled {
+        // The connection type makes the HTTP/1 dispatcher close the socket; the
+        // header keeps the intent visible (HTTP/2 drops it)
+        res.response_mut()
+            .head_mut()
+            .set_connection_type(ConnectionType::Close);
+        res.headers_mut()
+            .insert(header::CONNECTION, HeaderValue::from_static("close"));
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::parse_response_header;
+    use crate::endpoints::{chat_completions_handler, health_handler, models_list_handler};
+    use actix_web::{middleware::from_fn, test, App};
+
+    #[actix_web::test]
+    async fn test_response_headers_applied() {
+        let app_state = web::Data::new(AppState {
+            articles: vec![std::sync::Arc::new("hello world test".to_string())],
+            response_headers: vec![
+                parse_response_header("x-upstream: mock").unwrap(),
+                parse_response_header("X-Test-Run: 42").unwrap(),
+                parse_response_header("set-cookie: a=1").unwrap(),
+                parse_response_header("Set-Cookie: b=2").unwrap(),
+            ],
+            ..Default::default()
+        });
+
+        let app = test::init_service(
+            App::new()
+                .app_data(app_state)
+                .wrap(from_fn(response_headers))
+                .route("/health", web::get().to(health_handler))
+                .route(
+                    "/v1/chat/completions",
+                    web::post().to(chat_completions_handler),
+                ),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/health").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+        assert_eq!(resp.headers().get("x-upstream").unwrap(), "mock");
+        assert_eq!(resp.headers().get("x-test-run").unwrap(), "42");
+        // A repeated name keeps every value
+        let cookies: Vec<_> = resp.headers().get_all("set-cookie").collect();
+        assert_eq!(cookies, ["a=1", "b=2"]);
+
+        let req = test::TestRequest::post()
+            .uri("/v1/chat/completions")
+            .set_json(serde_json::json!({
+                "model": "gpt-4-mock",
+                "messages": [{"role": "user", "content": "hello"}]
+            }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+        assert_eq!(resp.headers().get("x-upstream").unwrap(), "mock");
+        assert_eq!(resp.headers().get("x-test-run").unwrap(), "42");
+    }
+
+    #[actix_web::test]
+    async fn test_openai_headers_on_api_routes() {
+        let app_state = web::Data::new(AppState {
+            organization: HeaderValue::from_static("org-test"),
+            ..Default::default()
+        });
+
+        let app = test::init_service(
+            App::new()
+                .app_data(app_state)
+                .wrap(from_fn(openai_headers))
+                .route("/health", web::get().to(health_handler))
+                .route("/v1/models", web::get().to(models_list_handler)),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/v1/models").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+        let processing_ms = resp.headers().get("openai-processing-ms").unwrap();
+        assert!(processing_ms.to_str().unwrap().parse::<u64>().is_ok());
+        assert_eq!(
+            resp.headers().get("openai-organization").unwrap(),
+            "org-test"
+        );
+        assert_eq!(
+            resp.headers().get("openai-version").unwrap(),
+            OPENAI_VERSION
+        );
+
+        // Non-API routes are left untouched
+        let req = test::TestRequest::get().uri("/health").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.headers().get("openai-processing-ms").is_none());
+    }
+
+    #[actix_web::test]
+    async fn test_idempotent_replays_identical_requests() {
+        let app_state = web::Data::new(AppState {
+            articles: vec![std::sync::Arc::new("hello world".to_string())],
+            response_cache: Some(Default::default()),
+            ..Default::default()
+        });
+
+        let app = test::init_service(
+            App::new()
+                .app_data(app_state)
+                .wrap(from_fn(idempotent_responses))
+                .route(
+                    "/v1/chat/completions",
+                    web::post().to(chat_completions_handler),
+                ),
+        )
+        .await;
+
+        let call = |content: &str| {
+            let req = test::TestRequest::post()
+                .uri("/v1/chat/completions")
+                .set_json(serde_json::json!({
+                    "model": "gpt-4-mock",
+                    "messages": [{"role": "user", "content": content}]
+                }))
+                .to_request();
+            test::call_and_read_body(&app, req)
+        };
+        let first = call("hi").await;
+        let repeated = call("hi").await;
+        let other = call("hello").await;
+        assert_eq!(first, repeated);
+        // Every generated response carries a fresh id
+        assert_ne!(first, other);
+        let json: serde_json::Value = serde_json::from_slice(&repeated).unwrap();
+        assert_eq!(json["object"], "chat.completion");
+    }
+
+    #[actix_web::test]
+    async fn test_idempotent_skips_other_and_oversized_requests() {
+        let app_state = web::Data::new(AppState {
+            articles: vec![std::sync::Arc::new("hello world".to_string())],
+            response_cache: Some(Default::default()),
+            ..Default::default()
+        });
+
+        let app = test::init_service(
+            App::new()
+                .app_data(app_state)
+                .wrap(from_fn(idempotent_responses))
+                .route(
+                    "/v1/chat/completions",
+                    web::post().to(chat_completions_handler),
+                )
+                .route(
+                    "/v1/files",
+                    web::post().to(|| async {
+                        HttpResponse::Ok().json(serde_json::json!({"id": uuid::Uuid::new_v4()}))
+                    }),
+                ),
+        )
+        .await;
+
+        let upload = || {
+            let req = test::TestRequest::post()
+                .uri("/v1/files")
+                .set_payload("same body")
+                .to_request();
+            test::call_and_read_body(&app, req)
+        };
+        assert_ne!(upload().await, upload().await);
+
+        // Too big to buffer; the JSON extractor rejects it as usual
+        let req = test::TestRequest::post()
+            .uri("/v1/chat/completions")
+            .insert_header((header::CONTENT_TYPE, "application/json"))
+            .set_payload(vec![b' '; IDEMPOTENT_MAX_BODY_BYTES + 1])
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(
+            resp.status(),
+            actix_web::http::StatusCode::PAYLOAD_TOO_LARGE
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_pre_parse_delay_applies_to_bad_json() {
+        let app_state = web::Data::new(AppState {
+            pre_parse_delay_ms: 100,
+            ..Default::default()
+        });
+
+        let app = test::init_service(
+            App::new()
+                .app_data(app_state)
+                .wrap(from_fn(pre_parse_delay))
+                .route(
+                    "/v1/chat/completions",
+                    web::post().to(chat_completions_handler),
+                ),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/v1/chat/completions")
+            .insert_header((header::CONTENT_TYPE, "application/json"))
+            .set_payload("{not json")
+            .to_request();
+        let started = Instant::now();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+        assert!(started.elapsed() >= Duration::from_millis(100));
+    }
+
+    #[actix_web::test]
+    async fn test_header_delay_holds_back_every_byte() {
+        use std::io::{ErrorKind, Read, Write};
+
+        let app_state = web::Data::new(AppState {
+            header_delay_ms: 300,
+            ..Default::default()
+        });
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = actix_web::HttpServer::new(move || {
+            App::new()
+                .app_data(app_state.clone())
+                .wrap(from_fn(header_delay))
+                .route("/health", web::get().to(health_handler))
+        })
+        .workers(1)
+        .listen(listener)
+        .unwrap()
+        .run();
+        let handle = server.handle();
+        actix_rt::spawn(server);
+
+        let client = tokio::task::spawn_blocking(move || {
+            let mut stream = std::net::TcpStream::connect(addr).unwrap();
+            stream
+                .write_all(b"GET /health HTTP/1.1\r\nHost: localhost\r\n\r\n")
+                .unwrap();
+            let mut buf = [0u8; 64];
+            stream
+                .set_read_timeout(Some(Duration::from_millis(200)))
+                .unwrap();
+            let early = stream.read(&mut buf).unwrap_err().kind();
+            stream
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            let n = stream.read(&mut buf).unwrap();
+            (early, buf[..n].to_vec())
+        });
+        let (early, head) = client.await.unwrap();
+        handle.stop(false).await;
+
+        assert!(matches!(early, ErrorKind::WouldBlock | ErrorKind::TimedOut));
+        assert!(head.starts_with(b"HTTP/1.1 200"));
+    }
+
+    #[actix_web::test]
+    async fn test_header_delay_does_not_eat_stream_duration() {
+        let app_state = web::Data::new(AppState {
+            articles: vec![std::sync::Arc::new("hello world test".to_string())],
+            stream_token_samples: std::sync::Arc::new(vec![1]),
+            header_delay_ms: 150,
+            max_stream_duration_ms: 100,
+            ..Default::default()
+        });
+        let app = test::init_service(
+            App::new()
+                .app_data(app_state)
+                .wrap(from_fn(header_delay))
+                .route(
+                    "/v1/chat/completions",
+                    web::post().to(chat_completions_handler),
+                ),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/v1/chat/completions")
+            .set_json(serde_json::json!({
+                "model": "gpt-4-mock",
+                "messages": [{"role": "user", "content": "hello"}],
+                "stream": true
+            }))
+            .to_request();
+        let started = Instant::now();
+        let body = test::call_and_read_body(&app, req).await;
+        assert!(started.elapsed() >= Duration::from_millis(150));
+        // The stream deadline starts after the header delay
+        let content: String = std::str::from_utf8(&body)
+            .unwrap()
+            .lines()
+            .filter_map(|line| line.strip_prefix("data: "))
+            .filter(|data| *data != "[DONE]")
+            .filter_map(|data| {
+                let frame: serde_json::Value = serde_json::from_str(data).unwrap();
+                frame["choices"][0]["delta"]["content"]
+                    .as_str()
+                    .map(String::from)
+            })
+            .collect();
+        assert_eq!(content, "hello world test");
+    }
+
+    #[actix_web::test]
+    async fn test_api_key_auth_header_forms() {
+        let app_state = web::Data::new(AppState {
+            api_key: Some("sk-test".to_string()),
+            ..Default::default()
+        });
+        let app = test::init_service(
+            App::new()
+                .app_data(app_state)
+                .wrap(from_fn(api_key_auth))
+                .route("/health", web::get().to(health_handler))
+                .route("/v1/models", web::get().to(models_list_handler)),
+        )
+        .await;
+
+        let cases: [(&[(&str, &str)], u16); 10] = [
+            (&[("Authorization", "Bearer sk-test")], 200),
+            (&[("Authorization", "bearer sk-test")], 200),
+            // A bare key or another scheme is not a Bearer key
+            (&[("Authorization", "sk-test")], 401),
+            (&[("Authorization", "Basic sk-test")], 401),
+            (&[("api-key", "sk-test")], 200),
+            (
+                &[("Authorization", "Bearer sk-test"), ("api-key", "sk-test")],
+                200,
+            ),
+            (&[], 401),
+            (&[("Authorization", "Bearer sk-wrong")], 401),
+            (&[("api-key", "sk-wrong")], 401),
+            // Conflicting keys are rejected even though one of them is right
+            (
+                &[("Authorization", "Bearer sk-test"), ("api-key", "sk-other")],
+                401,
+            ),
+        ];
+        for (headers, status) in cases {
+            let mut req = test::TestRequest::get().uri("/v1/models");
+            for header in headers {
+                req = req.insert_header(*header);
+            }
+            let resp = test::call_service(&app, req.to_request()).await;
+            assert_eq!(resp.status().as_u16(), status, "{:?}", headers);
+            if status == 401 {
+                let body: serde_json::Value = test::read_body_json(resp).await;
+                assert_eq!(body["error"]["type"], "invalid_request_error");
+            }
+        }
+
+        // Only the API routes are guarded
+        let req = test::TestRequest::get().uri("/health").to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+    }
+
+    #[actix_web::test]
+    async fn test_connection_close_header() {
+        for enabled in [false, true] {
+            let app_state = web::Data::new(AppState {
+                connection_close: enabled,
+                ..Default::default()
+            });
+            let app = test::init_service(
+                App::new()
+                    .app_data(app_state)
+                    .wrap(from_fn(connection_close))
+                    .route("/health", web::get().to(health_handler)),
+            )
+            .await;
+
+            let req = test::TestRequest::get().uri("/health").to_request();
+            let resp = test::call_service(&app, req).await;
+            assert!(resp.status().is_success());
+            assert_eq!(
+                resp.headers()
+                    .get(header::CONNECTION)
+                    .map(|v| v.to_str().unwrap()),
+                enabled.then_some("close")
+            );
+            assert_eq!(
+                resp.response().head().connection_type() == ConnectionType::Close,
+                enabled
+            );
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_corrupt_response_rate() {
+        for (rate, corrupted) in [(0.0, false), (1.0, true)] {
+            let app_state = web::Data::new(AppState {
+                articles: vec![std::sync::Arc::new("hello world".to_string())],
+                corrupt_response_rate: rate,
+                ..Default::default()
+            });
+            let app = test::init_service(
+                App::new()
+                    .app_data(app_state)
+                    .wrap(from_fn(corrupt_response))
+                    .route("/health", web::get().to(health_handler))
+                    .route("/v1/models", web::get().to(models_list_handler))
+                    .route(
+                        "/v1/chat/completions",
+                        web::post().to(chat_completions_handler),
+                    ),
+            )
+            .await;
+
+            for _ in 0..20 {
+                let req = test::TestRequest::post()
+                    .uri("/v1/chat/completions")
+                    .set_json(serde_json::json!({
+                        "model": "gpt-4-mock",
+                        "messages": [{"role": "user", "content": "hello"}]
+                    }))
+                    .to_request();
+                let resp = test::call_service(&app, req).await;
+                assert!(resp.status().is_success());
+                let body = test::read_body(resp).await;
+                let parsed = serde_json::from_slice::<serde_json::Value>(&body);
+                assert_eq!(parsed.is_err(), corrupted, "rate {}", rate);
+
+                // Probes and listings are never corrupted
+                for uri in ["/health", "/v1/models"] {
+                    let req = test::TestRequest::get().uri(uri).to_request();
+                    let body = test::call_and_read_body(&app, req).await;
+                    assert!(serde_json::from_slice::<serde_json::Value>(&body).is_ok());
+                }
+            }
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_force_content_length() {
+        let app_state = web::Data::new(AppState {
+            articles: vec![std::sync::Arc::new("lorem ipsum ".repeat(50))],
+            stream_token_samples: std::sync::Arc::new(vec![4, 8]),
+            force_content_length: true,
+            ..Default::default()
+        });
+
+        let app = test::init_service(
+            App::new()
+                .app_data(app_state)
+                .wrap(from_fn(force_content_length))
+                .route(
+                    "/v1/chat/completions",
+                    web::post().to(chat_completions_handler),
+                ),
+        )
+        .await;
+
+        let chat_request = |stream: bool| {
+            test::TestRequest::post()
+                .uri("/v1/chat/completions")
+                .set_json(serde_json::json!({
+                    "model": "gpt-4-mock",
+                    "messages": [{"role": "user", "content": "hello"}],
+                    "stream": stream
+                }))
+                .to_request()
+        };
+
+        let resp = test::call_service(&app, chat_request(false)).await;
+        assert!(resp.status().is_success());
+        let length: usize = resp
+            .headers()
+            .get(header::CONTENT_LENGTH)
+            .expect("explicit content-length")
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        let body = test::read_body(resp).await;
+        assert_eq!(length, body.len());
+
+        let resp = test::call_service(&app, chat_request(true)).await;
+        assert!(resp.status().is_success());
+        assert!(resp.headers().get(header::CONTENT_LENGTH).is_none());
+        assert_eq!(resp.response().body().size(), body::BodySize::Stream);
+    }
+
+    #[actix_web::test]
+    async fn test_compress_brotli() {
+        use crate::endpoints::chat_completions_handler;
+
+        let app_state = web::Data::new(AppState {
+            articles: vec![std::sync::Arc::new("lorem ipsum ".repeat(50))],
+            stream_token_samples: std::sync::Arc::new(vec![4, 8]),
+            compression_level: Some(5),
+            ..Default::default()
+        });
+
+        let app = test::init_service(
+            App::new()
+                .app_data(app_state)
+                .wrap(from_fn(compress))
+                .route("/v1/models", web::get().to(models_list_handler))
+                .route(
+                    "/v1/chat/completions",
+                    web::post().to(chat_completions_handler),
+                ),
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/v1/models")
+            .insert_header((header::ACCEPT_ENCODING, "gzip, br"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+        assert_eq!(resp.headers().get(header::CONTENT_ENCODING).unwrap(), "br");
+        let body = test::read_body(resp).await;
+        let mut decoded = Vec::new();
+        brotli::BrotliDecompress(&mut &body[..], &mut decoded).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&decoded).unwrap();
+        assert_eq!(json["data"][0]["id"], "gpt-4-mock");
+
+        // Without Accept-Encoding the body is sent as-is
+        let req = test::TestRequest::get().uri("/v1/models").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.headers().get(header::CONTENT_ENCODING).is_none());
+
+        // SSE is explicitly excluded
+        let req = test::TestRequest::post()
+            .uri("/v1/chat/completions")
+            .insert_header((header::ACCEPT_ENCODING, "br"))
+            .set_json(serde_json::json!({
+                "model": "gpt-4-mock",
+                "messages": [{"role": "user", "content": "hello"}],
+                "stream": true
+            }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.headers().get(header::CONTENT_ENCODING).is_none());
+        let body = test::read_body(resp).await;
+        assert!(body.ends_with(b"data: [DONE]\n\n"));
+    }
+
+    /// Minimal blocking HTTP/1.1 GET, returning the raw response
+    fn http_get(addr: std::net::SocketAddr, path: &str) -> std::io::Result<String> {
+        use std::io::{Read, Write};
+
+        let mut stream = std::net::TcpStream::connect(addr)?;
+        write!(
+            stream,
+            "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            path
+        )?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        Ok(response)
+    }
+
+    #[actix_web::test]
+    async fn test_shutdown_after_max_requests() {
+        let app_state = web::Data::new(AppState {
+            max_requests: 2,
+            ..Default::default()
+        });
+        let shutdown_state = app_state.clone();
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = actix_web::HttpServer::new(move || {
+            App::new()
+                .app_data(app_state.clone())
+                .wrap(from_fn(request_limit))
+                .route("/health", web::get().to(health_handler))
+                .route("/v1/models", web::get().to(models_list_handler))
+        })
+        .workers(1)
+        .listen(listener)
+        .unwrap()
+        .run();
+        stop_on_request_limit(shutdown_state, server.handle());
+        let server = actix_rt::spawn(server);
+
+        let responses = tokio::task::spawn_blocking(move || {
+            // /health does not count towards the limit
+            let health = http_get(addr, "/health").unwrap();
+            let first = http_get(addr, "/v1/models").unwrap();
+            let second = http_get(addr, "/v1/models").unwrap();
+            (health, first, second)
+        })
+        .await
+        .unwrap();
+        assert!(responses.0.starts_with("HTTP/1.1 200"));
+        assert!(responses.1.starts_with("HTTP/1.1 200"));
+        assert!(responses.2.starts_with("HTTP/1.1 200"));
+
+        tokio::time::timeout(std::time::Duration::from_secs(10), server)
+            .await
+            .expect("server did not shut down")
+            .unwrap()
+            .unwrap();
+        assert!(std::net::TcpStream::connect(addr).is_err());
+    }
+}
@@ -0,0 +1,73 @@
+//! Socket helpers for starting the server
+
+use std::io;
+use std::net::TcpListener;
+use std::time::Duration;
+
+/// Upper bound for the exponential backoff between bind attempts
+const MAX_BIND_BACKOFF: Duration = Duration::from_secs(2);
+
+/// Bind a TCP listener, retrying up to `retries` extra times with exponential
+/// backoff starting at `backoff` while the port is in use. Useful in CI where a
+/// freshly released port can briefly remain unavailable; any other error is
+/// returned right away.
+pub async fn bind_with_retry(
+    addr: &str,
+    retries: u32,
+    backoff: Duration,
+) -> io::Result<TcpListener> {
+    let mut delay = backoff;
+    let mut attempt = 0;
+    loop {
+        match TcpListener::bind(addr) {
+            Ok(listener) => return Ok(listener),
+            Err(e) if e.kind() == io::ErrorKind::AddrInUse && attempt < retries => {
+                attempt += 1;
+                eprintln!(
+                    "Failed to bind {} ({}); retrying in {:?} (attempt {}/{})",
+                    addr, e, delay, attempt, retries
+                );
+                tokio::time::sleep(delay).await;
+                delay = (delay * 2).min(MAX_BIND_BACKOFF);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_rt::test]
+    async fn test_bind_with_retry_waits_for_busy_port() {
+        let blocker = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = blocker.local_addr().unwrap().to_string();
+
+        // Without retries the busy port fails immediately
+        assert!(bind_with_retry(&addr, 0, Duration::from_millis(10))
+            .await
+            .is_err());
+
+        let releaser = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(100));
+            drop(blocker);
+        });
+        let listener = bind_with_retry(&addr, 10, Duration::from_millis(25))
+            .await
+            .unwrap();
+        assert_eq!(listener.local_addr().unwrap().to_string(), addr);
+        releaser.join().unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn test_bind_with_retry_fails_fast_on_other_errors() {
+        let started = std::time::Instant::now();
+        assert!(
+            bind_with_retry("not-an-address", 10, Duration::from_secs(1))
+                .await
+                .is_err()
+        );
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,79 @@
+//! Simulated prompt cache used to report `cached_tokens` in usage details
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::VecDeque;
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+
+/// Number of distinct prompts remembered before the oldest is evicted
+pub const PROMPT_CACHE_CAPACITY: usize = 1024;
+
+/// Small LRU of prompt hashes; a hit means the prompt was seen recently
+pub struct PromptCache {
+    capacity: usize,
+    entries: Mutex<VecDeque<u64>>,
+}
+
+impl PromptCache {
+    pub fn new(capacity: usize) -> Self {
+        PromptCache {
+            capacity,
+            entries: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    /// Record a prompt hash, returning whether it was already cached.
+    /// Hits are moved to the most-recently-used end.
+    pub fn check_and_insert(&self, hash: u64) -> bool {
+        if self.capacity == 0 {
+            return false;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        let hit = match entries.iter().position(|&h| h == hash) {
+            Some(pos) => {
+                entries.remove(pos);
+                true
+            }
+            None => {
+                if entries.len() >= self.capacity {
+                    entries.pop_front();
+                }
+                false
+            }
+        };
+        entries.push_back(hash);
+        hit
+    }
+}
+
+impl Default for PromptCache {
+    fn default() -> Self {
+        PromptCache::new(PROMPT_CACHE_CAPACITY)
+    }
+}
+
+/// Hash the pieces of a prompt (e.g. each message's role and content)
+pub fn hash_prompt<'a>(parts: impl IntoIterator<Item = &'a str>) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for part in parts {
+        part.hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prompt_cache_lru_eviction() {
+        let cache = PromptCache::new(2);
+        assert!(!cache.check_and_insert(1));
+        assert!(!cache.check_and_insert(2));
+        assert!(cache.check_and_insert(1));
+        // 2 is now least recently used and gets evicted
+        assert!(!cache.check_and_insert(3));
+        assert!(!cache.check_and_insert(2));
+        assert!(cache.check_and_insert(3));
+    }
+}
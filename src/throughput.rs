@@ -0,0 +1,63 @@
+//! Server-wide streaming token budget (`--global-tps`)
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Paces streamed tokens so that all concurrent streams together emit at most
+/// `tokens_per_sec`. Each chunk reserves the next free slice of the shared
+/// timeline, so streams slow down as more of them compete.
+#[derive(Debug)]
+pub struct GlobalTokenRate {
+    tokens_per_sec: f64,
+    origin: Instant,
+    /// End of the latest reservation, in nanoseconds since `origin`
+    next_free_nanos: AtomicU64,
+}
+
+impl GlobalTokenRate {
+    pub fn new(tokens_per_sec: f64) -> Self {
+        GlobalTokenRate {
+            tokens_per_sec,
+            origin: Instant::now(),
+            next_free_nanos: AtomicU64::new(0),
+        }
+    }
+
+    /// Reserve budget for `tokens` and return how long to wait before they may
+    /// be sent
+    pub fn reserve(&self, tokens: usize) -> Duration {
+        let cost = (tokens as f64 / self.tokens_per_sec * 1e9) as u64;
+        let now = self.origin.elapsed().as_nanos() as u64;
+        let mut current = self.next_free_nanos.load(Ordering::Relaxed);
+        loop {
+            // An idle budget does not accumulate: reservations start no earlier than now
+            let end = current.max(now).saturating_add(cost);
+            match self.next_free_nanos.compare_exchange_weak(
+                current,
+                end,
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return Duration::from_nanos(end - now),
+                Err(actual) => current = actual,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reservations_queue_behind_each_other() {
+        let rate = GlobalTokenRate::new(100.0);
+        let first = rate.reserve(10);
+        let second = rate.reserve(10);
+        // 10 tokens at 100/s take 100ms; the second caller waits for both
+        assert!(first <= Duration::from_millis(100));
+        assert!(first >= Duration::from_millis(99));
+        assert!(second >= Duration::from_millis(199));
+        assert!(second <= Duration::from_millis(200));
+    }
+}
@@ -0,0 +1,142 @@
+//! Mock tool (function) calling: picks tools from the request and fabricates
+//! arguments that match their JSON schema
+
+use crate::types::{ChatCompletionRequest, FunctionCall, Tool, ToolCall};
+use rand::Rng;
+use serde_json::{Map, Value};
+use uuid::Uuid;
+
+/// Decide which tools the assistant "calls" for this request.
+///
+/// Returns an empty list when no tools were provided or `tool_choice` is
+/// `"none"`. A `tool_choice` naming a specific function yields exactly one call
+/// to it; otherwise 1..=`max_tool_calls` calls are sampled across the provided
+/// tools, or a single call when `parallel_tool_calls` is false.
+pub fn sample_tool_calls<R: Rng>(
+    req: &ChatCompletionRequest,
+    max_tool_calls: usize,
+    rng: &mut R,
+) -> Vec<ToolCall> {
+    let tools = match req.tools.as_deref() {
+        Some(tools) if !tools.is_empty() => tools,
+        _ => return Vec::new(),
+    };
+
+    match &req.tool_choice {
+        Some(Value::String(choice)) if choice == "none" => return Vec::new(),
+        Some(Value::Object(choice)) => {
+            let forced = choice
+                .get("function")
+                .and_then(|f| f.get("name"))
+                .and_then(Value::as_str);
+            if let Some(tool) =
+                forced.and_then(|name| tools.iter().find(|t| t.function.name == name))
+            {
+                return vec![make_tool_call(tool)];
+            }
+        }
+        _ => {}
+    }
+
+    let count = if req.parallel_tool_calls.unwrap_or(true) {
+        rng.gen_range(1..=max_tool_calls.max(1))
+    } else {
+        1
+    };
+    (0..count)
+        .map(|_| make_tool_call(&tools[rng.gen_range(0..tools.len())]))
+        .collect()
+}
+
+/// Build a call to `tool` with a unique id and schema-shaped arguments
+fn make_tool_call(tool: &Tool) -> ToolCall {
+    let arguments = tool
+        .function
+        .parameters
+        .as_ref()
+        .map(mock_value_for_schema)
+        .unwrap_or_else(|| Value::Object(Map::new()));
+    ToolCall {
+        id: format!("call_{}", Uuid::new_v4().simple()),
+        kind: "function",
+        function: FunctionCall {
+            name: tool.function.name.clone(),
+            arguments: arguments.to_string(),
+        },
+    }
+}
+
+/// Produce a placeholder value matching a (subset of) JSON schema
+fn mock_value_for_schema(schema: &Value) -> Value {
+    if let Some(first) = schema
+        .get("enum")
+        .and_then(Value::as_array)
+        .and_then(|v| v.first())
+    {
+        return first.clone();
+    }
+    match schema.get("type").and_then(Value::as_str) {
+        Some("string") => Value::from("mock"),
+        Some("integer") => Value::from(0),
+        Some("number") => Value::from(0.0),
+        Some("boolean") => Value::from(false),
+        Some("array") => Value::Array(Vec::new()),
+        Some("null") => Value::Null,
+        _ => {
+            let mut obj = Map::new();
+            if let Some(props) = schema.get("properties").and_then(Value::as_object) {
+                for (name, prop) in props {
+                    obj.insert(name.clone(), mock_value_for_schema(prop));
+                }
+            }
+            Value::Object(obj)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    fn request(body: Value) -> ChatCompletionRequest {
+        serde_json::from_value(body).unwrap()
+    }
+
+    #[test]
+    fn test_tool_choice_none_and_forced() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+        let tools = serde_json::json!([
+            {"type": "function", "function": {"name": "a"}},
+            {"type": "function", "function": {"name": "b"}}
+        ]);
+        let req = request(serde_json::json!({
+            "model": "m", "tools": tools, "tool_choice": "none"
+        }));
+        assert!(sample_tool_calls(&req, 4, &mut rng).is_empty());
+
+        let req = request(serde_json::json!({
+            "model": "m", "tools": tools,
+            "tool_choice": {"type": "function", "function": {"name": "b"}}
+        }));
+        let calls = sample_tool_calls(&req, 4, &mut rng);
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].function.name, "b");
+    }
+
+    #[test]
+    fn test_arguments_follow_schema() {
+        let schema = serde_json::json!({
+            "type": "object",
+            "properties": {
+                "city": {"type": "string"},
+                "unit": {"type": "string", "enum": ["celsius", "fahrenheit"]},
+                "days": {"type": "integer"}
+            }
+        });
+        let value = mock_value_for_schema(&schema);
+        assert_eq!(value["city"], "mock");
+        assert_eq!(value["unit"], "celsius");
+        assert_eq!(value["days"], 0);
+    }
+}
@@ -1,6 +1,14 @@
 //! Request and response types for the mock OpenAI API
 
+use crate::batches::BatchStore;
+use crate::files::FileRecord;
+use crate::idempotency::ResponseCache;
+use crate::metrics::LatencyHistogram;
+use crate::prompt_cache::PromptCache;
+use crate::throughput::GlobalTokenRate;
+use actix_web::http::header::{HeaderName, HeaderValue};
 use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
 use std::sync::Arc;
 
 /// Shared application state - optimized for zero-copy and pre-computed data
@@ -11,17 +19,380 @@ pub struct AppState {
     /// Circular buffer; use atomic counter to cycle through without locks
     pub stream_token_samples: Arc<Vec<usize>>,
     pub stream_samples_idx: std::sync::atomic::AtomicUsize,
+    /// Content served when `articles` is empty (`--empty-pool-text`)
+    pub empty_pool_text: Arc<String>,
 
     pub token_mean: f64,
     pub token_stddev: f64,
+    /// Seed-independent multiplier range applied to sampled completion tokens
+    pub token_count_jitter: f64,
+    /// Floor and optional ceiling for every sampled token count
+    pub token_min: usize,
+    pub token_max: Option<usize>,
+    /// Token count multipliers for the request `verbosity` field
+    pub verbosity_factors: VerbosityFactors,
+    /// Latency multipliers for the request `service_tier` field
+    pub service_tier_factors: ServiceTierFactors,
     pub response_delay_ms: u64,
+    /// Leading tokens of every stream sent without the per-chunk delay
+    pub stream_burst_tokens: usize,
+    /// How each per-chunk delay is drawn around `response_delay_ms`
+    pub stream_delay_dist: StreamDelayDist,
+    /// How streamed content is cut into frames
+    pub stream_granularity: StreamGranularity,
+    /// Standard deviation of the per-chunk delay under `normal`
+    pub stream_delay_stddev_ms: f64,
+    /// Delay before the request body is read (client upload-phase timeouts)
+    pub pre_parse_delay_ms: u64,
+    /// Delay before the response head is sent (client read timeouts)
+    pub header_delay_ms: u64,
+    /// One-off delay before the first streamed event or the non-streaming body
+    pub thinking_delay_ms: u64,
+    /// Tokens streamed as `reasoning_content` ahead of a chat answer (0 = off)
+    pub reasoning_tokens: usize,
+    /// Pause between the reasoning and answer phases of a stream
+    pub reasoning_gap_ms: u64,
+    /// Wall-clock limit after which a stream is closed early (0 = unlimited)
+    pub max_stream_duration_ms: u64,
+    /// CPU time burned on the worker thread per chat/completions request
+    pub cpu_burn_ms: u64,
+    /// Prefill latency per prompt token, in milliseconds
+    pub prefill_ms_per_token: f64,
+    /// Concurrent chat requests the simulated backend handles before the
+    /// thinking delay starts growing (0 = latency independent of load)
+    pub capacity: usize,
+    /// Chat requests currently in flight (only counted when `capacity > 0`)
+    pub in_flight: std::sync::atomic::AtomicUsize,
+    /// Token budget shared by all streams (`--global-tps`; `None` = unlimited)
+    pub global_tps: Option<Arc<GlobalTokenRate>>,
+    /// Add a `_mock_ts_ms` emission timestamp to every streamed chunk
+    pub stream_timestamps: bool,
+    /// Yield to the runtime between SSE events so each is written separately
+    pub stream_flush_each: bool,
+    /// Write some streamed frames in two pieces split mid-codepoint
+    pub split_multibyte: bool,
+    /// Reconnection delay announced in a leading SSE `retry:` line
+    pub sse_retry_ms: Option<u64>,
+    /// Name sent in an `event:` line before every SSE data frame
+    pub sse_event_name: Option<Arc<str>>,
+    /// Payload of the final `data:` frame of every stream (`[DONE]`)
+    pub done_sentinel: Arc<str>,
+    /// Interval of `: keep-alive` comments during the thinking delay (0 = off)
+    pub stream_keepalive_ms: u64,
+    /// Most keep-alive comments per stream (0 = unlimited)
+    pub stream_keepalive_max: usize,
+    /// Minimum total handling time for non-streaming responses
+    pub min_response_ms: u64,
+    /// Copy the article into an owned String per request instead of slicing the
+    /// shared `Arc` (benchmarks the cost of the zero-copy design)
+    pub copy_articles: bool,
+    /// Pick any article, or one long enough for the sampled token count
+    pub article_selection: ArticleSelection,
+    /// Keep the article's leading/trailing whitespace in response content
+    /// instead of trimming it
+    pub preserve_whitespace: bool,
+    /// Prefix chat responses with a quote of the last user message
+    pub reflect_prompt: bool,
+    /// Mix control and edge-case Unicode characters into generated content
+    pub inject_control_chars: bool,
+    /// User messages containing this phrase get a refusal (`--refusal-trigger`)
+    pub refusal_trigger: Option<String>,
+    /// Body returned verbatim by non-streaming chat completions, bypassing
+    /// generation (`--canned-chat-response`)
+    pub canned_chat_response: Option<bytes::Bytes>,
+
+    /// Upper bound on tool calls sampled into a single assistant message
+    pub max_tool_calls: usize,
+    /// URL citations attached to each chat response's content (0 = none)
+    pub url_citations: usize,
+
+    /// Report `cached_tokens` for prompts seen before (`None` when disabled)
+    pub prompt_cache: Option<PromptCache>,
+    /// Fraction of prompt tokens reported as cached on a prompt cache hit
+    pub prompt_cache_fraction: f64,
+    /// Full responses replayed for identical requests (`None` unless `--idempotent`)
+    pub response_cache: Option<ResponseCache>,
+
+    /// Probability that a chat response is cut short with `content_filter`
+    pub content_filter_rate: f64,
+    /// Probability that a non-streaming JSON body is truncated into invalid JSON
+    pub corrupt_response_rate: f64,
+    /// Probability that a non-streaming chat response has no choices
+    pub empty_choices_rate: f64,
+
+    /// Return embeddings quantized instead of as f32 (`None` = f32)
+    pub embedding_quantize: Option<EmbeddingQuantization>,
+    /// Largest `n` accepted on /v1/embeddings
+    pub max_embedding_n: usize,
+    /// Embeddings latency: a base delay plus a delay per input in the batch
+    pub embedding_delay_ms: u64,
+    pub embedding_delay_per_input_ms: u64,
+    /// Largest number of messages in a chat request (0 = unlimited)
+    pub max_messages: usize,
+    /// Largest response body in bytes (0 = unlimited)
+    pub max_response_bytes: usize,
+    /// Add `first_id`/`last_id`/`has_more` to embedding list responses
+    pub list_pagination: bool,
+    /// Page /v1/models by `limit`/`after` and add `first_id`/`last_id`/`has_more`
+    pub models_paginate: bool,
+
+    /// Models served by /v1/models and /v1/models/{id}
+    pub models: Vec<ModelInfo>,
+
+    /// Model patterns that always yield a 404 `model_not_found`
+    pub deny_models: Vec<String>,
+    /// (alias, target) pairs from `--model-alias`
+    pub model_aliases: Vec<(String, String)>,
+    /// Whether aliased requests report the target or the requested name
+    pub model_echo: ModelEcho,
+    /// Replicas a response's model name is randomly suffixed with (0 = none)
+    pub model_replicas: usize,
+    /// Reject prompts whose tokens plus `max_tokens` exceed this (0 = no limit)
+    pub max_context_tokens: usize,
+    /// Reject or truncate prompts over `max_context_tokens`
+    pub context_overflow: ContextOverflow,
+    /// Reject requests that mix up chat and legacy completions fields
+    pub strict_schema: bool,
+    /// Shape responses like Azure OpenAI (`prompt_filter_results`)
+    pub azure_compat: bool,
+
+    /// Compress non-streaming responses at this level (`None` disables compression)
+    pub compression_level: Option<u32>,
+    /// Buffer non-streaming bodies and always send `Content-Length`
+    pub force_content_length: bool,
+    /// Send `Connection: close` on every response instead of keeping alive
+    pub connection_close: bool,
+
+    /// Extra headers attached to every response
+    pub response_headers: Vec<(HeaderName, HeaderValue)>,
+
+    /// Value of the `openai-organization` header on /v1/* responses
+    pub organization: HeaderValue,
+
+    /// Seconds added to every response's `created` (simulated clock skew)
+    pub created_offset_secs: i64,
+    /// Report `created` in seconds or milliseconds
+    pub timestamp_precision: TimestampPrecision,
+
+    /// When the server started, for `/version` uptime reporting
+    pub started_at: std::time::SystemTime,
+    pub started: std::time::Instant,
+
+    /// Shut down gracefully after this many /v1/* requests (0 = unlimited)
+    pub max_requests: u64,
+    pub completed_requests: std::sync::atomic::AtomicU64,
+    /// Signalled once `max_requests` is reached
+    pub shutdown: tokio::sync::Notify,
+
+    /// Resolved startup configuration (CLI plus env), served by GET /debug/config
+    pub config: serde_json::Value,
+
+    /// Bearer token guarding the /admin/* endpoints (`None` = not mounted)
+    pub admin_token: Option<String>,
+    /// API key every /v1/* request must carry (`None` = no auth)
+    pub api_key: Option<String>,
+    /// Status code GET /health reports when set through POST /admin/health
+    /// (0 = healthy)
+    pub health_override: std::sync::atomic::AtomicU16,
+
+    /// Request latencies reported on GET /metrics
+    pub latency_histogram: LatencyHistogram,
+
+    /// Jobs submitted to /v1/batches
+    pub batches: BatchStore,
+    /// Files uploaded to /v1/files and batch outputs, keyed by id
+    pub files: std::sync::Mutex<std::collections::HashMap<String, FileRecord>>,
+    /// Largest file accepted by POST /v1/files
+    pub max_file_bytes: usize,
+}
+
+/// Content served when the article pool is empty, unless overridden
+pub const DEFAULT_EMPTY_POOL_TEXT: &str = "Lorem ipsum dolor sit amet";
+
+/// Payload of the final frame of every stream, unless overridden
+pub const DEFAULT_DONE_SENTINEL: &str = "[DONE]";
+
+impl Default for AppState {
+    /// Empty article pool with the CLI default settings; mainly for tests and benches
+    fn default() -> Self {
+        AppState {
+            articles: Vec::new(),
+            stream_token_samples: Arc::new(Vec::new()),
+            stream_samples_idx: std::sync::atomic::AtomicUsize::new(0),
+            empty_pool_text: Arc::new(DEFAULT_EMPTY_POOL_TEXT.to_string()),
+            token_mean: 256.0,
+            token_stddev: 64.0,
+            token_count_jitter: 0.0,
+            token_min: 1,
+            token_max: None,
+            verbosity_factors: VerbosityFactors::default(),
+            service_tier_factors: ServiceTierFactors::default(),
+            response_delay_ms: 0,
+            stream_burst_tokens: 0,
+            stream_delay_dist: StreamDelayDist::default(),
+            stream_granularity: StreamGranularity::default(),
+            stream_delay_stddev_ms: 0.0,
+            pre_parse_delay_ms: 0,
+            header_delay_ms: 0,
+            thinking_delay_ms: 0,
+            reasoning_tokens: 0,
+            reasoning_gap_ms: 0,
+            max_stream_duration_ms: 0,
+            cpu_burn_ms: 0,
+            prefill_ms_per_token: 0.0,
+            capacity: 0,
+            global_tps: None,
+            in_flight: std::sync::atomic::AtomicUsize::new(0),
+            stream_timestamps: false,
+            stream_flush_each: false,
+            split_multibyte: false,
+            sse_retry_ms: None,
+            sse_event_name: None,
+            done_sentinel: Arc::from(DEFAULT_DONE_SENTINEL),
+            stream_keepalive_ms: 0,
+            stream_keepalive_max: 0,
+            min_response_ms: 0,
+            copy_articles: false,
+            article_selection: ArticleSelection::default(),
+            preserve_whitespace: false,
+            reflect_prompt: false,
+            inject_control_chars: false,
+            refusal_trigger: None,
+            canned_chat_response: None,
+            max_tool_calls: 1,
+            url_citations: 0,
+            prompt_cache: None,
+            prompt_cache_fraction: 0.5,
+            response_cache: None,
+            content_filter_rate: 0.0,
+            corrupt_response_rate: 0.0,
+            empty_choices_rate: 0.0,
+            embedding_quantize: None,
+            max_embedding_n: 16,
+            embedding_delay_ms: 0,
+            embedding_delay_per_input_ms: 0,
+            max_messages: 0,
+            max_response_bytes: 0,
+            list_pagination: false,
+            models_paginate: false,
+            models: default_models(),
+            deny_models: Vec::new(),
+            model_aliases: Vec::new(),
+            model_echo: ModelEcho::default(),
+            model_replicas: 0,
+            strict_schema: false,
+            azure_compat: false,
+            max_context_tokens: 0,
+            context_overflow: ContextOverflow::default(),
+            compression_level: None,
+            force_content_length: false,
+            connection_close: false,
+            response_headers: Vec::new(),
+            organization: HeaderValue::from_static("mock-openai"),
+            created_offset_secs: 0,
+            timestamp_precision: TimestampPrecision::default(),
+            started_at: std::time::SystemTime::now(),
+            started: std::time::Instant::now(),
+            max_requests: 0,
+            completed_requests: std::sync::atomic::AtomicU64::new(0),
+            shutdown: tokio::sync::Notify::new(),
+            config: serde_json::Value::Null,
+            admin_token: None,
+            api_key: None,
+            health_override: std::sync::atomic::AtomicU16::new(0),
+            latency_histogram: LatencyHistogram::default(),
+            batches: BatchStore::default(),
+            files: Default::default(),
+            max_file_bytes: 512 * 1024 * 1024,
+        }
+    }
 }
 
 /// Helper message types
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Message {
     pub role: String,
-    pub content: String,
+    pub content: MessageContent,
+}
+
+/// Message `content`: a plain string or an array of multimodal parts
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(untagged)]
+pub enum MessageContent {
+    Text(String),
+    Parts(Vec<ContentPart>),
+}
+
+impl MessageContent {
+    /// The text of the message; text parts are joined with newlines
+    pub fn text(&self) -> Cow<'_, str> {
+        match self {
+            MessageContent::Text(text) => Cow::Borrowed(text),
+            MessageContent::Parts(parts) => {
+                let mut texts = parts.iter().filter_map(|part| match part {
+                    ContentPart::Text { text } => Some(text.as_str()),
+                    _ => None,
+                });
+                match (texts.next(), texts.next()) {
+                    (None, _) => Cow::Borrowed(""),
+                    (Some(only), None) => Cow::Borrowed(only),
+                    (Some(first), Some(second)) => {
+                        let mut joined = format!("{}\n{}", first, second);
+                        for text in texts {
+                            joined.push('\n');
+                            joined.push_str(text);
+                        }
+                        Cow::Owned(joined)
+                    }
+                }
+            }
+        }
+    }
+
+    /// Number of `image_url` parts
+    pub fn image_count(&self) -> usize {
+        match self {
+            MessageContent::Text(_) => 0,
+            MessageContent::Parts(parts) => parts
+                .iter()
+                .filter(|part| matches!(part, ContentPart::ImageUrl { .. }))
+                .count(),
+        }
+    }
+}
+
+impl From<&str> for MessageContent {
+    fn from(text: &str) -> Self {
+        MessageContent::Text(text.to_string())
+    }
+}
+
+impl From<String> for MessageContent {
+    fn from(text: String) -> Self {
+        MessageContent::Text(text)
+    }
+}
+
+/// One element of an array-valued message `content`
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentPart {
+    Text {
+        text: String,
+    },
+    ImageUrl {
+        image_url: ImageUrl,
+    },
+    /// Any other part type (e.g. `input_audio`); accepted and ignored
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ImageUrl {
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
 }
 
 /// Chat Completions request (subset of OpenAI API)
@@ -33,6 +404,124 @@ pub struct ChatCompletionRequest {
     pub max_tokens: Option<usize>,
     pub n: Option<usize>,
     pub stream: Option<bool>,
+    pub tools: Option<Vec<Tool>>,
+    pub tool_choice: Option<serde_json::Value>,
+    pub parallel_tool_calls: Option<bool>,
+    /// -2.0 to 2.0; a high value drops repeated words from the content
+    pub frequency_penalty: Option<f64>,
+    /// -2.0 to 2.0; accepted and validated, no effect on the content
+    pub presence_penalty: Option<f64>,
+    /// "low" | "medium" | "high": coarse response length tier
+    pub verbosity: Option<String>,
+    /// "auto" | "default" | "flex": processing tier, echoed resolved in the response
+    pub service_tier: Option<String>,
+    pub stream_options: Option<StreamOptions>,
+    /// Makes sampling deterministic: the same seed yields the same content,
+    /// streamed or not
+    pub seed: Option<u64>,
+    /// Return mock log probabilities for each content token
+    pub logprobs: Option<bool>,
+    /// Alternatives listed per token when `logprobs` is set (0-20)
+    pub top_logprobs: Option<usize>,
+    /// Stream the user messages back as the first content deltas (streaming only)
+    pub echo: Option<bool>,
+    /// Test hook, not an OpenAI field: returned verbatim as the assistant content
+    pub x_mock_content: Option<String>,
+    /// Legacy completions field; only read to reject misuse under `--strict-schema`
+    pub prompt: Option<serde_json::Value>,
+}
+
+/// Options for streamed responses
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct StreamOptions {
+    /// Send a final frame with `usage` (and empty `choices`) before `[DONE]`
+    pub include_usage: Option<bool>,
+}
+
+/// Multipliers applied to the sampled token count per `verbosity` tier
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct VerbosityFactors {
+    pub low: f64,
+    pub medium: f64,
+    pub high: f64,
+}
+
+impl VerbosityFactors {
+    /// Factor for a tier name; unknown tiers fall back to `medium`
+    pub fn factor(&self, verbosity: &str) -> f64 {
+        match verbosity {
+            "low" => self.low,
+            "high" => self.high,
+            _ => self.medium,
+        }
+    }
+}
+
+impl Default for VerbosityFactors {
+    fn default() -> Self {
+        VerbosityFactors {
+            low: 0.3,
+            medium: 1.0,
+            high: 2.5,
+        }
+    }
+}
+
+/// Latency multipliers applied per resolved `service_tier`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct ServiceTierFactors {
+    pub default: f64,
+    pub flex: f64,
+}
+
+impl ServiceTierFactors {
+    /// Factor for a resolved tier name; anything but `flex` is `default`
+    pub fn factor(&self, tier: &str) -> f64 {
+        match tier {
+            "flex" => self.flex,
+            _ => self.default,
+        }
+    }
+}
+
+impl Default for ServiceTierFactors {
+    fn default() -> Self {
+        ServiceTierFactors {
+            default: 1.0,
+            flex: 3.0,
+        }
+    }
+}
+
+/// Tool definition supplied in a chat request
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Tool {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub function: FunctionDefinition,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct FunctionDefinition {
+    pub name: String,
+    pub description: Option<String>,
+    pub parameters: Option<serde_json::Value>,
+}
+
+/// Tool call made by the assistant
+#[derive(Debug, Serialize, Clone)]
+pub struct ToolCall {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub function: FunctionCall,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct FunctionCall {
+    pub name: String,
+    /// JSON-encoded arguments, as in the OpenAI API
+    pub arguments: String,
 }
 
 /// Chat completion response with lifetime parameter for borrowed content
@@ -42,23 +531,106 @@ pub struct ChatCompletionResponse<'a> {
     pub object: String,
     pub created: i64,
     pub model: String,
+    /// Only present for seeded requests; stable for the same seed, model and
+    /// sampling config
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub system_fingerprint: Option<String>,
+    /// Tier the request was processed with; only present when one was requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub service_tier: Option<&'static str>,
+    /// Azure-style prompt moderation results (`--azure-compat` only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prompt_filter_results: Option<Vec<PromptFilterResult>>,
     pub usage: Usage,
     pub choices: Vec<ChatChoice<'a>>,
 }
 
+/// Azure OpenAI content-filter verdict for one prompt
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct PromptFilterResult {
+    pub prompt_index: usize,
+    pub content_filter_results: ContentFilterResults,
+}
+
+/// Per-category results of Azure's content filter
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct ContentFilterResults {
+    pub hate: FilterCategoryResult,
+    pub self_harm: FilterCategoryResult,
+    pub sexual: FilterCategoryResult,
+    pub violence: FilterCategoryResult,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct FilterCategoryResult {
+    pub filtered: bool,
+    /// "safe" | "low" | "medium" | "high"
+    pub severity: String,
+}
+
 /// Chat choice with lifetime parameter for borrowed message content
 #[derive(Debug, Serialize)]
 pub struct ChatChoice<'a> {
     pub index: usize,
     pub message: ChatMessage<'a>,
+    /// Only present when the request set `logprobs`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logprobs: Option<ChoiceLogprobs>,
     pub finish_reason: &'a str,
 }
 
-/// Chat message with lifetime parameter for borrowed content
+/// Per-token log probabilities of a choice's content
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ChoiceLogprobs {
+    pub content: Vec<TokenLogprob>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct TokenLogprob {
+    pub token: String,
+    pub logprob: f64,
+    pub bytes: Vec<u8>,
+    pub top_logprobs: Vec<TopLogprob>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct TopLogprob {
+    pub token: String,
+    pub logprob: f64,
+    pub bytes: Vec<u8>,
+}
+
+/// Chat message with lifetime parameter for borrowed content; content is only
+/// owned with `--copy-articles` or when text is added to the article
 #[derive(Debug, Serialize)]
 pub struct ChatMessage<'a> {
     pub role: &'a str,
-    pub content: &'a str,
+    /// `None` (serialized as `null`) when the assistant only calls tools
+    pub content: Option<Cow<'a, str>>,
+    /// Set instead of `content` when the assistant declines to answer
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refusal: Option<&'a str>,
+    /// Citations pointing into `content` (`--url-citations`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub annotations: Option<Vec<Annotation>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<ToolCall>>,
+}
+
+/// Assistant message annotation, as returned by web-search-augmented models
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Annotation {
+    UrlCitation { url_citation: UrlCitation },
+}
+
+/// Source cited for `content` chars `start_index..end_index`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct UrlCitation {
+    pub url: String,
+    pub title: String,
+    pub start_index: usize,
+    pub end_index: usize,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -66,6 +638,14 @@ pub struct Usage {
     pub prompt_tokens: usize,
     pub completion_tokens: usize,
     pub total_tokens: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt_tokens_details: Option<PromptTokensDetails>,
+}
+
+/// Breakdown of prompt tokens; only reported with `--simulate-prompt-cache`
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PromptTokensDetails {
+    pub cached_tokens: usize,
 }
 
 /// Completions (legacy) request & response
@@ -75,7 +655,17 @@ pub struct CompletionsRequest {
     pub prompt: Option<String>,
     pub max_tokens: Option<usize>,
     pub n: Option<usize>,
+    /// Candidates generated server-side; all are billed, the best is returned
+    pub best_of: Option<usize>,
+    /// -2.0 to 2.0; a high value drops repeated words from the text
+    pub frequency_penalty: Option<f64>,
+    /// -2.0 to 2.0; accepted and validated, no effect on the text
+    pub presence_penalty: Option<f64>,
     pub stream: Option<bool>,
+    pub stream_options: Option<StreamOptions>,
+    /// Chat field; only read to reject misuse under `--strict-schema`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub messages: Option<serde_json::Value>,
 }
 
 /// Completions response with lifetime parameter for borrowed content
@@ -89,42 +679,326 @@ pub struct CompletionsResponse<'a> {
     pub choices: Vec<CompletionChoice<'a>>,
 }
 
-/// Completion choice with lifetime parameter for borrowed text
+/// Completion choice with lifetime parameter for borrowed text; owned only
+/// with `--copy-articles`
 #[derive(Debug, Serialize)]
 pub struct CompletionChoice<'a> {
     pub index: usize,
-    pub text: &'a str,
+    pub text: Cow<'a, str>,
     pub finish_reason: &'a str,
 }
 
 /// Embeddings request/response
 #[derive(Debug, Deserialize, Serialize)]
 pub struct EmbeddingRequest {
-    pub input: Option<String>,
+    pub input: Option<EmbeddingInput>,
     pub model: Option<String>,
+    /// Number of distinct embeddings returned per input
+    pub n: Option<usize>,
+    /// Length of each embedding vector; signed so that negative values reach
+    /// validation instead of failing deserialization
+    pub dimensions: Option<i64>,
+}
+
+/// Embeddings `input`: one string or a batch of them
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(untagged)]
+pub enum EmbeddingInput {
+    Single(String),
+    Batch(Vec<String>),
+}
+
+impl EmbeddingInput {
+    /// Number of inputs embedded
+    pub fn len(&self) -> usize {
+        match self {
+            EmbeddingInput::Single(_) => 1,
+            EmbeddingInput::Batch(inputs) => inputs.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct EmbeddingResponseItem {
-    pub embedding: Vec<f32>,
+    /// "embedding", or "embedding.int8" for quantized vectors
+    pub object: String,
+    pub embedding: Embedding,
     pub index: usize,
 }
 
+/// Embedding vector, serialized as a plain JSON number array either way
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum Embedding {
+    Float(Vec<f32>),
+    Int8(Vec<i8>),
+}
+
+/// Route groups that can be switched off with `--disable-endpoint`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Endpoint {
+    /// GET /health
+    Health,
+    /// GET /version
+    Version,
+    /// GET /metrics
+    Metrics,
+    /// GET /v1/models and /v1/models/{id}
+    Models,
+    /// POST /v1/chat/completions
+    #[value(alias = "chat")]
+    ChatCompletions,
+    /// POST /v1/completions
+    Completions,
+    /// POST /v1/embeddings
+    Embeddings,
+    /// POST /v1/batches and GET /v1/batches/{id}
+    Batches,
+    /// /v1/files upload, list, metadata, content and delete (also serves
+    /// batch output files)
+    Files,
+}
+
+/// Character set of the pre-generated articles (`--content-charset`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum ContentCharset {
+    /// Lorem ipsum words (ASCII)
+    #[default]
+    Latin,
+    /// Words of 1-4 CJK ideographs (3-byte UTF-8)
+    Cjk,
+    /// Words of 1-2 emoji (mostly 4-byte UTF-8)
+    Emoji,
+    /// Latin, CJK and emoji words interleaved
+    Mixed,
+    /// Random lowercase ASCII words whose lengths are normally distributed
+    /// (`--word-length-mean`, `--word-length-stddev`)
+    Synthetic,
+}
+
+/// Distribution of the per-chunk streaming delay (`--stream-delay-dist`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum StreamDelayDist {
+    /// Every gap is exactly `response_delay_ms`
+    #[default]
+    Constant,
+    /// Normal around `response_delay_ms` with `--stream-delay-stddev-ms`
+    Normal,
+    /// Exponential with mean `response_delay_ms` (Poisson token arrivals)
+    Exponential,
+}
+
+/// Unit of the `created` timestamp of responses (`--timestamp-precision`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, clap::ValueEnum)]
+pub enum TimestampPrecision {
+    /// Epoch seconds, as the OpenAI API reports them
+    #[default]
+    #[value(name = "s")]
+    #[serde(rename = "s")]
+    Seconds,
+    /// Epoch milliseconds
+    #[value(name = "ms")]
+    #[serde(rename = "ms")]
+    Millis,
+}
+
+/// How streamed content is cut into SSE frames (`--stream-granularity`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum StreamGranularity {
+    /// Frames of sampled token counts, split on word boundaries
+    #[default]
+    Token,
+    /// One sentence (through the next `.`, `!` or `?`) per frame
+    Sentence,
+}
+
+/// How a response's article is picked from the pool (`--article-selection`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum ArticleSelection {
+    /// Any article, uniformly; long responses are capped by its length
+    #[default]
+    Uniform,
+    /// An article long enough for the sampled token count, or several
+    /// joined together when none is
+    Fit,
+}
+
+/// Which model name responses report for an aliased request (`--model-echo`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum ModelEcho {
+    /// The alias target, e.g. `gpt-4-mock` for a `gpt-4` request
+    #[default]
+    Canonical,
+    /// The model name exactly as requested
+    Requested,
+}
+
+/// What to do with a request that does not fit `--max-context-tokens`
+/// (`--context-overflow`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum ContextOverflow {
+    /// Reject it with a 400 `context_length_exceeded`
+    #[default]
+    Error,
+    /// Silently drop prompt tokens until it fits and answer normally
+    Truncate,
+}
+
+/// Quantization applied to returned embeddings (`--embedding-quantize`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum EmbeddingQuantization {
+    /// Normalized vector scaled to integers in [-127, 127]
+    Int8,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct EmbeddingResponse {
     pub object: String,
     pub data: Vec<EmbeddingResponseItem>,
+    /// List-object pagination fields, only set with `--list-pagination`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub has_more: Option<bool>,
+}
+
+impl EmbeddingResponse {
+    /// Fill in the list-object pagination fields from the item indices; the
+    /// whole result always fits on one page
+    pub fn with_pagination(mut self) -> Self {
+        let id = |item: &EmbeddingResponseItem| format!("emb-{}", item.index);
+        self.first_id = self.data.first().map(id);
+        self.last_id = self.data.last().map(id);
+        self.has_more = Some(false);
+        self
+    }
+}
+
+/// GET /version response
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VersionResponse {
+    pub version: String,
+    pub commit: String,
+    /// Startup time in seconds since the Unix epoch
+    pub started_at: u64,
+    pub uptime_secs: u64,
 }
 
 /// Models list
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ModelsListResponse {
+    pub object: String,
     pub data: Vec<ModelInfo>,
+    /// List-object pagination fields, only set with `--models-paginate`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub has_more: Option<bool>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl ModelsListResponse {
+    pub fn new(data: Vec<ModelInfo>) -> Self {
+        ModelsListResponse {
+            object: "list".to_string(),
+            data,
+            first_id: None,
+            last_id: None,
+            has_more: None,
+        }
+    }
+
+    /// The page of `models` after the model `after` (from the start when
+    /// unknown or unset), at most `limit` long, with pagination fields
+    pub fn page(models: &[ModelInfo], limit: Option<usize>, after: Option<&str>) -> Self {
+        let start = after
+            .and_then(|after| models.iter().position(|m| m.id == after))
+            .map_or(0, |i| i + 1);
+        let rest = &models[start..];
+        let page = &rest[..limit.unwrap_or(rest.len()).min(rest.len())];
+        ModelsListResponse {
+            first_id: page.first().map(|m| m.id.clone()),
+            last_id: page.last().map(|m| m.id.clone()),
+            has_more: Some(page.len() < rest.len()),
+            ..ModelsListResponse::new(page.to_vec())
+        }
+    }
+}
+
+/// Query parameters of GET /v1/models, honored with `--models-paginate`
+#[derive(Debug, Deserialize)]
+pub struct ModelsListQuery {
+    pub limit: Option<usize>,
+    pub after: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ModelInfo {
     pub id: String,
     pub object: String,
     pub owned_by: String,
+    /// Synthetic metadata for cost-estimation tooling; omitted when unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub context_window: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_output_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pricing: Option<ModelPricing>,
+    /// Per-chunk stream delay overriding `--response-delay-ms` for this model
+    #[serde(skip)]
+    pub chunk_delay_ms: Option<u64>,
+    /// Probability that a request to this model fails with a 500
+    #[serde(skip)]
+    pub error_rate: f64,
+}
+
+impl ModelInfo {
+    /// A model without metadata
+    pub fn new(id: impl Into<String>) -> Self {
+        Self {
+            id: id.into(),
+            object: "model".into(),
+            owned_by: "mock-openai".into(),
+            context_window: None,
+            max_output_tokens: None,
+            pricing: None,
+            chunk_delay_ms: None,
+            error_rate: 0.0,
+        }
+    }
+}
+
+/// Synthetic price in USD per 1k tokens
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct ModelPricing {
+    pub input_per_1k: f64,
+    pub output_per_1k: f64,
+}
+
+/// The models served by default: `gpt-4-mock` with GPT-4-like metadata
+pub fn default_models() -> Vec<ModelInfo> {
+    vec![ModelInfo {
+        context_window: Some(8192),
+        max_output_tokens: Some(4096),
+        pricing: Some(ModelPricing {
+            input_per_1k: 0.03,
+            output_per_1k: 0.06,
+        }),
+        ..ModelInfo::new("gpt-4-mock")
+    }]
 }
@@ -1,26 +1,74 @@
 //! Utility functions for tokenization, sampling, and text processing
 
-use crate::types::{EmbeddingResponse, EmbeddingResponseItem};
+use crate::types::{
+    Annotation, ChoiceLogprobs, ContentCharset, ContentFilterResults, Embedding,
+    EmbeddingQuantization, EmbeddingResponse, EmbeddingResponseItem, FilterCategoryResult, Message,
+    PromptFilterResult, StreamDelayDist, TokenLogprob, ToolCall, TopLogprob, UrlCitation, Usage,
+};
+use bytes::{Bytes, BytesMut};
+use lipsum::lipsum_words;
 use rand::{Rng, SeedableRng};
+use std::borrow::Cow;
 use std::sync::Arc;
 
 const AVG_CHARS_PER_TOKEN: usize = 4; // Approx 1 token ≈ 4 chars (approximation)
 
+/// Name of the global allocator selected at compile time
+pub const fn allocator_name() -> &'static str {
+    if cfg!(feature = "jemalloc") {
+        "jemalloc"
+    } else {
+        "system"
+    }
+}
+
 /// Random sampling using Box-Muller transform to produce approximate normal samples
 pub fn sample_normal_f64<R: Rng>(rng: &mut R, mean: f64, stddev: f64) -> f64 {
     // We ensure u1 is > 0 to avoid ln(0)
     let mut u1 = rng.gen::<f64>();
     if u1 <= 0.0 {
-        u1 = std::f64::EPSILON;
+        u1 = f64::EPSILON;
     }
     let u2 = rng.gen::<f64>();
     let z0 = (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos();
     mean + z0 * stddev
 }
 
+/// Draw one per-chunk streaming delay in milliseconds (never negative)
+pub fn sample_stream_delay_ms<R: Rng>(
+    rng: &mut R,
+    dist: StreamDelayDist,
+    mean_ms: u64,
+    stddev_ms: f64,
+) -> f64 {
+    let mean_ms = mean_ms as f64;
+    let delay = match dist {
+        StreamDelayDist::Constant => mean_ms,
+        StreamDelayDist::Normal => sample_normal_f64(rng, mean_ms, stddev_ms),
+        // Inverse CDF; 1 - u is in (0, 1] so ln never sees 0
+        StreamDelayDist::Exponential => -mean_ms * (1.0 - rng.gen::<f64>()).ln(),
+    };
+    delay.max(0.0)
+}
+
+/// Round a sampled token count into `[min, max]` (`--token-min`/`--token-max`).
+/// NaN counts as `min`; huge samples saturate instead of overflowing.
+pub fn clamp_token_count(sampled: f64, min: usize, max: Option<usize>) -> usize {
+    let tokens = if sampled.is_nan() {
+        min
+    } else {
+        // Float-to-int `as` saturates, so +inf becomes usize::MAX
+        (sampled.round().max(0.0) as usize).max(min)
+    };
+    match max {
+        Some(max) => tokens.min(max),
+        None => tokens,
+    }
+}
+
 /// Convert tokens to approximate character count
 pub fn tokens_to_chars(tokens: usize) -> usize {
-    tokens * AVG_CHARS_PER_TOKEN
+    tokens.saturating_mul(AVG_CHARS_PER_TOKEN)
 }
 
 /// Convert character count to approximate token count
@@ -28,16 +76,250 @@ pub fn chars_to_tokens(chars: usize) -> usize {
     ((chars as f64) / (AVG_CHARS_PER_TOKEN as f64)).ceil() as usize
 }
 
-/// Choose a random article from pre-generated pool; fallback to short default string
-pub fn choose_article<R: Rng>(articles: &[Arc<String>], rng: &mut R) -> Arc<String> {
+/// Settings for pre-generating the article pool
+#[derive(Debug, Clone)]
+pub struct ArticleGenConfig {
+    pub count: usize,
+    pub token_mean: f64,
+    pub token_stddev: f64,
+    /// Every article has at least this many words, whatever token count was sampled
+    pub min_words: usize,
+    /// Characters the articles are written in
+    pub charset: ContentCharset,
+    /// Word length distribution of `ContentCharset::Synthetic` articles
+    pub word_length_mean: f64,
+    pub word_length_stddev: f64,
+    /// Stop generating before the pool's total size would exceed this many
+    /// bytes (`None` = generate all `count` articles)
+    pub max_corpus_bytes: Option<usize>,
+}
+
+impl ArticleGenConfig {
+    pub fn new(count: usize, token_mean: f64, token_stddev: f64) -> Self {
+        ArticleGenConfig {
+            count,
+            token_mean,
+            token_stddev,
+            min_words: 1,
+            charset: ContentCharset::default(),
+            word_length_mean: 5.0,
+            word_length_stddev: 2.0,
+            max_corpus_bytes: None,
+        }
+    }
+}
+
+/// Pre-generate a pool of lorem ipsum articles whose token counts follow a
+/// normal distribution (each article is at least one token)
+pub fn pregenerate_articles<R: Rng>(
+    count: usize,
+    mean: f64,
+    stddev: f64,
+    rng: &mut R,
+) -> Vec<Arc<String>> {
+    pregenerate_articles_with_progress(&ArticleGenConfig::new(count, mean, stddev), rng, |_| {})
+}
+
+/// Pre-generate articles as described by `config`, calling `on_progress` with
+/// the number of articles generated so far after each article
+pub fn pregenerate_articles_with_progress<R: Rng>(
+    config: &ArticleGenConfig,
+    rng: &mut R,
+    mut on_progress: impl FnMut(usize),
+) -> Vec<Arc<String>> {
+    let mut articles: Vec<Arc<String>> = Vec::with_capacity(config.count);
+    let mut corpus_bytes = 0usize;
+    for i in 1..=config.count {
+        let sampled = sample_normal_f64(rng, config.token_mean, config.token_stddev);
+        let tokens = clamp_token_count(sampled, 1, None);
+        let chars = tokens_to_chars(tokens);
+        // approximate words needed: chars / (avg word size + space)
+        let words =
+            ((chars as f64 / avg_word_chars(config)).round() as usize).max(config.min_words.max(1));
+        let article = match config.charset {
+            ContentCharset::Synthetic => synthetic_words(
+                words,
+                config.word_length_mean,
+                config.word_length_stddev,
+                rng,
+            ),
+            charset => charset_words(charset, words, rng),
+        };
+        corpus_bytes = corpus_bytes.saturating_add(article.len());
+        if config
+            .max_corpus_bytes
+            .is_some_and(|max| corpus_bytes > max)
+        {
+            break;
+        }
+        articles.push(Arc::new(article));
+        on_progress(i);
+    }
+    articles
+}
+
+/// Sample CJK ideographs for `--content-charset cjk`
+const CJK_CHARS: &str = "的一是不了人我在有他这中大来上国个到说们为子和你地出道也时年得就那要下以生会自着去之过家学对可她里后小么心多天而能好都然没日于起还发成事只作当想看文无开手十用主行方又如前所本见经头面公同三已老从动两长知民样现分将外但身些与高意进把法此实回二理美点月明其种声全工己话";
+
+/// Sample emoji for `--content-charset emoji`; single code points only so
+/// every char is a whole glyph
+const EMOJI_CHARS: &str = "😀😂😅😊😍😎🤔🙃🙌👍👀🎉🔥🌟🍕🍣🚀🌈🐱🐶🍀💡📚🎵🌍🧠🦀🐙🍩🎲";
+
+/// Lorem ipsum vocabulary for the Latin words of `--content-charset mixed`
+const LATIN_WORDS: [&str; 16] = [
+    "lorem",
+    "ipsum",
+    "dolor",
+    "sit",
+    "amet",
+    "consectetur",
+    "adipiscing",
+    "elit",
+    "sed",
+    "do",
+    "eiusmod",
+    "tempor",
+    "incididunt",
+    "ut",
+    "labore",
+    "magna",
+];
+
+/// Average characters per word, including the separating space
+fn avg_word_chars(config: &ArticleGenConfig) -> f64 {
+    match config.charset {
+        ContentCharset::Latin => 6.0,
+        ContentCharset::Cjk => 3.5,
+        ContentCharset::Emoji => 2.5,
+        ContentCharset::Mixed => 4.5,
+        ContentCharset::Synthetic => config.word_length_mean.max(1.0) + 1.0,
+    }
+}
+
+fn random_chars<R: Rng>(out: &mut String, pool: &str, len: usize, rng: &mut R) {
+    let count = pool.chars().count();
+    for _ in 0..len {
+        out.push(pool.chars().nth(rng.gen_range(0..count)).unwrap());
+    }
+}
+
+/// Space-separated text of `words` words in `charset`
+fn charset_words<R: Rng>(charset: ContentCharset, words: usize, rng: &mut R) -> String {
+    if charset == ContentCharset::Latin {
+        return lipsum_words(words);
+    }
+    let mut out = String::new();
+    for i in 0..words {
+        if i > 0 {
+            out.push(' ');
+        }
+        let word_charset = match charset {
+            ContentCharset::Mixed => [
+                ContentCharset::Latin,
+                ContentCharset::Cjk,
+                ContentCharset::Emoji,
+            ][rng.gen_range(0..3)],
+            other => other,
+        };
+        match word_charset {
+            ContentCharset::Cjk => {
+                let len = rng.gen_range(1..=4);
+                random_chars(&mut out, CJK_CHARS, len, rng)
+            }
+            ContentCharset::Emoji => {
+                let len = rng.gen_range(1..=2);
+                random_chars(&mut out, EMOJI_CHARS, len, rng)
+            }
+            _ => out.push_str(LATIN_WORDS[rng.gen_range(0..LATIN_WORDS.len())]),
+        }
+    }
+    out
+}
+
+/// Space-separated text of `words` random lowercase words, each with a length
+/// drawn from a normal distribution (at least one letter)
+fn synthetic_words<R: Rng>(words: usize, mean: f64, stddev: f64, rng: &mut R) -> String {
+    let mut out = String::new();
+    for i in 0..words {
+        if i > 0 {
+            out.push(' ');
+        }
+        let len = clamp_token_count(sample_normal_f64(rng, mean, stddev), 1, None);
+        out.extend((0..len).map(|_| rng.gen_range(b'a'..=b'z') as char));
+    }
+    out
+}
+
+/// Choose a random article from pre-generated pool; `fallback` when the pool is
+/// empty (`--empty-pool-text`)
+pub fn choose_article<R: Rng>(
+    articles: &[Arc<String>],
+    fallback: &Arc<String>,
+    rng: &mut R,
+) -> Arc<String> {
     if articles.is_empty() {
-        Arc::new("Lorem ipsum dolor sit amet".to_string())
+        Arc::clone(fallback)
     } else {
         let idx = rng.gen_range(0..articles.len());
         Arc::clone(&articles[idx])
     }
 }
 
+/// Choose an article holding at least `tokens` worth of characters, searching
+/// the pool from a random position (`--article-selection fit`). When no single
+/// article is long enough, consecutive articles are joined until one is.
+pub fn choose_article_fitting<R: Rng>(
+    articles: &[Arc<String>],
+    fallback: &Arc<String>,
+    tokens: usize,
+    rng: &mut R,
+) -> Arc<String> {
+    if articles.is_empty() {
+        return Arc::clone(fallback);
+    }
+    let needed = tokens_to_chars(tokens);
+    let start = rng.gen_range(0..articles.len());
+    let rotated = articles[start..].iter().chain(&articles[..start]);
+    // Byte length bounds the char count from above, so most misses are cheap
+    if let Some(article) = rotated
+        .clone()
+        .find(|a| a.len() >= needed && a.chars().count() >= needed)
+    {
+        return Arc::clone(article);
+    }
+
+    let mut joined = String::new();
+    let mut joined_chars = 0;
+    for article in rotated.cycle() {
+        if !joined.is_empty() {
+            joined.push(' ');
+            joined_chars += 1;
+        }
+        joined.push_str(article);
+        joined_chars += article.chars().count();
+        if joined_chars >= needed {
+            break;
+        }
+    }
+    Arc::new(joined)
+}
+
+/// Keep the current thread busy hashing for `duration` (`--cpu-burn-ms`);
+/// unlike a sleep, this holds the worker the whole time
+pub fn burn_cpu(duration: std::time::Duration) -> u64 {
+    use std::hash::{Hash, Hasher};
+    let started = std::time::Instant::now();
+    let mut digest = 0u64;
+    while started.elapsed() < duration {
+        for _ in 0..1000 {
+            let mut hasher = std::collections::hash_map::DefaultHasher::new();
+            digest.hash(&mut hasher);
+            digest = hasher.finish();
+        }
+    }
+    digest
+}
+
 /// Convert an index defined as a char count (0-based) into a byte offset
 pub fn char_pos_to_byte_idx(s: &str, char_pos: usize) -> usize {
     if char_pos == 0 {
@@ -49,20 +331,21 @@ pub fn char_pos_to_byte_idx(s: &str, char_pos: usize) -> usize {
         .unwrap_or_else(|| s.len())
 }
 
-/// Slice text by tokens (approximate tokens->chars mapping), returns borrowed &str
-pub fn slice_text_by_tokens(s: &str, tokens: usize) -> &str {
+/// Byte index `chars` characters past byte index `from`, clamped to the end of
+/// `s`; only the skipped chars are scanned
+pub fn byte_idx_after_chars(s: &str, from: usize, chars: usize) -> usize {
+    from + char_pos_to_byte_idx(&s[from..], chars)
+}
+
+/// Slice text by tokens (approximate tokens->chars mapping), returns borrowed &str.
+/// The slice is trimmed unless `preserve_whitespace` is set, in which case the
+/// article's own spacing, including the space the cut falls on, is kept.
+pub fn slice_text_by_tokens(s: &str, tokens: usize, preserve_whitespace: bool) -> &str {
     let chars_needed = tokens_to_chars(tokens);
-    let total_chars = s.chars().count();
-    if total_chars <= chars_needed {
+    // Find byte end by char count, scanning no further than the cut
+    let Some((end_byte, _)) = s.char_indices().nth(chars_needed) else {
         return s;
-    }
-
-    // Find byte end by char count
-    let mut end_char_pos = chars_needed;
-    if end_char_pos > total_chars {
-        end_char_pos = total_chars;
-    }
-    let end_byte = char_pos_to_byte_idx(s, end_char_pos);
+    };
 
     // Prefer slicing at whitespace to avoid cutting a word
     let trimmed_end_byte = if let Some(rel) = s[..end_byte].rfind(' ') {
@@ -71,7 +354,12 @@ pub fn slice_text_by_tokens(s: &str, tokens: usize) -> &str {
         end_byte
     };
 
-    let slice = s[..trimmed_end_byte].trim();
+    let slice = if preserve_whitespace {
+        let keep_space = (trimmed_end_byte < end_byte) as usize;
+        &s[..trimmed_end_byte + keep_space]
+    } else {
+        s[..trimmed_end_byte].trim()
+    };
     if slice.is_empty() {
         // fallback to the untrimmed slice
         &s[..end_byte]
@@ -80,6 +368,239 @@ pub fn slice_text_by_tokens(s: &str, tokens: usize) -> &str {
     }
 }
 
+/// Drop every repeated word from `text`, keeping its first occurrence; words
+/// are compared case-insensitively and without surrounding punctuation
+pub fn dedupe_repeated_words(text: &str) -> String {
+    let mut seen = std::collections::HashSet::new();
+    let mut out = String::with_capacity(text.len());
+    for segment in text.split_inclusive(char::is_whitespace) {
+        let word = segment
+            .trim_end()
+            .trim_matches(|c: char| c.is_ascii_punctuation())
+            .to_lowercase();
+        if word.is_empty() || seen.insert(word) {
+            out.push_str(segment);
+        }
+    }
+    out.truncate(out.trim_end().len());
+    out
+}
+
+/// Characters `--inject-control-chars` cycles through: NUL, zero-width space,
+/// an emoji outside the BMP (a surrogate pair in UTF-16) and ESC
+pub const INJECTED_CHARS: [char; 4] = ['\u{0}', '\u{200B}', '\u{1F980}', '\u{1B}'];
+
+/// Words between two injected characters
+const INJECT_EVERY_WORDS: usize = 4;
+
+/// Append one of [`INJECTED_CHARS`] to every few words of `text`, starting
+/// with the first
+pub fn inject_control_chars(text: &str) -> String {
+    let mut out = String::with_capacity(text.len() + text.len() / INJECT_EVERY_WORDS);
+    let mut injected = INJECTED_CHARS.iter().cycle();
+    for (i, segment) in text.split_inclusive(char::is_whitespace).enumerate() {
+        let word = segment.trim_end();
+        out.push_str(word);
+        if i % INJECT_EVERY_WORDS == 0 {
+            out.extend(injected.next());
+        }
+        out.push_str(&segment[word.len()..]);
+    }
+    out
+}
+
+/// Drop at least `bytes` bytes from the end of `text`, cutting on a char
+/// boundary; returns false when there was nothing left to drop
+pub fn truncate_cow_bytes(text: &mut Cow<'_, str>, bytes: usize) -> bool {
+    if text.is_empty() {
+        return false;
+    }
+    let mut end = text.len().saturating_sub(bytes);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    match text {
+        Cow::Borrowed(s) => *s = &s[..end],
+        Cow::Owned(s) => s.truncate(end),
+    }
+    true
+}
+
+/// Cite up to `count` distinct words of `content` with `url_citation`
+/// annotations, in content order. Indices are char offsets (end exclusive).
+pub fn url_citations<R: Rng>(content: &str, count: usize, rng: &mut R) -> Vec<Annotation> {
+    let mut words = Vec::new();
+    let mut start = None;
+    for (pos, c) in content.chars().chain([' ']).enumerate() {
+        match (c.is_whitespace(), start) {
+            (false, None) => start = Some(pos),
+            (true, Some(s)) => {
+                words.push((s, pos));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+
+    let mut picked = rand::seq::index::sample(rng, words.len(), count.min(words.len())).into_vec();
+    picked.sort_unstable();
+    picked
+        .into_iter()
+        .enumerate()
+        .map(|(n, i)| {
+            let (start_index, end_index) = words[i];
+            Annotation::UrlCitation {
+                url_citation: UrlCitation {
+                    url: format!("https://example.com/source/{}", n + 1),
+                    title: format!("Mock source {}", n + 1),
+                    start_index,
+                    end_index,
+                },
+            }
+        })
+        .collect()
+}
+
+/// Match a model id against a `--deny-model` pattern: patterns containing `*`
+/// or `?` are globs matched against the whole id, anything else is a substring
+pub fn model_matches_pattern(model: &str, pattern: &str) -> bool {
+    if pattern.contains(['*', '?']) {
+        glob_match(pattern.as_bytes(), model.as_bytes())
+    } else {
+        model.contains(pattern)
+    }
+}
+
+/// Minimal glob matcher supporting `*` (any run) and `?` (any single byte)
+fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // Position of the last `*` seen and the text index it is currently matched up to
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == b'?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == b'*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((sp, st)) = star {
+            // Let the last `*` swallow one more byte and retry
+            p = sp + 1;
+            t = st + 1;
+            star = Some((sp, st + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+/// Maximum number of characters of the user's message quoted by `--reflect-prompt`
+const REFLECT_MAX_CHARS: usize = 80;
+
+/// Build the `--reflect-prompt` prefix quoting the last user message, if any
+pub fn reflect_prompt_prefix(messages: &[Message]) -> Option<String> {
+    let last = messages.iter().rev().find(|m| m.role == "user")?;
+    let content = last.content.text();
+    let text = content.trim();
+    let quoted = if text.chars().count() > REFLECT_MAX_CHARS {
+        let end = char_pos_to_byte_idx(text, REFLECT_MAX_CHARS);
+        format!("{}...", &text[..end])
+    } else {
+        text.to_string()
+    };
+    Some(format!("You said: '{}'. ", quoted))
+}
+
+/// Content of all user messages, one per line, streamed ahead of the article
+/// for a chat request with `echo`
+pub fn echo_prompt(messages: &[Message]) -> Option<String> {
+    let texts: Vec<_> = messages
+        .iter()
+        .filter(|m| m.role == "user")
+        .map(|m| m.content.text())
+        .collect();
+    (!texts.is_empty()).then(|| texts.join("\n"))
+}
+
+/// Characters in `text` up to and including the first `.`, `!` or `?`; all of
+/// it when there is no sentence punctuation
+pub fn first_sentence_chars(text: &str) -> usize {
+    let mut count = 0;
+    for c in text.chars() {
+        count += 1;
+        if matches!(c, '.' | '!' | '?') {
+            return count;
+        }
+    }
+    count
+}
+
+/// Put an `event: <name>` line in front of an SSE data frame; other lines
+/// (such as `retry:`) pass through unchanged
+pub fn sse_with_event_name(frame: Bytes, name: &str) -> Bytes {
+    if !frame.starts_with(b"data: ") {
+        return frame;
+    }
+    let mut named = BytesMut::with_capacity(frame.len() + name.len() + 8);
+    named.extend_from_slice(b"event: ");
+    named.extend_from_slice(name.as_bytes());
+    named.extend_from_slice(b"\n");
+    named.extend_from_slice(&frame);
+    named.freeze()
+}
+
+/// Words that flag each Azure content-filter category for `--azure-compat`
+const FILTER_TRIGGERS: [(&str, &[&str]); 4] = [
+    ("hate", &["hate", "slur"]),
+    ("self_harm", &["self-harm", "suicide"]),
+    ("sexual", &["explicit", "nsfw"]),
+    ("violence", &["kill", "weapon", "attack"]),
+];
+
+/// Azure-style `prompt_filter_results` for a prompt: each category whose
+/// trigger words appear in `prompt` (case-insensitively) is rated `low`,
+/// which Azure reports without filtering; everything else is `safe`
+pub fn prompt_filter_results(prompt: &str) -> Vec<PromptFilterResult> {
+    let prompt = prompt.to_lowercase();
+    let rate = |category: &str| {
+        let triggered = FILTER_TRIGGERS
+            .iter()
+            .find(|(name, _)| *name == category)
+            .is_some_and(|(_, words)| words.iter().any(|w| prompt.contains(w)));
+        FilterCategoryResult {
+            filtered: false,
+            severity: if triggered { "low" } else { "safe" }.to_string(),
+        }
+    };
+    vec![PromptFilterResult {
+        prompt_index: 0,
+        content_filter_results: ContentFilterResults {
+            hate: rate("hate"),
+            self_harm: rate("self_harm"),
+            sexual: rate("sexual"),
+            violence: rate("violence"),
+        },
+    }]
+}
+
+/// Split `frame` inside one of its multibyte characters, picked at random, so
+/// neither piece is valid UTF-8 on its own; `None` when it is all ASCII
+pub fn split_mid_codepoint<R: Rng>(frame: &Bytes, rng: &mut R) -> Option<(Bytes, Bytes)> {
+    // A continuation byte (0b10xxxxxx) always follows the start of its char
+    let cuts: Vec<usize> = frame
+        .iter()
+        .enumerate()
+        .filter(|(_, &b)| b & 0xc0 == 0x80)
+        .map(|(i, _)| i)
+        .collect();
+    if cuts.is_empty() {
+        return None;
+    }
+    let cut = cuts[rng.gen_range(0..cuts.len())];
+    Some((frame.slice(..cut), frame.slice(cut..)))
+}
+
 /// Build minimal SSE event payload from a chunk of content
 pub fn sse_event_from_content(content: &str) -> String {
     // Data format: {"choices":[{"delta":{"content":"..."}}]}
@@ -90,32 +611,346 @@ pub fn sse_event_from_content(content: &str) -> String {
             }
         ]
     });
-    format!("data: {}\n\n", data.to_string())
+    format!("data: {}\n\n", data)
+}
+
+/// Append the frame [`sse_event_from_content`] builds for `content` to `buf`,
+/// writing the fixed JSON skeleton directly and escaping only `content`, so
+/// the frame costs no allocation once `buf` has grown to size
+pub fn write_sse_event_from_content(buf: &mut BytesMut, content: &str) {
+    const HEAD: &[u8] = b"data: {\"choices\":[{\"delta\":{\"content\":\"";
+    const TAIL: &[u8] = b"\"}}]}\n\n";
+    buf.reserve(HEAD.len() + content.len() + TAIL.len());
+    buf.extend_from_slice(HEAD);
+    write_json_escaped(buf, content);
+    buf.extend_from_slice(TAIL);
+}
+
+/// Append `s` to `buf` escaped exactly as serde_json escapes string contents
+fn write_json_escaped(buf: &mut BytesMut, s: &str) {
+    const HEX: &[u8; 16] = b"0123456789abcdef";
+    let bytes = s.as_bytes();
+    let mut start = 0;
+    for (i, &b) in bytes.iter().enumerate() {
+        let short: &[u8] = match b {
+            b'"' => b"\\\"",
+            b'\\' => b"\\\\",
+            b'\n' => b"\\n",
+            b'\r' => b"\\r",
+            b'\t' => b"\\t",
+            0x08 => b"\\b",
+            0x0c => b"\\f",
+            0x00..=0x1f => b"",
+            _ => continue,
+        };
+        buf.extend_from_slice(&bytes[start..i]);
+        if short.is_empty() {
+            buf.extend_from_slice(b"\\u00");
+            buf.extend_from_slice(&[HEX[(b >> 4) as usize], HEX[(b & 0xf) as usize]]);
+        } else {
+            buf.extend_from_slice(short);
+        }
+        start = i + 1;
+    }
+    buf.extend_from_slice(&bytes[start..]);
+}
+
+/// Split text into mock tokens: each word with the whitespace before it, so
+/// the tokens concatenate back to `text`
+pub fn split_mock_tokens(text: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let mut start = 0;
+    let mut in_word = false;
+    for (i, c) in text.char_indices() {
+        if c.is_whitespace() {
+            if in_word {
+                tokens.push(&text[start..i]);
+                start = i;
+                in_word = false;
+            }
+        } else {
+            in_word = true;
+        }
+    }
+    if start < text.len() {
+        tokens.push(&text[start..]);
+    }
+    tokens
+}
+
+/// Stand-in alternatives listed in `top_logprobs` after the chosen token
+const ALTERNATIVE_TOKENS: [&str; 8] = ["the", "a", "of", "and", "to", "in", "is", "it"];
+
+/// Random log probabilities for every mock token of `text`, each listing `top`
+/// alternatives (the chosen token first)
+pub fn mock_logprobs<R: Rng>(text: &str, top: usize, rng: &mut R) -> ChoiceLogprobs {
+    let content = split_mock_tokens(text)
+        .into_iter()
+        .map(|token| {
+            let logprob = -rng.gen_range(0.0..1.5);
+            let leading_space = if token.starts_with(char::is_whitespace) {
+                " "
+            } else {
+                ""
+            };
+            let top_logprobs = (0..top)
+                .map(|k| {
+                    let (token, logprob) = if k == 0 {
+                        (token.to_string(), logprob)
+                    } else {
+                        let alternative = ALTERNATIVE_TOKENS[(k - 1) % ALTERNATIVE_TOKENS.len()];
+                        (
+                            format!("{}{}", leading_space, alternative),
+                            logprob - k as f64 - rng.gen::<f64>(),
+                        )
+                    };
+                    TopLogprob {
+                        bytes: token.as_bytes().to_vec(),
+                        token,
+                        logprob,
+                    }
+                })
+                .collect();
+            TokenLogprob {
+                token: token.to_string(),
+                logprob,
+                bytes: token.as_bytes().to_vec(),
+                top_logprobs,
+            }
+        })
+        .collect();
+    ChoiceLogprobs { content }
+}
+
+/// Build a chat SSE event carrying `content` plus the `logprobs` of its tokens,
+/// stamped with `_mock_ts_ms` when `ts_ms` is given
+pub fn sse_event_with_logprobs(
+    content: &str,
+    logprobs: &ChoiceLogprobs,
+    ts_ms: Option<u64>,
+) -> String {
+    let mut data = serde_json::json!({
+        "choices": [
+            {
+                "delta": { "content": content },
+                "logprobs": logprobs
+            }
+        ]
+    });
+    if let Some(ts_ms) = ts_ms {
+        data["_mock_ts_ms"] = ts_ms.into();
+    }
+    format!("data: {}\n\n", data)
+}
+
+/// Build an SSE event like [`sse_event_from_content`] that also carries the
+/// server-side emission time as a top-level `_mock_ts_ms` field
+pub fn sse_event_with_timestamp(content: &str, ts_ms: u64) -> String {
+    let data = serde_json::json!({
+        "choices": [
+            {
+                "delta": { "content": content }
+            }
+        ],
+        "_mock_ts_ms": ts_ms
+    });
+    format!("data: {}\n\n", data)
+}
+
+/// Build a legacy completions SSE event carrying a chunk of `text`, stamped
+/// with `_mock_ts_ms` when `ts_ms` is given
+pub fn sse_text_event(text: &str, ts_ms: Option<u64>) -> String {
+    let mut data = serde_json::json!({
+        "object": "text_completion",
+        "choices": [
+            {
+                "text": text,
+                "index": 0,
+                "finish_reason": null
+            }
+        ]
+    });
+    if let Some(ts_ms) = ts_ms {
+        data["_mock_ts_ms"] = ts_ms.into();
+    }
+    format!("data: {}\n\n", data)
+}
+
+/// Build the usage SSE event sent before `[DONE]` when the request set
+/// `stream_options.include_usage`
+pub fn sse_usage_event(usage: &Usage) -> String {
+    let data = serde_json::json!({
+        "choices": [],
+        "usage": usage
+    });
+    format!("data: {}\n\n", data)
+}
+
+/// Build a chat SSE event carrying a chunk of reasoning in
+/// `delta.reasoning_content`, stamped with `_mock_ts_ms` when `ts_ms` is given
+pub fn sse_reasoning_event(reasoning: &str, ts_ms: Option<u64>) -> String {
+    let mut data = serde_json::json!({
+        "choices": [
+            {
+                "delta": { "reasoning_content": reasoning }
+            }
+        ]
+    });
+    if let Some(ts_ms) = ts_ms {
+        data["_mock_ts_ms"] = ts_ms.into();
+    }
+    format!("data: {}\n\n", data)
 }
 
-/// Generate a mock embedding vector
-pub fn generate_embedding(dimension: usize) -> EmbeddingResponse {
+/// Build a chat SSE event carrying one `delta.tool_calls` fragment for the
+/// call at `index`: the first fragment of a call (`head`) names its id and
+/// function, later ones only append to its `arguments`
+pub fn sse_tool_call_event(index: usize, head: Option<&ToolCall>, arguments: &str) -> String {
+    let call = match head {
+        Some(call) => serde_json::json!({
+            "index": index,
+            "id": call.id,
+            "type": call.kind,
+            "function": { "name": call.function.name, "arguments": arguments }
+        }),
+        None => serde_json::json!({
+            "index": index,
+            "function": { "arguments": arguments }
+        }),
+    };
+    let data = serde_json::json!({
+        "choices": [
+            {
+                "delta": { "tool_calls": [call] }
+            }
+        ]
+    });
+    format!("data: {}\n\n", data)
+}
+
+/// SSE frames streaming `calls` as `delta.tool_calls` fragments: per call, a
+/// head frame with an empty `arguments`, then the arguments JSON in pieces of
+/// about one token each
+pub fn sse_tool_call_frames(calls: &[ToolCall]) -> Vec<String> {
+    let mut frames = Vec::new();
+    for (index, call) in calls.iter().enumerate() {
+        frames.push(sse_tool_call_event(index, Some(call), ""));
+        let arguments = call.function.arguments.as_str();
+        let mut start = 0;
+        while start < arguments.len() {
+            let end = byte_idx_after_chars(arguments, start, tokens_to_chars(1));
+            frames.push(sse_tool_call_event(index, None, &arguments[start..end]));
+            start = end;
+        }
+    }
+    frames
+}
+
+/// Build the closing SSE event of a stream that ended with `reason`: an empty
+/// delta carrying `finish_reason`
+pub fn sse_finish_event(reason: &str) -> String {
+    let data = serde_json::json!({
+        "choices": [
+            {
+                "delta": {},
+                "logprobs": null,
+                "finish_reason": reason
+            }
+        ]
+    });
+    format!("data: {}\n\n", data)
+}
+
+/// Legacy completions counterpart of [`sse_finish_event`]: an empty `text`
+/// carrying `finish_reason`
+pub fn sse_text_finish_event(reason: &str) -> String {
+    let data = serde_json::json!({
+        "object": "text_completion",
+        "choices": [
+            {
+                "text": "",
+                "index": 0,
+                "finish_reason": reason
+            }
+        ]
+    });
+    format!("data: {}\n\n", data)
+}
+
+/// Read a canned response body, rejecting files that are not valid JSON
+pub fn load_canned_response(path: &std::path::Path) -> Result<bytes::Bytes, String> {
+    let body = std::fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    serde_json::from_slice::<serde::de::IgnoredAny>(&body)
+        .map_err(|e| format!("{}: invalid JSON: {}", path.display(), e))?;
+    Ok(bytes::Bytes::from(body))
+}
+
+/// Current wall-clock time in milliseconds since the Unix epoch
+pub fn unix_millis() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Generate `count` independent mock embedding vectors, optionally quantized
+pub fn generate_embedding(
+    dimension: usize,
+    count: usize,
+    quantize: Option<EmbeddingQuantization>,
+) -> EmbeddingResponse {
     let mut rng = rand::thread_rng();
-    let embedding: Vec<f32> = (0..dimension).map(|_| rng.gen()).collect();
-    let data = vec![EmbeddingResponseItem {
-        embedding,
-        index: 0,
-    }];
+    let data = (0..count)
+        .map(|index| {
+            // Centred on zero like real embedding components
+            let embedding: Vec<f32> = (0..dimension).map(|_| rng.gen_range(-1.0..1.0)).collect();
+            let (object, embedding) = match quantize {
+                None => ("embedding", Embedding::Float(embedding)),
+                Some(EmbeddingQuantization::Int8) => ("embedding.int8", quantize_int8(&embedding)),
+            };
+            EmbeddingResponseItem {
+                object: object.into(),
+                embedding,
+                index,
+            }
+        })
+        .collect();
     EmbeddingResponse {
         object: "list".into(),
         data,
+        first_id: None,
+        last_id: None,
+        has_more: None,
     }
 }
 
+/// Scale `v` so its largest magnitude maps to 127, giving integers spread
+/// over [-127, 127]
+fn quantize_int8(v: &[f32]) -> Embedding {
+    let max_abs = v.iter().fold(0.0f32, |max, x| max.max(x.abs()));
+    let scale = if max_abs > 0.0 { 127.0 / max_abs } else { 0.0 };
+    Embedding::Int8(
+        v.iter()
+            .map(|x| (x * scale).round().clamp(-127.0, 127.0) as i8)
+            .collect(),
+    )
+}
+
 /// Pre-generate token samples for streaming (circular buffer of random values)
 /// This allows SSE handlers to pull from pre-computed samples without per-request RNG calls
-pub fn generate_stream_token_samples(count: usize, mean: f64, stddev: f64) -> Vec<usize> {
+pub fn generate_stream_token_samples(
+    count: usize,
+    mean: f64,
+    stddev: f64,
+    min: usize,
+    max: Option<usize>,
+) -> Vec<usize> {
     let mut rng = rand::rngs::StdRng::from_entropy();
     let mut samples = Vec::with_capacity(count);
 
     for _ in 0..count {
-        let sampled = sample_normal_f64(&mut rng, mean, stddev).round() as isize;
-        samples.push(sampled.max(0) as usize);
+        let sampled = sample_normal_f64(&mut rng, mean, stddev);
+        samples.push(clamp_token_count(sampled, min, max));
     }
 
     samples
@@ -143,18 +978,395 @@ mod tests {
         assert!((mean - 100.0).abs() < 1.0, "mean was {}", mean);
     }
 
+    #[test]
+    fn test_sample_stream_delay_ms() {
+        let mut rng = seeded_rng();
+        let n = 10_000usize;
+        let mean_of = |dist, rng: &mut rand::rngs::StdRng| {
+            (0..n)
+                .map(|_| sample_stream_delay_ms(rng, dist, 20, 5.0))
+                .sum::<f64>()
+                / n as f64
+        };
+        assert_eq!(
+            sample_stream_delay_ms(&mut rng, StreamDelayDist::Constant, 20, 5.0),
+            20.0
+        );
+        let normal = mean_of(StreamDelayDist::Normal, &mut rng);
+        assert!((normal - 20.0).abs() < 0.5, "normal mean was {}", normal);
+        let exponential = mean_of(StreamDelayDist::Exponential, &mut rng);
+        assert!(
+            (exponential - 20.0).abs() < 1.0,
+            "exponential mean was {}",
+            exponential
+        );
+    }
+
+    #[test]
+    fn test_cjk_articles_slice_on_char_boundaries() {
+        let mut rng = seeded_rng();
+        let config = ArticleGenConfig {
+            charset: ContentCharset::Cjk,
+            ..ArticleGenConfig::new(50, 64.0, 32.0)
+        };
+        let articles = pregenerate_articles_with_progress(&config, &mut rng, |_| {});
+        assert_eq!(articles.len(), 50);
+        for article in &articles {
+            assert!(article.chars().any(|c| c.len_utf8() > 1));
+            assert!(!article.chars().any(|c| c.is_ascii_alphabetic()));
+            let chars = article.chars().count();
+            for tokens in 0..=chars_to_tokens(chars) + 1 {
+                let slice = slice_text_by_tokens(article, tokens, false);
+                assert!(article.starts_with(slice));
+            }
+            for pos in 0..=chars + 1 {
+                assert!(article.is_char_boundary(char_pos_to_byte_idx(article, pos)));
+            }
+        }
+
+        for charset in [ContentCharset::Emoji, ContentCharset::Mixed] {
+            let text = charset_words(charset, 200, &mut rng);
+            assert_eq!(text.split(' ').count(), 200);
+            assert!(text.chars().any(|c| c.len_utf8() == 4));
+        }
+    }
+
+    #[test]
+    fn test_synthetic_word_lengths() {
+        let mut rng = seeded_rng();
+        let config = ArticleGenConfig {
+            charset: ContentCharset::Synthetic,
+            word_length_mean: 12.0,
+            word_length_stddev: 3.0,
+            ..ArticleGenConfig::new(20, 256.0, 32.0)
+        };
+        let articles = pregenerate_articles_with_progress(&config, &mut rng, |_| {});
+        let lengths: Vec<usize> = articles
+            .iter()
+            .flat_map(|a| a.split(' ').map(str::len))
+            .collect();
+        assert!(lengths.iter().all(|&len| len >= 1));
+        let mean = lengths.iter().sum::<usize>() as f64 / lengths.len() as f64;
+        assert!((mean - 12.0).abs() < 0.5, "mean word length {}", mean);
+        assert!(articles
+            .iter()
+            .all(|a| a.chars().all(|c| c == ' ' || c.is_ascii_lowercase())));
+    }
+
+    #[test]
+    fn test_url_citations_within_content() {
+        let mut rng = seeded_rng();
+        let content = "héllo wörld 你好 世界 and more words here";
+        let citations = url_citations(content, 3, &mut rng);
+        assert_eq!(citations.len(), 3);
+        let chars: Vec<char> = content.chars().collect();
+        let mut last_end = 0;
+        for Annotation::UrlCitation { url_citation: c } in &citations {
+            assert!(c.start_index >= last_end && c.start_index < c.end_index);
+            assert!(c.end_index <= chars.len());
+            let cited: String = chars[c.start_index..c.end_index].iter().collect();
+            assert!(content.split(' ').any(|w| w == cited), "{:?}", cited);
+            last_end = c.end_index;
+        }
+        assert_eq!(url_citations("two words", 5, &mut rng).len(), 2);
+        assert!(url_citations("", 2, &mut rng).is_empty());
+    }
+
+    #[test]
+    fn test_mock_logprobs_cover_text() {
+        assert_eq!(
+            split_mock_tokens("Lorem ipsum  dolor "),
+            ["Lorem", " ipsum", "  dolor", " "]
+        );
+        assert!(split_mock_tokens("").is_empty());
+
+        let mut rng = seeded_rng();
+        let logprobs = mock_logprobs(" héllo world", 3, &mut rng);
+        let tokens: Vec<&str> = logprobs.content.iter().map(|t| t.token.as_str()).collect();
+        assert_eq!(tokens, [" héllo", " world"]);
+        for token in &logprobs.content {
+            assert!(token.logprob <= 0.0);
+            assert_eq!(token.bytes, token.token.as_bytes());
+            assert_eq!(token.top_logprobs.len(), 3);
+            assert_eq!(token.top_logprobs[0].token, token.token);
+            assert!(token.top_logprobs[1].logprob < token.logprob);
+        }
+    }
+
+    #[test]
+    fn test_pregenerate_articles_count() {
+        let articles = pregenerate_articles(25, 50.0, 10.0, &mut seeded_rng());
+        assert_eq!(articles.len(), 25);
+        assert!(articles.iter().all(|a| !a.is_empty()));
+    }
+
+    #[test]
+    fn test_pregenerate_articles_min_words() {
+        // A tiny mean with a large stddev would otherwise yield one-word articles
+        let config = ArticleGenConfig {
+            min_words: 12,
+            ..ArticleGenConfig::new(200, 2.0, 20.0)
+        };
+        let articles = pregenerate_articles_with_progress(&config, &mut seeded_rng(), |_| {});
+        assert_eq!(articles.len(), 200);
+        for article in &articles {
+            assert!(article.split_whitespace().count() >= 12, "{:?}", article);
+        }
+    }
+
+    #[test]
+    fn test_pregenerate_articles_max_corpus_bytes() {
+        let config = ArticleGenConfig {
+            max_corpus_bytes: Some(10_000),
+            ..ArticleGenConfig::new(1000, 100.0, 20.0)
+        };
+        let articles = pregenerate_articles_with_progress(&config, &mut seeded_rng(), |_| {});
+        let bytes: usize = articles.iter().map(|a| a.len()).sum();
+        assert!(bytes <= 10_000, "{} bytes", bytes);
+        assert!(!articles.is_empty() && articles.len() < 1000);
+        // The cap cuts the pool short by no more than one article's worth
+        assert!(bytes > 10_000 - 1000, "{} bytes", bytes);
+    }
+
+    #[test]
+    fn test_pregenerate_articles_mean_tokens() {
+        let n = 500usize;
+        let articles = pregenerate_articles(n, 200.0, 20.0, &mut seeded_rng());
+        let total: usize = articles
+            .iter()
+            .map(|a| chars_to_tokens(a.chars().count()))
+            .sum();
+        let mean = total as f64 / n as f64;
+        // Word-based generation only approximates the char budget
+        assert!((mean - 200.0).abs() < 200.0 * 0.15, "mean was {}", mean);
+    }
+
+    #[test]
+    fn test_choose_article_fitting() {
+        let articles: Vec<_> = ["short one", "a much longer article than the rest", "tiny"]
+            .iter()
+            .map(|a| Arc::new(a.to_string()))
+            .collect();
+        let fallback = Arc::new("fallback".to_string());
+        let mut rng = seeded_rng();
+        for _ in 0..10 {
+            let article = choose_article_fitting(&articles, &fallback, 5, &mut rng);
+            assert_eq!(article.as_str(), "a much longer article than the rest");
+        }
+
+        // Nothing is long enough: articles are joined until the content fits
+        let joined = choose_article_fitting(&articles, &fallback, 20, &mut rng);
+        assert!(joined.chars().count() >= tokens_to_chars(20));
+        assert!(choose_article_fitting(&[], &fallback, 20, &mut rng) == fallback);
+    }
+
     #[test]
     fn test_slicing_text_by_tokens() {
         let s = "hello world this is a test of the slicing function. it should cut off at a token boundary.";
-        let cut = slice_text_by_tokens(s, 3);
-        assert!(cut.len() > 0 && cut.len() < s.len());
+        let cut = slice_text_by_tokens(s, 3, false);
+        assert!(!cut.is_empty() && cut.len() < s.len());
+    }
+
+    #[test]
+    fn test_slicing_preserves_whitespace() {
+        let s = "  padded\n\nparagraph with   gaps and more words after it";
+        let trimmed = slice_text_by_tokens(s, 6, false);
+        let preserved = slice_text_by_tokens(s, 6, true);
+        assert_eq!(trimmed, "padded\n\nparagraph");
+        assert_eq!(preserved, "  padded\n\nparagraph ");
+    }
+
+    #[test]
+    #[cfg(feature = "jemalloc")]
+    fn test_allocator_name_jemalloc() {
+        assert_eq!(allocator_name(), "jemalloc");
+    }
+
+    // Exercised by `cargo test --no-default-features`, which also proves the
+    // crate builds without jemalloc.
+    #[test]
+    #[cfg(not(feature = "jemalloc"))]
+    fn test_allocator_name_system() {
+        assert_eq!(allocator_name(), "system");
+    }
+
+    #[test]
+    fn test_model_matches_pattern() {
+        assert!(model_matches_pattern("gpt-4-mock", "gpt-4*"));
+        assert!(model_matches_pattern("gpt-4-mock", "*mock"));
+        assert!(model_matches_pattern("gpt-4-mock", "gpt-?-mock"));
+        assert!(!model_matches_pattern("gpt-4-mock", "gpt-3*"));
+        assert!(model_matches_pattern("gpt-4-mock", "4-mo"));
+        assert!(!model_matches_pattern("gpt-4-mock", "davinci"));
+    }
+
+    #[test]
+    fn test_reflect_prompt_prefix() {
+        let messages = vec![
+            Message {
+                role: "user".into(),
+                content: "first".into(),
+            },
+            Message {
+                role: "assistant".into(),
+                content: "reply".into(),
+            },
+            Message {
+                role: "user".into(),
+                content: "second question".into(),
+            },
+        ];
+        assert_eq!(
+            reflect_prompt_prefix(&messages).unwrap(),
+            "You said: 'second question'. "
+        );
+        assert!(reflect_prompt_prefix(&messages[1..2]).is_none());
+
+        let long = vec![Message {
+            role: "user".into(),
+            content: "x".repeat(200).into(),
+        }];
+        let prefix = reflect_prompt_prefix(&long).unwrap();
+        assert!(prefix.contains("..."));
+        assert!(prefix.len() < 200);
+    }
+
+    #[test]
+    fn test_first_sentence_chars() {
+        assert_eq!(first_sentence_chars("Hi there. More"), 9);
+        assert_eq!(first_sentence_chars("Wait?! Yes"), 5);
+        assert_eq!(first_sentence_chars("¿Qué tal? Bien"), 9);
+        assert_eq!(first_sentence_chars("no punctuation"), 14);
+        assert_eq!(first_sentence_chars(""), 0);
+    }
+
+    #[test]
+    fn test_write_sse_event_matches_serde() {
+        let control: String = (0u8..0x20).map(char::from).collect();
+        let mut buf = BytesMut::new();
+        for content in [
+            "",
+            "plain words",
+            "quote \" backslash \\ slash / tab\t",
+            "line\nbreak\r\n",
+            &control,
+            "del \u{7f} nbsp \u{a0} sep \u{2028} 日本語 🦀",
+        ] {
+            write_sse_event_from_content(&mut buf, content);
+            let frame = buf.split();
+            assert_eq!(
+                &frame[..],
+                sse_event_from_content(content).as_bytes(),
+                "{:?}",
+                content
+            );
+        }
+    }
+
+    #[test]
+    fn test_byte_idx_after_chars() {
+        let s = "aé日🦀b";
+        assert_eq!(byte_idx_after_chars(s, 0, 2), 3);
+        assert_eq!(byte_idx_after_chars(s, 1, 2), 6);
+        assert_eq!(byte_idx_after_chars(s, 3, 0), 3);
+        // Clamped to the end of the string
+        assert_eq!(byte_idx_after_chars(s, 6, 10), s.len());
+    }
+
+    #[test]
+    fn test_dedupe_repeated_words() {
+        assert_eq!(
+            dedupe_repeated_words("The cat and the dog. And a cat!"),
+            "The cat and dog. a"
+        );
+        assert_eq!(dedupe_repeated_words("one  two\none"), "one  two");
+        assert_eq!(dedupe_repeated_words(""), "");
+    }
+
+    #[test]
+    fn test_inject_control_chars() {
+        let text = "one two three four five six seven eight nine";
+        let injected = inject_control_chars(text);
+        assert_eq!(
+            injected,
+            "one\u{0} two three four five\u{200B} six seven eight nine\u{1F980}"
+        );
+        let stripped: String = injected
+            .chars()
+            .filter(|c| !INJECTED_CHARS.contains(c))
+            .collect();
+        assert_eq!(stripped, text);
+    }
+
+    #[test]
+    fn test_truncate_cow_bytes() {
+        let mut text = Cow::Borrowed("héllo");
+        assert!(truncate_cow_bytes(&mut text, 4));
+        // Cutting 4 of 6 bytes would split the 2-byte 'é'
+        assert_eq!(text, "h");
+        let mut owned: Cow<str> = Cow::Owned("abc".to_string());
+        assert!(truncate_cow_bytes(&mut owned, 10));
+        assert_eq!(owned, "");
+        assert!(!truncate_cow_bytes(&mut owned, 1));
+    }
+
+    #[test]
+    fn test_prompt_filter_results() {
+        let results = prompt_filter_results("How do I KILL a stuck process?");
+        assert_eq!(results.len(), 1);
+        let categories = &results[0].content_filter_results;
+        assert_eq!(categories.violence.severity, "low");
+        assert_eq!(categories.hate.severity, "safe");
+        assert!(!categories.violence.filtered);
+        assert_eq!(
+            prompt_filter_results("hello"),
+            prompt_filter_results("hello")
+        );
+    }
+
+    #[test]
+    fn test_echo_prompt() {
+        let messages = vec![
+            Message {
+                role: "system".into(),
+                content: "be brief".into(),
+            },
+            Message {
+                role: "user".into(),
+                content: "first".into(),
+            },
+            Message {
+                role: "user".into(),
+                content: "second".into(),
+            },
+        ];
+        assert_eq!(echo_prompt(&messages).unwrap(), "first\nsecond");
+        assert!(echo_prompt(&messages[..1]).is_none());
     }
 
     #[test]
     fn test_generate_stream_token_samples() {
-        let samples = generate_stream_token_samples(100, 50.0, 10.0);
+        let samples = generate_stream_token_samples(100, 50.0, 10.0, 0, None);
         assert_eq!(samples.len(), 100);
         // All samples should be valid (usize is always non-negative)
         assert!(!samples.is_empty());
+
+        let samples = generate_stream_token_samples(1000, 50.0, 40.0, 20, Some(60));
+        assert!(samples.iter().all(|s| (20..=60).contains(s)));
+    }
+
+    #[test]
+    fn test_clamp_token_count() {
+        assert_eq!(clamp_token_count(-5.0, 1, None), 1);
+        assert_eq!(clamp_token_count(7.4, 1, None), 7);
+        assert_eq!(clamp_token_count(7.4, 10, Some(20)), 10);
+        assert_eq!(clamp_token_count(1e9, 1, Some(20)), 20);
+        assert_eq!(clamp_token_count(f64::NAN, 3, None), 3);
+        assert_eq!(clamp_token_count(f64::INFINITY, 1, Some(20)), 20);
+        assert_eq!(clamp_token_count(f64::NEG_INFINITY, 1, None), 1);
+        assert_eq!(
+            tokens_to_chars(clamp_token_count(f64::INFINITY, 1, None)),
+            usize::MAX
+        );
     }
 }
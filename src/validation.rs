@@ -0,0 +1,376 @@
+//! Request validation that collects every problem instead of stopping at the first
+
+use crate::types::{ChatCompletionRequest, CompletionsRequest, EmbeddingRequest};
+use serde::Serialize;
+
+/// Largest `n` accepted, mirroring the OpenAI API limit
+pub const MAX_CHOICES: usize = 128;
+
+/// Largest `top_logprobs` accepted, mirroring the OpenAI API limit
+pub const MAX_TOP_LOGPROBS: usize = 20;
+
+/// Largest completions `best_of` accepted, mirroring the OpenAI API limit
+pub const MAX_BEST_OF: usize = 20;
+
+/// Largest embedding `dimensions` accepted, as for text-embedding-3-large
+pub const MAX_EMBEDDING_DIMENSIONS: i64 = 3072;
+
+/// A single problem found while validating a request
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct ValidationIssue {
+    pub param: &'static str,
+    pub message: String,
+}
+
+impl ValidationIssue {
+    fn new(param: &'static str, message: impl Into<String>) -> Self {
+        ValidationIssue {
+            param,
+            message: message.into(),
+        }
+    }
+}
+
+/// Checks shared by chat and legacy completions requests
+fn validate_common(
+    model: &str,
+    max_tokens: Option<usize>,
+    n: Option<usize>,
+    issues: &mut Vec<ValidationIssue>,
+) {
+    if model.trim().is_empty() {
+        issues.push(ValidationIssue::new(
+            "model",
+            "you must provide a model parameter",
+        ));
+    }
+    if max_tokens == Some(0) {
+        issues.push(ValidationIssue::new(
+            "max_tokens",
+            "max_tokens must be at least 1",
+        ));
+    }
+    if let Some(n) = n {
+        if n == 0 || n > MAX_CHOICES {
+            issues.push(ValidationIssue::new(
+                "n",
+                format!("n must be between 1 and {}, got {}", MAX_CHOICES, n),
+            ));
+        }
+    }
+}
+
+/// `frequency_penalty` and `presence_penalty` share the OpenAI range
+fn validate_penalties(
+    frequency_penalty: Option<f64>,
+    presence_penalty: Option<f64>,
+    issues: &mut Vec<ValidationIssue>,
+) {
+    let penalties = [
+        ("frequency_penalty", frequency_penalty),
+        ("presence_penalty", presence_penalty),
+    ];
+    for (param, value) in penalties {
+        if let Some(value) = value {
+            if !(-2.0..=2.0).contains(&value) {
+                issues.push(ValidationIssue::new(
+                    param,
+                    format!("{} must be between -2 and 2, got {}", param, value),
+                ));
+            }
+        }
+    }
+}
+
+/// Validate a chat completions request, returning every problem found
+pub fn validate_chat_request(req: &ChatCompletionRequest) -> Vec<ValidationIssue> {
+    let mut issues = Vec::new();
+    validate_common(&req.model, req.max_tokens, req.n, &mut issues);
+    validate_penalties(req.frequency_penalty, req.presence_penalty, &mut issues);
+
+    if let Some(messages) = &req.messages {
+        if messages.is_empty() {
+            issues.push(ValidationIssue::new(
+                "messages",
+                "messages must contain at least one message",
+            ));
+        }
+    }
+    if let Some(temperature) = req.temperature {
+        if !(0.0..=2.0).contains(&temperature) {
+            issues.push(ValidationIssue::new(
+                "temperature",
+                format!("temperature must be between 0 and 2, got {}", temperature),
+            ));
+        }
+    }
+    if let Some(verbosity) = req.verbosity.as_deref() {
+        if !matches!(verbosity, "low" | "medium" | "high") {
+            issues.push(ValidationIssue::new(
+                "verbosity",
+                format!(
+                    "verbosity must be one of low, medium, high; got {:?}",
+                    verbosity
+                ),
+            ));
+        }
+    }
+    if let Some(tier) = req.service_tier.as_deref() {
+        if !matches!(tier, "auto" | "default" | "flex") {
+            issues.push(ValidationIssue::new(
+                "service_tier",
+                format!(
+                    "service_tier must be one of auto, default, flex; got {:?}",
+                    tier
+                ),
+            ));
+        }
+    }
+    if let Some(top_logprobs) = req.top_logprobs {
+        if top_logprobs > MAX_TOP_LOGPROBS {
+            issues.push(ValidationIssue::new(
+                "top_logprobs",
+                format!(
+                    "top_logprobs must be between 0 and {}, got {}",
+                    MAX_TOP_LOGPROBS, top_logprobs
+                ),
+            ));
+        } else if req.logprobs != Some(true) {
+            issues.push(ValidationIssue::new(
+                "top_logprobs",
+                "logprobs must be set to true if top_logprobs is specified",
+            ));
+        }
+    }
+    // Multi-choice streaming is not implemented; refuse rather than silently
+    // collapsing to a single choice
+    if req.stream.unwrap_or(false) && req.n.unwrap_or(1) > 1 {
+        issues.push(ValidationIssue::new(
+            "n",
+            "Streaming with n > 1 is not supported by this server; set n to 1 or disable stream.",
+        ));
+    }
+    issues
+}
+
+/// Validate a legacy completions request, returning every problem found
+pub fn validate_completions_request(req: &CompletionsRequest) -> Vec<ValidationIssue> {
+    let mut issues = Vec::new();
+    validate_common(&req.model, req.max_tokens, req.n, &mut issues);
+    validate_penalties(req.frequency_penalty, req.presence_penalty, &mut issues);
+    if let Some(best_of) = req.best_of {
+        let n = req.n.unwrap_or(1);
+        if best_of == 0 || best_of > MAX_BEST_OF {
+            issues.push(ValidationIssue::new(
+                "best_of",
+                format!(
+                    "best_of must be between 1 and {}, got {}",
+                    MAX_BEST_OF, best_of
+                ),
+            ));
+        } else if best_of < n {
+            issues.push(ValidationIssue::new(
+                "best_of",
+                format!(
+                    "best_of must be greater than or equal to n ({}), got {}",
+                    n, best_of
+                ),
+            ));
+        }
+        if req.stream == Some(true) && best_of > 1 {
+            issues.push(ValidationIssue::new(
+                "best_of",
+                "best_of cannot be used with stream",
+            ));
+        }
+    }
+    issues
+}
+
+/// Validate an embeddings request; `n` fans each input out into that many
+/// vectors and may not exceed `max_n` (`--max-embedding-n`)
+pub fn validate_embedding_request(req: &EmbeddingRequest, max_n: usize) -> Vec<ValidationIssue> {
+    let mut issues = Vec::new();
+    if let Some(n) = req.n {
+        if n == 0 || n > max_n {
+            issues.push(ValidationIssue::new(
+                "n",
+                format!("n must be between 1 and {}, got {}", max_n, n),
+            ));
+        }
+    }
+    if let Some(dimensions) = req.dimensions {
+        if !(1..=MAX_EMBEDDING_DIMENSIONS).contains(&dimensions) {
+            issues.push(ValidationIssue::new(
+                "dimensions",
+                format!(
+                    "dimensions must be between 1 and {}, got {}",
+                    MAX_EMBEDDING_DIMENSIONS, dimensions
+                ),
+            ));
+        }
+    }
+    issues
+}
+
+/// `--max-messages` check; runs before prompt tokens are counted so oversized
+/// message lists are rejected without walking them
+pub fn max_messages_issues(
+    req: &ChatCompletionRequest,
+    max_messages: usize,
+) -> Vec<ValidationIssue> {
+    let mut issues = Vec::new();
+    let count = req.messages.as_ref().map_or(0, Vec::len);
+    if count > max_messages {
+        issues.push(ValidationIssue::new(
+            "messages",
+            format!(
+                "messages may contain at most {} messages, got {}",
+                max_messages, count
+            ),
+        ));
+    }
+    issues
+}
+
+/// `--strict-schema` checks for a chat request carrying a legacy `prompt`
+pub fn strict_chat_request_issues(req: &ChatCompletionRequest) -> Vec<ValidationIssue> {
+    let mut issues = Vec::new();
+    if req.prompt.is_some() {
+        issues.push(ValidationIssue::new(
+            "prompt",
+            "prompt is not supported by /v1/chat/completions; send messages instead, or use /v1/completions for prompt-style requests",
+        ));
+    }
+    issues
+}
+
+/// `--strict-schema` checks for a legacy completions request carrying chat
+/// `messages`
+pub fn strict_completions_request_issues(req: &CompletionsRequest) -> Vec<ValidationIssue> {
+    let mut issues = Vec::new();
+    if req.messages.is_some() {
+        issues.push(ValidationIssue::new(
+            "messages",
+            "messages is not supported by /v1/completions; send prompt instead, or use /v1/chat/completions for chat-style requests",
+        ));
+    }
+    issues
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collects_all_chat_issues() {
+        let req: ChatCompletionRequest = serde_json::from_value(serde_json::json!({
+            "model": "",
+            "max_tokens": 0,
+            "n": 0,
+            "temperature": 3.5,
+            "messages": []
+        }))
+        .unwrap();
+        let params: Vec<_> = validate_chat_request(&req)
+            .iter()
+            .map(|i| i.param)
+            .collect();
+        assert_eq!(
+            params,
+            ["model", "max_tokens", "n", "messages", "temperature"]
+        );
+    }
+
+    #[test]
+    fn test_strict_cross_field_issues() {
+        let chat: ChatCompletionRequest = serde_json::from_value(serde_json::json!({
+            "model": "gpt-4-mock",
+            "prompt": "hi"
+        }))
+        .unwrap();
+        assert_eq!(strict_chat_request_issues(&chat)[0].param, "prompt");
+
+        let completions: CompletionsRequest = serde_json::from_value(serde_json::json!({
+            "model": "gpt-4-mock",
+            "messages": [{"role": "user", "content": "hi"}]
+        }))
+        .unwrap();
+        assert_eq!(
+            strict_completions_request_issues(&completions)[0].param,
+            "messages"
+        );
+    }
+
+    #[test]
+    fn test_max_messages_issues() {
+        let req: ChatCompletionRequest = serde_json::from_value(serde_json::json!({
+            "model": "gpt-4-mock",
+            "messages": [
+                {"role": "user", "content": "a"},
+                {"role": "user", "content": "b"}
+            ]
+        }))
+        .unwrap();
+        assert!(max_messages_issues(&req, 2).is_empty());
+        assert_eq!(max_messages_issues(&req, 1)[0].param, "messages");
+    }
+
+    #[test]
+    fn test_embedding_dimensions_range() {
+        let issues = |dimensions: serde_json::Value| {
+            let req: EmbeddingRequest = serde_json::from_value(serde_json::json!({
+                "input": "hi",
+                "dimensions": dimensions
+            }))
+            .unwrap();
+            validate_embedding_request(&req, 1)
+        };
+        assert!(issues(serde_json::json!(1)).is_empty());
+        assert!(issues(serde_json::json!(MAX_EMBEDDING_DIMENSIONS)).is_empty());
+        for bad in [
+            serde_json::json!(0),
+            serde_json::json!(-8),
+            serde_json::json!(MAX_EMBEDDING_DIMENSIONS + 1),
+            serde_json::json!(5_000_000_000i64),
+        ] {
+            assert_eq!(issues(bad.clone())[0].param, "dimensions", "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_completions_best_of() {
+        let params = |body: serde_json::Value| {
+            let req: CompletionsRequest = serde_json::from_value(body).unwrap();
+            validate_completions_request(&req)
+                .iter()
+                .map(|i| i.param)
+                .collect::<Vec<_>>()
+        };
+        assert!(params(serde_json::json!({"model": "m", "n": 2, "best_of": 3})).is_empty());
+        assert_eq!(
+            params(serde_json::json!({"model": "m", "n": 3, "best_of": 2})),
+            ["best_of"]
+        );
+        assert_eq!(
+            params(serde_json::json!({"model": "m", "best_of": 0})),
+            ["best_of"]
+        );
+        assert_eq!(
+            params(serde_json::json!({"model": "m", "best_of": 2, "stream": true})),
+            ["best_of"]
+        );
+    }
+
+    #[test]
+    fn test_valid_completions_request() {
+        let req: CompletionsRequest = serde_json::from_value(serde_json::json!({
+            "model": "gpt-4-mock",
+            "prompt": "hi",
+            "max_tokens": 5,
+            "n": 1
+        }))
+        .unwrap();
+        assert!(validate_completions_request(&req).is_empty());
+    }
+}